@@ -8,8 +8,8 @@ extern crate cubeb;
 
 mod common;
 
-use cubeb::{MonoFrame, Sample};
-use std::f32::consts::PI;
+use cubeb::signal::{Signal, Sine};
+use cubeb::MonoFrame;
 use std::thread;
 use std::time::Duration;
 
@@ -28,26 +28,17 @@ fn main() {
         .layout(cubeb::ChannelLayout::MONO)
         .take();
 
-    let mut position = 0u32;
+    // North American dial tone
+    let tone = Sine::new(350.0, SAMPLE_FREQUENCY)
+        .mix(Sine::new(440.0, SAMPLE_FREQUENCY))
+        .gain(0.5);
 
     let mut builder = cubeb::StreamBuilder::<Frame>::new();
     builder
         .name("Cubeb tone (mono)")
         .default_output(&params)
         .latency(0x1000)
-        .data_callback(move |_, output| {
-            // generate our test tone on the fly
-            for f in output.iter_mut() {
-                // North American dial tone
-                let t1 = (2.0 * PI * 350.0 * position as f32 / SAMPLE_FREQUENCY as f32).sin();
-                let t2 = (2.0 * PI * 440.0 * position as f32 / SAMPLE_FREQUENCY as f32).sin();
-
-                f.m = i16::from_float(0.5 * (t1 + t2));
-
-                position += 1;
-            }
-            output.len() as isize
-        })
+        .data_callback(tone.into_callback())
         .state_callback(|state| {
             println!("stream {:?}", state);
         });
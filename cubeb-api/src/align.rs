@@ -0,0 +1,116 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Time alignment of duplex input and output
+
+use crate::Frame;
+
+/// History of the output frames of a duplex stream, used to find the output
+/// that was playing when each input frame was captured.
+pub(crate) struct DelayLine<F> {
+    history: Vec<F>,
+    // Index in `history` of the next frame to write.
+    write: usize,
+    // Round-trip latency, in frames.
+    delay: usize,
+    reference: Vec<F>,
+    // Frames of `reference` prepared for the current callback.
+    frames: usize,
+}
+
+impl<F: Frame> DelayLine<F> {
+    /// Create a delay line for round-trip latencies of up to `max_delay`
+    /// frames, preallocating references of `frames` frames.
+    pub(crate) fn new(max_delay: usize, frames: usize) -> DelayLine<F> {
+        DelayLine {
+            history: vec![F::silence(); max_delay.max(1)],
+            write: 0,
+            delay: 0,
+            reference: vec![F::silence(); frames],
+            frames: 0,
+        }
+    }
+
+    /// Set the round-trip latency, clamped to the maximum delay.
+    pub(crate) fn set_delay(&mut self, delay: usize) {
+        self.delay = delay.min(self.history.len());
+    }
+
+    /// Prepare the output frames played when the `frames` input frames of
+    /// the current callback were captured. Frames which are output by the
+    /// current callback, when the delay is shorter than the callback, are
+    /// silent.
+    pub(crate) fn prepare(&mut self, frames: usize) {
+        if self.reference.len() < frames {
+            // Only allocates if the backend asks for more frames than the
+            // latency the stream was created with.
+            self.reference.resize(frames, F::silence());
+        }
+        let len = self.history.len();
+        let start = (self.write + len - self.delay) % len;
+        let available = self.delay.min(frames);
+        for (i, frame) in self.reference[..available].iter_mut().enumerate() {
+            *frame = self.history[(start + i) % len];
+        }
+        self.reference[available..frames].fill(F::silence());
+        self.frames = frames;
+    }
+
+    /// Record the frames output by the current callback.
+    pub(crate) fn write(&mut self, frames: &[F]) {
+        let len = self.history.len();
+        // Only the most recent frames can ever be referenced.
+        let frames = &frames[frames.len().saturating_sub(len)..];
+        for frame in frames {
+            self.history[self.write] = *frame;
+            self.write = (self.write + 1) % len;
+        }
+    }
+}
+
+impl<F> DelayLine<F> {
+    /// The frames prepared by [`DelayLine::prepare`].
+    pub(crate) fn reference(&self) -> &[F] {
+        &self.reference[..self.frames]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonoFrame;
+
+    fn frames(values: &[f32]) -> Vec<MonoFrame<f32>> {
+        values.iter().map(|&m| MonoFrame { m }).collect()
+    }
+
+    #[test]
+    fn reference_is_delayed_output() {
+        let mut delay = DelayLine::new(8, 4);
+        delay.set_delay(6);
+        delay.prepare(4);
+        assert_eq!(delay.reference(), frames(&[0.0; 4]));
+        delay.write(&frames(&[1.0, 2.0, 3.0, 4.0]));
+        // Frames 1 and 2 were written 6 and 5 frames before the next
+        // callback.
+        delay.prepare(4);
+        assert_eq!(delay.reference(), frames(&[0.0, 0.0, 1.0, 2.0]));
+        delay.write(&frames(&[5.0, 6.0, 7.0, 8.0]));
+        delay.prepare(4);
+        assert_eq!(delay.reference(), frames(&[3.0, 4.0, 5.0, 6.0]));
+        delay.write(&frames(&[9.0, 10.0, 11.0, 12.0]));
+        delay.prepare(4);
+        assert_eq!(delay.reference(), frames(&[7.0, 8.0, 9.0, 10.0]));
+    }
+
+    #[test]
+    fn short_delay() {
+        let mut delay = DelayLine::new(8, 4);
+        delay.set_delay(2);
+        delay.write(&frames(&[1.0, 2.0, 3.0, 4.0]));
+        delay.prepare(4);
+        assert_eq!(delay.reference(), frames(&[3.0, 4.0, 0.0, 0.0]));
+    }
+}
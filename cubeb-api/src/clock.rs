@@ -0,0 +1,331 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Stream clock utilities
+
+use crate::{Error, Result, StreamRef};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Maximum number of observations kept to estimate the device clock rate.
+const MAX_SAMPLES: usize = 128;
+/// Minimum span of observations before a rate estimate is produced.
+const MIN_SPAN: Duration = Duration::from_millis(500);
+/// Time without the position advancing after which the stream is
+/// considered stopped.
+const STALL_TIMEOUT: Duration = Duration::from_millis(200);
+/// Estimates further than this from the nominal rate are discarded.
+const MAX_DEVIATION: f64 = 0.05;
+/// Weight given to each new rate estimate.
+const SMOOTHING: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug)]
+struct Observation {
+    time: Instant,
+    position: u64,
+}
+
+/// Relates a stream's playback position to the system monotonic clock.
+///
+/// `StreamClock` is fed with periodic observations of
+/// [`StreamRef::position`] and [`StreamRef::latency`], taken from a
+/// non-real-time thread, and uses them to convert between frames and
+/// [`Instant`]s. It also estimates the rate at which the device
+/// consumes frames, which drifts slightly from the nominal stream rate
+/// on most hardware.
+///
+/// # Example
+/// ```no_run
+/// # fn example(stream: &cubeb::StreamRef) -> cubeb::Result<()> {
+/// let mut clock = cubeb::StreamClock::new(48_000);
+/// loop {
+///     clock.update(stream)?;
+///     println!(
+///         "playing {:?}, device rate {:.2} Hz",
+///         clock.playback_time(),
+///         clock.estimated_rate()
+///     );
+///     std::thread::sleep(std::time::Duration::from_millis(100));
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct StreamClock {
+    rate: u32,
+    latency: u32,
+    observations: VecDeque<Observation>,
+    running: bool,
+    ratio: Option<f64>,
+}
+
+impl StreamClock {
+    /// Create a clock for a stream running at `rate` frames per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    pub fn new(rate: u32) -> StreamClock {
+        assert!(rate > 0, "stream rate must be non-zero");
+        StreamClock {
+            rate,
+            latency: 0,
+            observations: VecDeque::with_capacity(MAX_SAMPLES),
+            running: false,
+            ratio: None,
+        }
+    }
+
+    /// Sample the position and latency of `stream` now.
+    ///
+    /// Latency is treated as zero if the backend doesn't support
+    /// querying it.
+    pub fn update(&mut self, stream: &StreamRef) -> Result<()> {
+        let position = stream.position()?;
+        let now = Instant::now();
+        let latency = match stream.latency() {
+            Ok(latency) => latency,
+            Err(Error::NotSupported) => 0,
+            Err(e) => return Err(e),
+        };
+        self.update_at(now, position, latency);
+        Ok(())
+    }
+
+    /// Record an observation of `position` and `latency` made at `now`.
+    pub fn update_at(&mut self, now: Instant, position: u64, latency: u32) {
+        self.latency = latency;
+
+        if let Some(last) = self.observations.back() {
+            if now <= last.time {
+                return;
+            }
+            if position < last.position {
+                // Stream was reset; start a new measurement segment but
+                // keep the last rate estimate.
+                self.running = false;
+                self.observations.clear();
+            } else if position == last.position {
+                // Positions are only updated once per device period, so
+                // only treat the stream as stopped once it has stalled
+                // for a while.
+                if now - last.time >= STALL_TIMEOUT {
+                    self.running = false;
+                    self.observations.clear();
+                    self.observations.push_back(Observation {
+                        time: now,
+                        position,
+                    });
+                }
+                return;
+            } else {
+                self.running = true;
+            }
+        }
+
+        if self.observations.len() == MAX_SAMPLES {
+            // Halve the density of observations rather than dropping the
+            // oldest so the fit keeps covering the whole segment; drift is
+            // only visible over long periods.
+            let mut i = 0;
+            self.observations.retain(|_| {
+                i += 1;
+                i % 2 == 1
+            });
+        }
+        self.observations.push_back(Observation {
+            time: now,
+            position,
+        });
+
+        if let Some(measured) = self.measure_ratio() {
+            self.ratio = Some(match self.ratio {
+                Some(ratio) => ratio + SMOOTHING * (measured - ratio),
+                None => measured,
+            });
+        }
+    }
+
+    /// Forget all observations and the rate estimate.
+    pub fn reset(&mut self) {
+        self.observations.clear();
+        self.running = false;
+        self.ratio = None;
+    }
+
+    /// Nominal stream rate in frames per second.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Output latency reported by the last observation.
+    pub fn latency(&self) -> Duration {
+        self.frames_to_duration(f64::from(self.latency))
+    }
+
+    /// Smoothed ratio between the measured device rate and the nominal
+    /// stream rate. Returns `1.0` until enough observations are
+    /// available.
+    pub fn rate_ratio(&self) -> f64 {
+        self.ratio.unwrap_or(1.0)
+    }
+
+    /// Smoothed estimate of the device rate in frames per second of the
+    /// system monotonic clock.
+    pub fn estimated_rate(&self) -> f64 {
+        f64::from(self.rate) * self.rate_ratio()
+    }
+
+    /// Playback position extrapolated to `now`, in frames.
+    ///
+    /// Returns `None` before the first observation.
+    pub fn position_at(&self, now: Instant) -> Option<f64> {
+        let last = self.observations.back()?;
+        let position = last.position as f64;
+        if !self.running || now <= last.time {
+            return Some(position);
+        }
+        let elapsed = (now - last.time).as_secs_f64();
+        Some(position + elapsed * self.estimated_rate())
+    }
+
+    /// Time of the audio currently being heard, measured from the
+    /// start of the stream: the position, less the frames still in the
+    /// output latency.
+    pub fn playback_time(&self) -> Duration {
+        let position = self.position_at(Instant::now()).unwrap_or(0.0);
+        self.frames_to_duration((position - f64::from(self.latency)).max(0.0))
+    }
+
+    /// The instant at which `frame` is, or was, heard: when the position
+    /// reaches it, plus the output latency.
+    ///
+    /// Returns `None` before the first observation.
+    pub fn presentation_time_of(&self, frame: u64) -> Option<Instant> {
+        let last = self.observations.back()?;
+        let rate = self.estimated_rate();
+        let heard = last.time.checked_add(self.latency())?;
+        if frame >= last.position {
+            let ahead = (frame - last.position) as f64 / rate;
+            heard.checked_add(Duration::from_secs_f64(ahead))
+        } else {
+            let behind = (last.position - frame) as f64 / rate;
+            heard.checked_sub(Duration::from_secs_f64(behind))
+        }
+    }
+
+    fn frames_to_duration(&self, frames: f64) -> Duration {
+        Duration::from_secs_f64(frames / f64::from(self.rate))
+    }
+
+    // Least squares fit of position against time over the current
+    // segment of observations.
+    fn measure_ratio(&self) -> Option<f64> {
+        let first = self.observations.front()?;
+        let last = self.observations.back()?;
+        if !self.running || last.time - first.time < MIN_SPAN {
+            return None;
+        }
+
+        let n = self.observations.len() as f64;
+        let (mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0);
+        for o in &self.observations {
+            let x = (o.time - first.time).as_secs_f64();
+            let y = (o.position - first.position) as f64;
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+        }
+        let denom = n * sxx - sx * sx;
+        if denom <= 0.0 {
+            return None;
+        }
+        let slope = (n * sxy - sx * sy) / denom;
+        let ratio = slope / f64::from(self.rate);
+        if (ratio - 1.0).abs() > MAX_DEVIATION {
+            return None;
+        }
+        Some(ratio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamClock;
+    use std::time::{Duration, Instant};
+
+    // Feed `clock` observations every 30ms of a device running at
+    // `ratio` times the nominal rate, with the position advancing in
+    // whole periods of `period` frames.
+    fn run(clock: &mut StreamClock, start: Instant, secs: u64, ratio: f64, period: u64) -> Instant {
+        let rate = f64::from(clock.rate()) * ratio;
+        let mut now = start;
+        for i in 1..=secs * 1000 / 30 {
+            now = start + Duration::from_millis(i * 30);
+            let frames = ((i * 30) as f64 / 1000.0 * rate) as u64;
+            clock.update_at(now, frames / period * period, 512);
+        }
+        now
+    }
+
+    #[test]
+    fn clock_defaults_to_nominal_rate() {
+        let clock = StreamClock::new(48_000);
+        assert_eq!(clock.rate_ratio(), 1.0);
+        assert_eq!(clock.position_at(Instant::now()), None);
+        assert_eq!(clock.presentation_time_of(0), None);
+    }
+
+    #[test]
+    fn clock_estimates_drift() {
+        let mut clock = StreamClock::new(48_000);
+        run(&mut clock, Instant::now(), 120, 1.0005, 480);
+        assert!((clock.rate_ratio() - 1.0005).abs() < 1e-4);
+        assert!((clock.estimated_rate() - 48_024.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn clock_presentation_time() {
+        let mut clock = StreamClock::new(48_000);
+        let start = Instant::now();
+        clock.update_at(start, 48_000, 480);
+        assert_eq!(clock.latency(), Duration::from_millis(10));
+        // Frames are heard after the latency.
+        assert_eq!(
+            clock.presentation_time_of(48_000),
+            Some(start + Duration::from_millis(10))
+        );
+        assert_eq!(
+            clock.presentation_time_of(72_000),
+            Some(start + Duration::from_millis(510))
+        );
+        assert_eq!(
+            clock.presentation_time_of(24_000),
+            Some(start - Duration::from_millis(490))
+        );
+        // A single observation doesn't extrapolate, and the frames in the
+        // latency aren't heard yet.
+        assert_eq!(clock.playback_time(), Duration::from_millis(990));
+    }
+
+    #[test]
+    #[should_panic]
+    fn clock_zero_rate() {
+        StreamClock::new(0);
+    }
+
+    #[test]
+    fn clock_holds_position_when_stopped() {
+        let mut clock = StreamClock::new(48_000);
+        let start = Instant::now();
+        let now = run(&mut clock, start, 3, 1.0, 480);
+        let stopped = now + Duration::from_millis(500);
+        clock.update_at(stopped, 144_000, 512);
+        let later = stopped + Duration::from_secs(1);
+        assert_eq!(clock.position_at(later), Some(144_000.0));
+        // The rate estimate survives the stop.
+        assert!((clock.rate_ratio() - 1.0).abs() < 1e-3);
+    }
+}
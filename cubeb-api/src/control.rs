@@ -0,0 +1,184 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Stream state machine
+
+use crate::Error;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::{error, fmt};
+
+/// State of a [`Stream`](crate::Stream), as tracked by cubeb-api.
+///
+/// ```text
+///           start           pause
+/// Created --------> Started ------> Paused
+///                    ^  |  <------    |
+///              start |  |  resume     |
+///                    |  v stop        |
+///                   Stopped <---------+ stop
+///                      ^
+///                      | stop
+///                   Drained
+/// ```
+///
+/// `Started` becomes `Drained` when the backend reports it has drained.
+/// Any state becomes `Error` when the backend reports an error; `Error`
+/// is final.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamState {
+    /// The stream was created and has never been started.
+    Created,
+    /// The stream is running.
+    Started,
+    /// The stream was paused with [`Stream::pause`](crate::Stream::pause).
+    Paused,
+    /// The stream was stopped.
+    Stopped,
+    /// The stream output all its frames after the data callback returned
+    /// fewer frames than requested. It must be stopped before it can be
+    /// started again.
+    Drained,
+    /// The backend reported an error.
+    Error,
+}
+
+impl StreamState {
+    /// True if a stream in this state may move to `to` through a control
+    /// method.
+    pub fn can_transition_to(self, to: StreamState) -> bool {
+        use StreamState::*;
+        matches!(
+            (self, to),
+            (Created | Stopped | Paused, Started)
+                | (Started, Paused)
+                | (Created | Started | Paused | Stopped | Drained, Stopped)
+        )
+    }
+
+    fn from_u8(state: u8) -> StreamState {
+        match state {
+            0 => StreamState::Created,
+            1 => StreamState::Started,
+            2 => StreamState::Paused,
+            3 => StreamState::Stopped,
+            4 => StreamState::Drained,
+            _ => StreamState::Error,
+        }
+    }
+}
+
+/// How [`Stream::pause`](crate::Stream::pause) pauses the stream, see
+/// [`StreamBuilder::pause_mode`](crate::StreamBuilder::pause_mode).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PauseMode {
+    /// Stop the backend stream. Audio buffered by the backend may be
+    /// lost, and the stream position holds its value until the stream is
+    /// resumed.
+    #[default]
+    HardStop,
+    /// Keep the backend stream running and output silence instead of
+    /// calling the data callback. No buffered audio is lost, and the
+    /// stream position keeps advancing by the silent frames while paused.
+    /// Input is discarded while paused.
+    SilenceFill,
+}
+
+/// Error returned by the [`Stream`](crate::Stream) control methods.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlError {
+    /// The stream can't move from `from` to `to`, e.g. starting a
+    /// drained stream.
+    InvalidTransition { from: StreamState, to: StreamState },
+    /// The backend failed to change the state of the stream, which was
+    /// left unchanged.
+    Backend(Error),
+}
+
+impl error::Error for ControlError {}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlError::InvalidTransition { from, to } => {
+                write!(f, "Invalid transition from {from:?} to {to:?}")
+            }
+            ControlError::Backend(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ControlError> for Error {
+    fn from(e: ControlError) -> Error {
+        match e {
+            ControlError::InvalidTransition { .. } => Error::InvalidParameter,
+            ControlError::Backend(e) => e,
+        }
+    }
+}
+
+/// [`StreamState`] shared between the control methods and the state
+/// callback trampoline.
+pub(crate) struct AtomicStreamState(AtomicU8);
+
+impl AtomicStreamState {
+    pub(crate) fn new(state: StreamState) -> AtomicStreamState {
+        AtomicStreamState(AtomicU8::new(state as u8))
+    }
+
+    pub(crate) fn load(&self) -> StreamState {
+        StreamState::from_u8(self.0.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn store(&self, state: StreamState) {
+        self.0.store(state as u8, Ordering::Release);
+    }
+
+    /// Move to `to` if the state is still `from`.
+    pub(crate) fn replace(&self, from: StreamState, to: StreamState) -> bool {
+        self.0
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use StreamState::*;
+
+    #[test]
+    fn transitions() {
+        assert!(Created.can_transition_to(Started));
+        assert!(Started.can_transition_to(Paused));
+        assert!(Paused.can_transition_to(Started));
+        assert!(Drained.can_transition_to(Stopped));
+        assert!(Stopped.can_transition_to(Started));
+        assert!(!Drained.can_transition_to(Started));
+        assert!(!Stopped.can_transition_to(Paused));
+        assert!(!Started.can_transition_to(Started));
+        assert!(!Error.can_transition_to(Stopped));
+        assert!(!Started.can_transition_to(Created));
+    }
+
+    #[test]
+    fn atomic_state() {
+        let state = AtomicStreamState::new(Created);
+        for s in [Created, Started, Paused, Stopped, Drained, Error] {
+            state.store(s);
+            assert_eq!(state.load(), s);
+        }
+        state.store(Started);
+        assert!(state.replace(Started, Drained));
+        assert!(!state.replace(Started, Stopped));
+        assert_eq!(state.load(), Drained);
+        assert_eq!(
+            crate::Error::from(ControlError::InvalidTransition {
+                from: Drained,
+                to: Started
+            }),
+            crate::Error::InvalidParameter
+        );
+    }
+}
@@ -1,19 +1,61 @@
 //! Frame utilities
 
+use crate::Sample;
+use std::slice;
+
 /// A `Frame` is a collection of samples which have a a specific
 /// layout represented by `ChannelLayout`
-pub trait Frame {}
+pub trait Frame: Copy + Send {
+    /// Type of each channel's sample.
+    type Sample: Sample;
+    /// Number of channels in the frame.
+    const CHANNELS: usize;
+
+    /// A frame with every channel silent.
+    fn silence() -> Self;
+    /// The frame's samples, in channel order.
+    fn channels(&self) -> &[Self::Sample];
+    /// The frame's samples, in channel order.
+    fn channels_mut(&mut self) -> &mut [Self::Sample];
+}
+
+// Frames are `repr(C)` structs of `CHANNELS` samples, so they can be
+// viewed as a slice of samples.
+macro_rules! impl_frame {
+    ($frame:ident, $channels:expr, $($field:ident),+) => {
+        impl<T: Sample> Frame for $frame<T> {
+            type Sample = T;
+            const CHANNELS: usize = $channels;
+
+            fn silence() -> Self {
+                $frame {
+                    $($field: T::from_float(0.0)),+
+                }
+            }
+
+            fn channels(&self) -> &[T] {
+                unsafe { slice::from_raw_parts(self as *const Self as *const T, $channels) }
+            }
+
+            fn channels_mut(&mut self) -> &mut [T] {
+                unsafe { slice::from_raw_parts_mut(self as *mut Self as *mut T, $channels) }
+            }
+        }
+    };
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
 /// A monaural frame.
 pub struct MonoFrame<T> {
     /// Mono channel
     pub m: T,
 }
 
-impl<T> Frame for MonoFrame<T> {}
+impl_frame!(MonoFrame, 1, m);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
 /// A stereo frame.
 pub struct StereoFrame<T> {
     /// Left channel
@@ -22,4 +64,27 @@ pub struct StereoFrame<T> {
     pub r: T,
 }
 
-impl<T> Frame for StereoFrame<T> {}
+impl_frame!(StereoFrame, 2, l, r);
+
+// A frame with more channels than the usual layouts, for tests.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct WideFrame(pub [f32; 40]);
+
+#[cfg(test)]
+impl Frame for WideFrame {
+    type Sample = f32;
+    const CHANNELS: usize = 40;
+
+    fn silence() -> Self {
+        WideFrame([0.0; 40])
+    }
+
+    fn channels(&self) -> &[f32] {
+        &self.0
+    }
+
+    fn channels_mut(&mut self) -> &mut [f32] {
+        &mut self.0
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Click-free gain stage applied by the data callback trampoline
+
+use crate::{Frame, Sample};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Duration of the fades applied by `mute` and `unmute`.
+pub(crate) const MUTE_FADE: Duration = Duration::from_millis(10);
+
+/// Gain settings written by the control thread.
+pub(crate) struct GainControl {
+    rate: u32,
+    // f32 bits of the volume to ramp to.
+    volume: AtomicU32,
+    ramp_frames: AtomicU32,
+    // Incremented each time a new volume ramp is requested.
+    generation: AtomicU32,
+    muted: AtomicBool,
+    // f32 bits of the gain of each channel.
+    channels: Box<[AtomicU32]>,
+}
+
+impl GainControl {
+    pub(crate) fn new(rate: u32, channels: usize) -> GainControl {
+        GainControl {
+            rate,
+            volume: AtomicU32::new(1f32.to_bits()),
+            ramp_frames: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+            muted: AtomicBool::new(false),
+            channels: (0..channels)
+                .map(|_| AtomicU32::new(1f32.to_bits()))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn ramp_volume(&self, volume: f32, duration: Duration) {
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+        self.ramp_frames
+            .store(self.frames(duration), Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub(crate) fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Release);
+    }
+
+    pub(crate) fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub(crate) fn set_channel_gain(&self, channel: usize, gain: f32) {
+        self.channels[channel].store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn channel_gain(&self, channel: usize) -> f32 {
+        f32::from_bits(self.channels[channel].load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn frames(&self, duration: Duration) -> u32 {
+        (duration.as_secs_f64() * f64::from(self.rate)).round() as u32
+    }
+}
+
+/// Linear ramp between two gains, advanced one frame at a time.
+#[derive(Clone, Copy, Debug)]
+struct Ramp {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
+}
+
+impl Ramp {
+    fn new(gain: f32) -> Ramp {
+        Ramp {
+            current: gain,
+            target: gain,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    fn start(&mut self, target: f32, frames: u32) {
+        self.target = target;
+        if frames == 0 {
+            self.current = target;
+            self.remaining = 0;
+        } else {
+            self.step = (target - self.current) / frames as f32;
+            self.remaining = frames;
+        }
+    }
+
+    fn is_steady(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn next(&mut self) -> f32 {
+        let gain = self.current;
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+        gain
+    }
+}
+
+/// Gain state owned by the data callback trampoline.
+pub(crate) struct GainStage {
+    generation: u32,
+    muted: bool,
+    volume: Ramp,
+    mute: Ramp,
+    fade: Ramp,
+    channels: Box<[f32]>,
+    // Start and step of the per-channel gains over the current buffer.
+    ramps: Box<[(f32, f32)]>,
+}
+
+impl GainStage {
+    pub(crate) fn new(control: &GainControl) -> GainStage {
+        GainStage {
+            generation: control.generation.load(Ordering::Acquire),
+            muted: false,
+            volume: Ramp::new(control.volume()),
+            mute: Ramp::new(1.0),
+            fade: Ramp::new(1.0),
+            channels: vec![1.0; control.channel_count()].into_boxed_slice(),
+            ramps: vec![(1.0, 0.0); control.channel_count()].into_boxed_slice(),
+        }
+    }
+
+    /// True when the last ramp requested by the control thread has
+    /// completed.
+    #[cfg(test)]
+    pub(crate) fn is_settled(&self, control: &GainControl) -> bool {
+        self.generation == control.generation.load(Ordering::Acquire)
+            && self.volume.is_steady()
+            && self.mute.is_steady()
+    }
+
+    /// Fade the output out over `frames`, on top of the other gains.
+    pub(crate) fn fade_out(&mut self, frames: u32) {
+        self.fade.start(0.0, frames);
+    }
+
+    /// Cancel a fade started by [`GainStage::fade_out`].
+    pub(crate) fn reset_fade(&mut self) {
+        self.fade = Ramp::new(1.0);
+    }
+
+    /// Apply the current gains to `frames`, advancing any ramps.
+    pub(crate) fn process<F: Frame>(&mut self, control: &GainControl, frames: &mut [F]) {
+        let generation = control.generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.generation = generation;
+            let frames = control.ramp_frames.load(Ordering::Relaxed);
+            self.volume.start(control.volume(), frames);
+        }
+        let muted = control.is_muted();
+        if muted != self.muted {
+            self.muted = muted;
+            let target = if muted { 0.0 } else { 1.0 };
+            self.mute.start(target, control.frames(MUTE_FADE));
+        }
+
+        if frames.is_empty() {
+            // Per-channel gain changes are ramped over the next buffer.
+            return;
+        }
+
+        // Per-channel gain changes are ramped over the buffer.
+        let len = frames.len() as f32;
+        let mut unity = [self.volume, self.mute, self.fade]
+            .iter()
+            .all(|ramp| ramp.is_steady() && ramp.current == 1.0);
+        let channels = self.channels.len().min(F::CHANNELS);
+        for (c, ramp) in self.ramps.iter_mut().enumerate().take(channels) {
+            let target = control.channel_gain(c);
+            *ramp = (self.channels[c], (target - self.channels[c]) / len);
+            self.channels[c] = target;
+            unity &= ramp.0 == 1.0 && ramp.1 == 0.0;
+        }
+        if unity {
+            return;
+        }
+
+        for frame in frames.iter_mut() {
+            let gain = self.volume.next() * self.mute.next() * self.fade.next();
+            for (sample, (channel, step)) in frame
+                .channels_mut()
+                .iter_mut()
+                .zip(self.ramps.iter_mut().take(channels))
+            {
+                *sample = F::Sample::from_float(sample.to_float() * gain * *channel);
+                *channel += *step;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::WideFrame;
+    use crate::{MonoFrame, StereoFrame};
+
+    type Frame = MonoFrame<f32>;
+
+    fn ones(n: usize) -> Vec<Frame> {
+        vec![Frame { m: 1.0 }; n]
+    }
+
+    #[test]
+    fn unity_gain_is_passthrough() {
+        let control = GainControl::new(1000, 1);
+        let mut stage = GainStage::new(&control);
+        let mut frames = ones(4);
+        stage.process(&control, &mut frames);
+        assert_eq!(frames, ones(4));
+        assert!(stage.is_settled(&control));
+    }
+
+    #[test]
+    fn volume_ramp() {
+        let control = GainControl::new(1000, 1);
+        let mut stage = GainStage::new(&control);
+        control.ramp_volume(0.0, Duration::from_millis(4));
+        assert!(!stage.is_settled(&control));
+
+        let mut frames = ones(6);
+        stage.process(&control, &mut frames);
+        let gains: Vec<f32> = frames.iter().map(|f| f.m).collect();
+        assert_eq!(gains, [1.0, 0.75, 0.5, 0.25, 0.0, 0.0]);
+        assert!(stage.is_settled(&control));
+        assert_eq!(control.volume(), 0.0);
+    }
+
+    #[test]
+    fn ramp_continues_across_buffers() {
+        let control = GainControl::new(1000, 1);
+        let mut stage = GainStage::new(&control);
+        control.ramp_volume(0.5, Duration::from_millis(2));
+        let mut frames = ones(1);
+        stage.process(&control, &mut frames);
+        assert_eq!(frames[0].m, 1.0);
+        stage.process(&control, &mut frames);
+        assert_eq!(frames[0].m, 0.75);
+        let mut frames = ones(2);
+        stage.process(&control, &mut frames);
+        assert_eq!(frames, vec![Frame { m: 0.5 }; 2]);
+    }
+
+    #[test]
+    fn mute_fades() {
+        let control = GainControl::new(1000, 1);
+        let mut stage = GainStage::new(&control);
+        control.set_muted(true);
+        let mut frames = ones(12);
+        stage.process(&control, &mut frames);
+        assert_eq!(frames[0].m, 1.0);
+        assert!(frames[5].m > 0.0 && frames[5].m < 1.0);
+        assert_eq!(frames[11].m, 0.0);
+
+        control.set_muted(false);
+        let mut frames = ones(12);
+        stage.process(&control, &mut frames);
+        assert_eq!(frames[0].m, 0.0);
+        assert_eq!(frames[11].m, 1.0);
+    }
+
+    #[test]
+    fn fade_out() {
+        let control = GainControl::new(1000, 1);
+        let mut stage = GainStage::new(&control);
+        control.ramp_volume(0.5, Duration::ZERO);
+        stage.fade_out(2);
+        let mut frames = ones(3);
+        stage.process(&control, &mut frames);
+        assert_eq!(
+            frames.iter().map(|f| f.m).collect::<Vec<_>>(),
+            [0.5, 0.25, 0.0]
+        );
+        stage.reset_fade();
+        let mut frames = ones(1);
+        stage.process(&control, &mut frames);
+        assert_eq!(frames[0].m, 0.5);
+    }
+
+    #[test]
+    fn channel_gain() {
+        let control = GainControl::new(1000, 2);
+        let mut stage = GainStage::new(&control);
+        control.set_channel_gain(1, 0.0);
+        let mut frames = vec![StereoFrame { l: 1.0f32, r: 1.0 }; 4];
+        stage.process(&control, &mut frames);
+        assert!(frames.iter().all(|f| f.l == 1.0));
+        let right: Vec<f32> = frames.iter().map(|f| f.r).collect();
+        assert_eq!(right, [1.0, 0.75, 0.5, 0.25]);
+
+        let mut frames = vec![StereoFrame { l: 1.0f32, r: 1.0 }; 2];
+        stage.process(&control, &mut frames);
+        assert_eq!(frames, vec![StereoFrame { l: 1.0, r: 0.0 }; 2]);
+    }
+
+    #[test]
+    fn channel_gain_ramps_after_empty_buffer() {
+        let control = GainControl::new(1000, 2);
+        let mut stage = GainStage::new(&control);
+        control.set_channel_gain(0, 0.0);
+        stage.process::<StereoFrame<f32>>(&control, &mut []);
+        let mut frames = vec![StereoFrame { l: 1.0f32, r: 1.0 }; 2];
+        stage.process(&control, &mut frames);
+        let left: Vec<f32> = frames.iter().map(|f| f.l).collect();
+        assert_eq!(left, [1.0, 0.5]);
+    }
+
+    #[test]
+    fn many_channels() {
+        let control = GainControl::new(1000, 40);
+        let mut stage = GainStage::new(&control);
+        control.set_channel_gain(39, 0.0);
+        let mut frames = vec![WideFrame([1.0; 40]); 2];
+        stage.process(&control, &mut frames);
+        assert_eq!(frames[1].0[39], 0.5);
+    }
+}
@@ -0,0 +1,302 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Xrun and glitch detection
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Kind of a probable glitch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlitchKind {
+    /// Output ran out of data.
+    Underrun,
+    /// Input data was dropped.
+    Overrun,
+}
+
+/// How a glitch was detected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlitchSource {
+    /// A data callback arrived later than the frames already delivered
+    /// could cover.
+    CallbackCadence,
+    /// The stream position didn't progress in step with the system clock.
+    Position,
+}
+
+/// A probable underrun or overrun.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlitchEvent {
+    /// Kind of the glitch.
+    pub kind: GlitchKind,
+    /// How the glitch was detected.
+    pub source: GlitchSource,
+    /// When the glitch was detected.
+    pub timestamp: Instant,
+    /// Estimated number of frames lost.
+    pub frames_lost: u64,
+}
+
+/// User supplied callback notified of probable glitches detected from the
+/// callback cadence. It is called on the audio thread, right before the
+/// data callback, so it must not block.
+pub type GlitchCallback = dyn FnMut(GlitchEvent) + Send + Sync + 'static;
+
+/// Glitch counters, see [`Stream::glitches`](crate::Stream::glitches).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GlitchCounts {
+    /// Number of probable underruns.
+    pub underruns: u64,
+    /// Number of probable overruns.
+    pub overruns: u64,
+    /// Estimated total number of frames lost.
+    pub frames_lost: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct GlitchCounters {
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+    frames_lost: AtomicU64,
+}
+
+impl GlitchCounters {
+    pub(crate) fn record(&self, event: &GlitchEvent) {
+        match event.kind {
+            GlitchKind::Underrun => &self.underruns,
+            GlitchKind::Overrun => &self.overruns,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        self.frames_lost
+            .fetch_add(event.frames_lost, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> GlitchCounts {
+        GlitchCounts {
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            frames_lost: self.frames_lost.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Detects glitches from the arrival times of data callbacks.
+///
+/// Models the device buffer as a bucket filled by each callback and
+/// drained at the stream rate. The backend is assumed to buffer at least
+/// the largest callback seen, so a glitch is reported once the bucket is
+/// overdrawn by more than that.
+pub(crate) struct CadenceDetector {
+    kind: GlitchKind,
+    rate: f64,
+    level: f64,
+    max_frames: usize,
+    last: Option<(Instant, usize)>,
+}
+
+impl CadenceDetector {
+    pub(crate) fn new(kind: GlitchKind, rate: u32) -> CadenceDetector {
+        CadenceDetector {
+            kind,
+            rate: f64::from(rate),
+            level: 0.0,
+            max_frames: 0,
+            last: None,
+        }
+    }
+
+    /// Forget the callback history, e.g. when the stream is restarted.
+    pub(crate) fn reset(&mut self) {
+        self.level = 0.0;
+        self.last = None;
+    }
+
+    pub(crate) fn observe(&mut self, now: Instant, frames: usize) -> Option<GlitchEvent> {
+        self.max_frames = self.max_frames.max(frames);
+        let last = self.last.replace((now, frames));
+        let (time, delivered) = last?;
+
+        let consumed = now.saturating_duration_since(time).as_secs_f64() * self.rate;
+        self.level = (self.level + delivered as f64 - consumed).min(self.max_frames as f64);
+        if self.level >= -(self.max_frames as f64) {
+            return None;
+        }
+
+        let frames_lost = (-self.level - self.max_frames as f64) as u64;
+        self.level = 0.0;
+        Some(GlitchEvent {
+            kind: self.kind,
+            source: GlitchSource::CallbackCadence,
+            timestamp: now,
+            frames_lost,
+        })
+    }
+}
+
+/// Minimum discrepancy, as a duration, between the stream position and
+/// the system clock before a glitch is reported.
+const POSITION_TOLERANCE: Duration = Duration::from_millis(50);
+
+/// Detects glitches from the progression of the stream position.
+pub(crate) struct PositionDetector {
+    kind: GlitchKind,
+    // Rate of the stream position.
+    rate: f64,
+    // Stream frames per data callback frame, see
+    // `StreamBuilder::user_rate`.
+    period_scale: f64,
+    last: Option<(Instant, u64)>,
+}
+
+impl PositionDetector {
+    /// Detect glitches of a stream running at `stream_rate`, whose data
+    /// callback runs at `callback_rate`.
+    pub(crate) fn new(kind: GlitchKind, stream_rate: u32, callback_rate: u32) -> PositionDetector {
+        PositionDetector {
+            kind,
+            rate: f64::from(stream_rate),
+            period_scale: f64::from(stream_rate) / f64::from(callback_rate),
+            last: None,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Compare `position`, sampled at `now`, with the previous sample.
+    /// `period` is the largest number of data callback frames the position
+    /// may lag behind because it's only updated once per device period.
+    pub(crate) fn observe(
+        &mut self,
+        now: Instant,
+        position: u64,
+        period: usize,
+    ) -> Option<GlitchEvent> {
+        let last = self.last.replace((now, position));
+        let (time, last_position) = last?;
+        if position < last_position {
+            return None;
+        }
+
+        let expected = now.saturating_duration_since(time).as_secs_f64() * self.rate;
+        let advanced = (position - last_position) as f64;
+        let period = period as f64 * self.period_scale;
+        let tolerance = (POSITION_TOLERANCE.as_secs_f64() * self.rate).max(2.0 * period);
+        let discrepancy = (expected - advanced).abs();
+        if discrepancy <= tolerance {
+            return None;
+        }
+
+        Some(GlitchEvent {
+            kind: self.kind,
+            source: GlitchSource::Position,
+            timestamp: now,
+            frames_lost: discrepancy as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    #[test]
+    fn cadence_regular_callbacks() {
+        let mut d = CadenceDetector::new(GlitchKind::Underrun, RATE);
+        let start = Instant::now();
+        for i in 0..100 {
+            // Jitter of half a period either way is absorbed.
+            let jitter = if i % 2 == 0 { 5 } else { 0 };
+            let now = start + Duration::from_millis(i * 10 + jitter);
+            assert_eq!(d.observe(now, 480), None);
+        }
+    }
+
+    #[test]
+    fn cadence_late_callback() {
+        let mut d = CadenceDetector::new(GlitchKind::Underrun, RATE);
+        let start = Instant::now();
+        for i in 0..10 {
+            assert_eq!(d.observe(start + Duration::from_millis(i * 10), 480), None);
+        }
+        // The next callback was due at 100ms.
+        let now = start + Duration::from_millis(140);
+        let event = d.observe(now, 480).unwrap();
+        assert_eq!(event.kind, GlitchKind::Underrun);
+        assert_eq!(event.source, GlitchSource::CallbackCadence);
+        assert_eq!(event.timestamp, now);
+        assert!((event.frames_lost as i64 - 1440).abs() <= 1);
+        // Detection restarts from an empty buffer.
+        assert_eq!(d.observe(now + Duration::from_millis(10), 480), None);
+    }
+
+    #[test]
+    fn position_progression() {
+        let mut d = PositionDetector::new(GlitchKind::Overrun, RATE, RATE);
+        let start = Instant::now();
+        assert_eq!(d.observe(start, 0, 480), None);
+        let now = start + Duration::from_secs(1);
+        assert_eq!(d.observe(now, 47_520, 480), None);
+        // Position stalled for half a second.
+        let now = now + Duration::from_secs(1);
+        let event = d.observe(now, 71_520, 480).unwrap();
+        assert_eq!(event.kind, GlitchKind::Overrun);
+        assert_eq!(event.source, GlitchSource::Position);
+        assert_eq!(event.frames_lost, 24_000);
+        // Restarting the stream resets the position.
+        assert_eq!(d.observe(now + Duration::from_secs(1), 0, 480), None);
+    }
+
+    #[test]
+    fn position_progression_user_rate() {
+        // The position counts at the stream rate, not at the rate of the
+        // data callback.
+        let mut d = PositionDetector::new(GlitchKind::Underrun, 44_100, RATE);
+        let start = Instant::now();
+        assert_eq!(d.observe(start, 0, 480), None);
+        let now = start + Duration::from_secs(1);
+        assert_eq!(d.observe(now, 44_100, 480), None);
+        let now = now + Duration::from_secs(1);
+        assert_eq!(d.observe(now, 66_150, 480).unwrap().frames_lost, 22_050);
+
+        // Periods of 4800 callback frames are 4410 stream frames, so the
+        // position may lag by up to 8820 frames.
+        let mut d = PositionDetector::new(GlitchKind::Underrun, 44_100, RATE);
+        assert_eq!(d.observe(start, 0, 4800), None);
+        let now = start + Duration::from_secs(1);
+        assert_eq!(d.observe(now, 44_100 - 8820, 4800), None);
+        let now = now + Duration::from_secs(1);
+        let position = 44_100 - 8820 + 44_100 - 9000;
+        assert_eq!(d.observe(now, position, 4800).unwrap().frames_lost, 9000);
+    }
+
+    #[test]
+    fn counters() {
+        let c = GlitchCounters::default();
+        let event = GlitchEvent {
+            kind: GlitchKind::Underrun,
+            source: GlitchSource::Position,
+            timestamp: Instant::now(),
+            frames_lost: 10,
+        };
+        c.record(&event);
+        c.record(&GlitchEvent {
+            kind: GlitchKind::Overrun,
+            ..event
+        });
+        assert_eq!(
+            c.snapshot(),
+            GlitchCounts {
+                underruns: 1,
+                overruns: 1,
+                frames_lost: 20,
+            }
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Synchronized start of several streams
+
+use crate::{ControlError, Frame, Stream};
+use std::time::{Duration, Instant};
+
+trait GroupMember {
+    fn start_at(&self, when: Instant) -> Result<(), ControlError>;
+    fn stop(&self) -> Result<(), ControlError>;
+}
+
+impl<F: Frame> GroupMember for Stream<F> {
+    fn start_at(&self, when: Instant) -> Result<(), ControlError> {
+        Stream::start_at(self, when)
+    }
+
+    fn stop(&self) -> Result<(), ControlError> {
+        Stream::try_stop(self)
+    }
+}
+
+/// Starts several streams so that their first frames are heard at the
+/// same instant, see [`Stream::start_at`].
+///
+/// The streams should be created from the same
+/// [`Context`](crate::Context), so their latencies are measured against
+/// the same clock.
+///
+/// # Example
+/// ```no_run
+/// # fn example(
+/// #     music: &cubeb::Stream<cubeb::StereoFrame<f32>>,
+/// #     click: &cubeb::Stream<cubeb::MonoFrame<f32>>,
+/// # ) -> Result<(), cubeb::ControlError> {
+/// let mut group = cubeb::StreamGroup::new();
+/// group.add(music).add(click);
+/// group.start_in(std::time::Duration::from_millis(200))
+/// # }
+/// ```
+#[derive(Default)]
+pub struct StreamGroup<'a> {
+    streams: Vec<&'a dyn GroupMember>,
+}
+
+impl<'a> StreamGroup<'a> {
+    pub fn new() -> StreamGroup<'a> {
+        Default::default()
+    }
+
+    /// Add `stream` to the group.
+    pub fn add<F: Frame>(&mut self, stream: &'a Stream<F>) -> &mut Self {
+        self.streams.push(stream);
+        self
+    }
+
+    /// Start all the streams so they're heard from `when`.
+    ///
+    /// `when` should leave enough time for each stream to start and fill
+    /// its output latency, otherwise the streams that start late won't be
+    /// aligned. If any stream fails to start, the streams already started
+    /// are stopped and the error is returned.
+    pub fn start_at(&self, when: Instant) -> Result<(), ControlError> {
+        for (i, stream) in self.streams.iter().enumerate() {
+            if let Err(e) = stream.start_at(when) {
+                for started in &self.streams[..i] {
+                    let _ = started.stop();
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Start all the streams so they're heard `delay` from now, see
+    /// [`StreamGroup::start_at`].
+    pub fn start_in(&self, delay: Duration) -> Result<(), ControlError> {
+        self.start_at(Instant::now() + delay)
+    }
+}
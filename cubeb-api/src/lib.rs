@@ -22,19 +22,50 @@
 
 extern crate cubeb_core;
 
+mod align;
+mod clock;
 mod context;
+mod control;
 mod frame;
+mod gain;
+mod glitch;
+mod group;
+mod meter;
+mod mixer;
+mod processing;
+mod record;
+mod remap;
+mod resampler;
+mod ring;
 mod sample;
+pub mod signal;
+mod stats;
 mod stream;
+mod tap;
+pub mod tools;
+#[cfg(feature = "voice-processing")]
+mod voice;
 
+pub use crate::clock::*;
 pub use crate::context::*;
+pub use crate::control::*;
 // Re-export cubeb_core types
 pub use crate::frame::*;
+pub use crate::glitch::*;
+pub use crate::group::*;
+pub use crate::meter::*;
+pub use crate::mixer::*;
+pub use crate::processing::*;
+pub use crate::record::*;
 pub use crate::sample::*;
+pub use crate::stats::*;
 pub use crate::stream::*;
+pub use crate::tap::*;
+#[cfg(feature = "voice-processing")]
+pub use crate::voice::*;
 pub use cubeb_core::{
     ffi, ChannelLayout, Context, ContextRef, Device, DeviceCollection, DeviceCollectionRef,
     DeviceFormat, DeviceId, DeviceInfo, DeviceInfoRef, DeviceRef, DeviceState, DeviceType, Error,
-    LogLevel, Result, SampleFormat, State, StreamParams, StreamParamsBuilder, StreamParamsRef,
-    StreamPrefs, StreamRef,
+    InputProcessingParams, LogLevel, Result, SampleFormat, State, StreamParams,
+    StreamParamsBuilder, StreamParamsRef, StreamPrefs, StreamRef,
 };
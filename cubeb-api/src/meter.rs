@@ -0,0 +1,296 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Peak, RMS and loudness meters computed by the data callback trampoline
+
+use crate::{Frame, Sample};
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Integration time of the RMS levels, in seconds.
+const RMS_TIME: f32 = 0.3;
+// Loudness is measured over blocks of 100ms, see ITU-R BS.1770.
+const BLOCKS_PER_SECOND: u32 = 10;
+// Blocks in the momentary (400ms) and short-term (3s) windows.
+const MOMENTARY_BLOCKS: usize = 4;
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// Levels of one channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelLevel {
+    /// Largest absolute sample value since the previous
+    /// [`Stream::meters`](crate::Stream::meters) call, from 0 to 1.
+    pub peak: f32,
+    /// Root mean square level, integrated over 300ms, from 0 to 1.
+    pub rms: f32,
+}
+
+/// Levels of the input or output of a stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeterReading {
+    /// Levels of each channel, in stream order.
+    pub channels: Vec<ChannelLevel>,
+    /// EBU R128 momentary loudness over the last 400ms, in LUFS.
+    /// Negative infinity for silence.
+    pub momentary: f32,
+    /// EBU R128 short-term loudness over the last 3s, in LUFS. Negative
+    /// infinity for silence.
+    pub short_term: f32,
+}
+
+/// Levels of a stream, see [`Stream::meters`](crate::Stream::meters).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Meters {
+    /// Levels of the input passed to the data callback, `None` for
+    /// output-only streams.
+    pub input: Option<MeterReading>,
+    /// Levels of the output, after the stream volume, `None` for
+    /// input-only streams.
+    pub output: Option<MeterReading>,
+}
+
+/// Levels of one direction published by the trampoline.
+pub(crate) struct MeterLevels {
+    // f32 bits of the peak and RMS level of each channel.
+    peaks: Box<[AtomicU32]>,
+    rms: Box<[AtomicU32]>,
+    momentary: AtomicU32,
+    short_term: AtomicU32,
+}
+
+impl MeterLevels {
+    pub(crate) fn new(channels: usize) -> MeterLevels {
+        let zeros = || (0..channels).map(|_| AtomicU32::new(0)).collect();
+        MeterLevels {
+            peaks: zeros(),
+            rms: zeros(),
+            momentary: AtomicU32::new(f32::NEG_INFINITY.to_bits()),
+            short_term: AtomicU32::new(f32::NEG_INFINITY.to_bits()),
+        }
+    }
+
+    /// Read the levels, resetting the peaks.
+    pub(crate) fn read(&self) -> MeterReading {
+        let load = |bits: &AtomicU32| f32::from_bits(bits.load(Ordering::Relaxed));
+        MeterReading {
+            channels: self
+                .peaks
+                .iter()
+                .zip(self.rms.iter())
+                .map(|(peak, rms)| ChannelLevel {
+                    peak: f32::from_bits(peak.swap(0, Ordering::Relaxed)),
+                    rms: load(rms),
+                })
+                .collect(),
+            momentary: load(&self.momentary),
+            short_term: load(&self.short_term),
+        }
+    }
+}
+
+/// Second order IIR filter.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    // Transposed direct form II state.
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// K-weighting filter of ITU-R BS.1770 at any rate: a high shelf
+/// modelling the head followed by a high-pass filter.
+#[derive(Clone, Copy)]
+struct KWeighting([Biquad; 2]);
+
+impl KWeighting {
+    fn new(rate: u32) -> KWeighting {
+        let rate = f64::from(rate.max(1));
+        let shelf = {
+            let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+            let k = (PI * f0 / rate).tan();
+            let vh = 10f64.powf(gain / 20.0);
+            let vb = vh.powf(0.4996667741545416);
+            let a0 = 1.0 + k / q + k * k;
+            Biquad {
+                b: [
+                    (vh + vb * k / q + k * k) / a0,
+                    2.0 * (k * k - vh) / a0,
+                    (vh - vb * k / q + k * k) / a0,
+                ],
+                a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+                state: [0.0; 2],
+            }
+        };
+        let high_pass = {
+            let (f0, q) = (38.13547087602444, 0.5003270373238773);
+            let k = (PI * f0 / rate).tan();
+            let a0 = 1.0 + k / q + k * k;
+            Biquad {
+                b: [1.0, -2.0, 1.0],
+                a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+                state: [0.0; 2],
+            }
+        };
+        KWeighting([shelf, high_pass])
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let [shelf, high_pass] = &mut self.0;
+        high_pass.process(shelf.process(x))
+    }
+}
+
+/// Meter state of one direction, owned by the data callback trampoline.
+pub(crate) struct Meter {
+    filters: Vec<KWeighting>,
+    // Integrated mean square of each channel.
+    mean_squares: Vec<f32>,
+    // Peak of each channel in the current buffer.
+    peaks: Vec<f32>,
+    rms_coeff: f32,
+    block_frames: usize,
+    // Frames and sum of the K-weighted mean squares of the current block.
+    block_position: usize,
+    block_sum: f64,
+    // Mean squares of the last blocks, most recent at `block_index - 1`.
+    blocks: [f64; SHORT_TERM_BLOCKS],
+    block_index: usize,
+}
+
+impl Meter {
+    pub(crate) fn new(channels: usize, rate: u32) -> Meter {
+        Meter {
+            filters: vec![KWeighting::new(rate); channels],
+            mean_squares: vec![0.0; channels],
+            peaks: vec![0.0; channels],
+            rms_coeff: 1.0 - (-1.0 / (RMS_TIME * rate.max(1) as f32)).exp(),
+            block_frames: (rate / BLOCKS_PER_SECOND).max(1) as usize,
+            block_position: 0,
+            block_sum: 0.0,
+            blocks: [0.0; SHORT_TERM_BLOCKS],
+            block_index: 0,
+        }
+    }
+
+    pub(crate) fn process<F: Frame>(&mut self, frames: &[F], levels: &MeterLevels) {
+        self.peaks.fill(0.0);
+        for frame in frames {
+            for (c, sample) in frame.channels().iter().enumerate().take(self.filters.len()) {
+                let x = sample.to_float();
+                self.peaks[c] = self.peaks[c].max(x.abs());
+                self.mean_squares[c] += (x * x - self.mean_squares[c]) * self.rms_coeff;
+                let weighted = self.filters[c].process(f64::from(x));
+                self.block_sum += weighted * weighted;
+            }
+            self.block_position += 1;
+            if self.block_position == self.block_frames {
+                self.finish_block(levels);
+            }
+        }
+
+        for (c, level) in levels.peaks.iter().enumerate() {
+            // Non-negative floats order like their bits.
+            let peak = self.peaks.get(c).copied().unwrap_or(0.0);
+            level.fetch_max(peak.to_bits(), Ordering::Relaxed);
+            let rms = self.mean_squares.get(c).map_or(0.0, |ms| ms.sqrt());
+            levels.rms[c].store(rms.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    fn finish_block(&mut self, levels: &MeterLevels) {
+        self.blocks[self.block_index] = self.block_sum / self.block_frames as f64;
+        self.block_index = (self.block_index + 1) % SHORT_TERM_BLOCKS;
+        self.block_position = 0;
+        self.block_sum = 0.0;
+
+        let loudness = |blocks: usize| {
+            let sum: f64 = (1..=blocks)
+                .map(|i| {
+                    self.blocks[(self.block_index + SHORT_TERM_BLOCKS - i) % SHORT_TERM_BLOCKS]
+                })
+                .sum();
+            (-0.691 + 10.0 * (sum / blocks as f64).log10()) as f32
+        };
+        levels
+            .momentary
+            .store(loudness(MOMENTARY_BLOCKS).to_bits(), Ordering::Relaxed);
+        levels
+            .short_term
+            .store(loudness(SHORT_TERM_BLOCKS).to_bits(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::WideFrame;
+    use crate::StereoFrame;
+
+    fn sine(frequency: f32, frames: usize) -> Vec<StereoFrame<f32>> {
+        (0..frames)
+            .map(|i| StereoFrame {
+                l: (2.0 * std::f32::consts::PI * frequency * i as f32 / 48_000.0).sin(),
+                r: 0.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_sine_on_one_channel() {
+        let levels = MeterLevels::new(2);
+        let mut meter = Meter::new(2, 48_000);
+        // 3s of a 1kHz 0dBFS sine on the left channel only.
+        for chunk in sine(1000.0, 144_000).chunks(480) {
+            meter.process(chunk, &levels);
+        }
+
+        let reading = levels.read();
+        assert!(
+            (reading.momentary + 3.01).abs() < 0.05,
+            "{}",
+            reading.momentary
+        );
+        assert!(
+            (reading.short_term + 3.01).abs() < 0.05,
+            "{}",
+            reading.short_term
+        );
+        assert!((reading.channels[0].peak - 1.0).abs() < 1e-3);
+        assert!((reading.channels[0].rms - 0.5f32.sqrt()).abs() < 0.01);
+        assert_eq!(reading.channels[1].peak, 0.0);
+        assert_eq!(reading.channels[1].rms, 0.0);
+        // Peaks are reset when read.
+        assert_eq!(levels.read().channels[0].peak, 0.0);
+    }
+
+    #[test]
+    fn silence() {
+        let levels = MeterLevels::new(2);
+        let mut meter = Meter::new(2, 48_000);
+        meter.process(&[StereoFrame { l: 0.0, r: 0.0 }; 9600], &levels);
+        let reading = levels.read();
+        assert_eq!(reading.momentary, f32::NEG_INFINITY);
+        assert_eq!(reading.short_term, f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn many_channels() {
+        let levels = MeterLevels::new(40);
+        let mut meter = Meter::new(40, 48_000);
+        let mut frame = WideFrame([0.0; 40]);
+        frame.0[39] = -0.5;
+        meter.process(&[frame], &levels);
+        assert_eq!(levels.read().channels[39].peak, 0.5);
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Mixing several sources into one output stream
+
+use crate::ring::{self, Consumer, Producer};
+use crate::{ContextRef, Error, Frame, Result, Sample, Stream, StreamBuilder};
+use std::f32::consts::FRAC_PI_2;
+use std::ops;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Number of frames mixed at a time.
+const CHUNK_FRAMES: usize = 256;
+/// Number of commands that can be queued between two data callbacks.
+const COMMAND_CAPACITY: usize = 256;
+
+/// User supplied source callback.
+///
+/// Fills the buffer with frames and returns the number of frames written.
+/// Returning fewer frames than the length of the buffer ends the source.
+/// It's called on the audio thread, so it must not block.
+pub type SourceCallback<F> = dyn FnMut(&mut [F]) -> usize + Send + Sync + 'static;
+
+/// Identifies a source added to a [`MixerStream`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SourceId(u64);
+
+enum SourceKind<F> {
+    Callback(Box<SourceCallback<F>>),
+    Buffer { frames: Arc<[F]>, position: usize },
+}
+
+/// A source of frames for a [`MixerStream`].
+///
+/// # Example
+/// ```
+/// use cubeb::{Source, StereoFrame};
+/// use std::sync::Arc;
+///
+/// let clip: Arc<[StereoFrame<f32>]> = vec![StereoFrame { l: 0.5, r: 0.5 }; 4800].into();
+/// let source = Source::from_buffer(clip).gain(0.5).pan(-1.0).looping(true);
+/// # drop(source);
+/// ```
+pub struct Source<F> {
+    kind: SourceKind<F>,
+    gain: f32,
+    pan: f32,
+    looping: bool,
+}
+
+impl<F> Source<F> {
+    /// A source producing frames from `cb`, see [`SourceCallback`].
+    pub fn from_fn<C>(cb: C) -> Source<F>
+    where
+        C: FnMut(&mut [F]) -> usize + Send + Sync + 'static,
+    {
+        Source::new(SourceKind::Callback(Box::new(cb)))
+    }
+
+    /// A source playing `frames`. The buffer can be shared by several
+    /// sources.
+    pub fn from_buffer<B: Into<Arc<[F]>>>(frames: B) -> Source<F> {
+        Source::new(SourceKind::Buffer {
+            frames: frames.into(),
+            position: 0,
+        })
+    }
+
+    fn new(kind: SourceKind<F>) -> Source<F> {
+        Source {
+            kind,
+            gain: 1.0,
+            pan: 0.0,
+            looping: false,
+        }
+    }
+
+    /// Linear gain of the source. Defaults to `1.0`.
+    pub fn gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Balance of a stereo source, from `-1.0` (left only) to `1.0` (right
+    /// only). Defaults to `0.0`. Ignored unless the output is stereo.
+    pub fn pan(mut self, pan: f32) -> Self {
+        self.pan = pan;
+        self
+    }
+
+    /// Restart a buffer source from the beginning when it ends. Defaults to
+    /// `false`. Ignored for callback sources.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+}
+
+impl<F: Frame> Source<F> {
+    // Fill `frames`, returning the number of frames written.
+    fn render(&mut self, out: &mut [F]) -> usize {
+        match &mut self.kind {
+            SourceKind::Callback(cb) => cb(out).min(out.len()),
+            SourceKind::Buffer { frames, position } => {
+                let mut written = 0;
+                while written < out.len() {
+                    if *position == frames.len() {
+                        if !self.looping || frames.is_empty() {
+                            break;
+                        }
+                        *position = 0;
+                    }
+                    let n = (frames.len() - *position).min(out.len() - written);
+                    out[written..written + n].copy_from_slice(&frames[*position..*position + n]);
+                    written += n;
+                    *position += n;
+                }
+                written
+            }
+        }
+    }
+}
+
+fn check_gain(gain: f32) -> Result<()> {
+    if !gain.is_finite() || gain < 0.0 {
+        return Err(Error::InvalidParameter);
+    }
+    Ok(())
+}
+
+fn check_pan(pan: f32) -> Result<()> {
+    if !(-1.0..=1.0).contains(&pan) {
+        return Err(Error::InvalidParameter);
+    }
+    Ok(())
+}
+
+// Per channel gains of a stereo balance.
+fn pan_gains(pan: f32) -> [f32; 2] {
+    [
+        (pan.max(0.0) * FRAC_PI_2).cos(),
+        ((-pan).max(0.0) * FRAC_PI_2).cos(),
+    ]
+}
+
+/// A source being mixed, owned by the audio thread.
+struct Voice<F> {
+    id: SourceId,
+    source: Source<F>,
+    // Gain applied at the end of the last chunk, ramped towards
+    // `source.gain` over the next chunk.
+    gain: f32,
+}
+
+enum Command<F> {
+    Add(Voice<F>),
+    Remove(SourceId),
+    SetGain(SourceId, f32),
+    SetPan(SourceId, f32),
+}
+
+/// Mixing state owned by the data callback.
+struct Engine<F> {
+    voices: Vec<Voice<F>>,
+    commands: Consumer<Command<F>>,
+    // Voices that ended or were removed, dropped by the control thread.
+    garbage: Producer<Voice<F>>,
+    scratch: Vec<F>,
+    mix: Vec<f32>,
+}
+
+impl<F: Frame> Engine<F> {
+    fn new(
+        capacity: usize,
+        commands: Consumer<Command<F>>,
+        garbage: Producer<Voice<F>>,
+    ) -> Engine<F> {
+        Engine {
+            voices: Vec::with_capacity(capacity),
+            commands,
+            garbage,
+            scratch: vec![F::silence(); CHUNK_FRAMES],
+            mix: vec![0.0; CHUNK_FRAMES * F::CHANNELS],
+        }
+    }
+
+    fn process(&mut self, output: &mut [F]) {
+        self.apply_commands();
+        for chunk in output.chunks_mut(CHUNK_FRAMES) {
+            self.mix_chunk(chunk);
+        }
+    }
+
+    fn apply_commands(&mut self) {
+        while let Some(command) = self.commands.pop() {
+            match command {
+                // The control thread doesn't add more voices than the
+                // capacity, so this doesn't allocate.
+                Command::Add(voice) => self.voices.push(voice),
+                Command::Remove(id) => {
+                    if let Some(i) = self.voices.iter().position(|v| v.id == id) {
+                        self.retire(i);
+                    }
+                }
+                Command::SetGain(id, gain) => {
+                    if let Some(v) = self.voices.iter_mut().find(|v| v.id == id) {
+                        v.source.gain = gain;
+                    }
+                }
+                Command::SetPan(id, pan) => {
+                    if let Some(v) = self.voices.iter_mut().find(|v| v.id == id) {
+                        v.source.pan = pan;
+                    }
+                }
+            }
+        }
+    }
+
+    fn retire(&mut self, i: usize) {
+        let voice = self.voices.swap_remove(i);
+        // The garbage queue holds as many voices as can be added, so this
+        // only fails if a voice is retired twice, which can't happen.
+        let _ = self.garbage.push(voice);
+    }
+
+    fn mix_chunk(&mut self, out: &mut [F]) {
+        let frames = out.len();
+        let mix = &mut self.mix[..frames * F::CHANNELS];
+        mix.fill(0.0);
+
+        let mut i = 0;
+        while i < self.voices.len() {
+            let voice = &mut self.voices[i];
+            let scratch = &mut self.scratch[..frames];
+            let written = voice.source.render(scratch);
+
+            let pan = if F::CHANNELS == 2 {
+                pan_gains(voice.source.pan)
+            } else {
+                [1.0; 2]
+            };
+            let step = (voice.source.gain - voice.gain) / frames as f32;
+            let mut gain = voice.gain;
+            for (frame, mixed) in scratch[..written]
+                .iter()
+                .zip(mix.chunks_exact_mut(F::CHANNELS))
+            {
+                for (c, (sample, m)) in frame.channels().iter().zip(mixed).enumerate() {
+                    *m += sample.to_float() * gain * pan.get(c).copied().unwrap_or(1.0);
+                }
+                gain += step;
+            }
+            voice.gain = voice.source.gain;
+
+            if written < frames {
+                let _ = self.garbage.push(self.voices.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        for (frame, mixed) in out.iter_mut().zip(mix.chunks_exact(F::CHANNELS)) {
+            for (sample, m) in frame.channels_mut().iter_mut().zip(mixed) {
+                *sample = F::Sample::from_float(*m);
+            }
+        }
+    }
+}
+
+/// One output stream mixing any number of dynamically added [`Source`]s.
+///
+/// Sources are added, changed and removed from non-audio threads through a
+/// lock-free queue, so the data callback never blocks. Sources that end, or
+/// are removed, are handed back to be dropped off the audio thread.
+///
+/// `MixerStream` dereferences to the underlying [`Stream`] to start, stop
+/// and control it.
+///
+/// # Example
+/// ```no_run
+/// use cubeb::{MixerStream, Source, StereoFrame, StreamBuilder};
+///
+/// type Frame = StereoFrame<f32>;
+///
+/// let ctx = cubeb::init("Cubeb mixer example").unwrap();
+/// let params = cubeb::StreamParamsBuilder::new()
+///     .format(cubeb::SampleFormat::Float32NE)
+///     .rate(48_000)
+///     .channels(2)
+///     .layout(cubeb::ChannelLayout::STEREO)
+///     .take();
+/// let mut builder = StreamBuilder::<Frame>::new();
+/// builder
+///     .default_output(&params)
+///     .latency(0x1000)
+///     .state_callback(|state| println!("stream {:?}", state));
+/// let mixer = MixerStream::init(&ctx, builder, 32).unwrap();
+/// mixer.start().unwrap();
+///
+/// let beep: Vec<Frame> = (0..4800)
+///     .map(|i| {
+///         let x = (i as f32 * 440.0 / 48_000.0 * std::f32::consts::TAU).sin() * 0.2;
+///         StereoFrame { l: x, r: x }
+///     })
+///     .collect();
+/// let id = mixer.add(Source::from_buffer(beep).pan(0.5)).unwrap();
+/// mixer.set_gain(id, 0.5).unwrap();
+/// ```
+pub struct MixerStream<F> {
+    stream: Stream<F>,
+    commands: Mutex<Producer<Command<F>>>,
+    garbage: Mutex<Consumer<Voice<F>>>,
+    capacity: usize,
+    // Voices added and not yet handed back through `garbage`.
+    live: AtomicUsize,
+    next_id: AtomicU64,
+}
+
+impl<F: Frame + Sync + 'static> MixerStream<F> {
+    /// Build a mixer stream from `builder`, which must have an output and a
+    /// state callback. Its data callback is replaced by the mixer. Up to
+    /// `max_sources` sources can play at once.
+    pub fn init(
+        ctx: &ContextRef,
+        mut builder: StreamBuilder<'_, F>,
+        max_sources: usize,
+    ) -> Result<MixerStream<F>> {
+        let (commands, command_rx) = ring::channel(COMMAND_CAPACITY);
+        let (garbage_tx, garbage) = ring::channel(max_sources);
+        let mut engine = Engine::new(max_sources, command_rx, garbage_tx);
+        builder.data_callback(move |_, output| {
+            engine.process(output);
+            output.len() as isize
+        });
+        let stream = builder.init(ctx)?;
+        Ok(MixerStream {
+            stream,
+            commands: Mutex::new(commands),
+            garbage: Mutex::new(garbage),
+            capacity: max_sources,
+            live: AtomicUsize::new(0),
+            next_id: AtomicU64::new(0),
+        })
+    }
+}
+
+impl<F> MixerStream<F> {
+    /// Start mixing `source`. Fails with `Error::Error` if `max_sources`
+    /// sources are already playing.
+    pub fn add(&self, source: Source<F>) -> Result<SourceId> {
+        check_gain(source.gain)?;
+        check_pan(source.pan)?;
+        self.collect_garbage();
+        self.live
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |live| {
+                (live < self.capacity).then_some(live + 1)
+            })
+            .map_err(|_| Error::Error)?;
+        let id = SourceId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let gain = source.gain;
+        let voice = Voice { id, source, gain };
+        if self.send(Command::Add(voice)).is_err() {
+            self.live.fetch_sub(1, Ordering::AcqRel);
+            return Err(Error::Error);
+        }
+        Ok(id)
+    }
+
+    /// Stop mixing source `id`. Does nothing if it already ended.
+    pub fn remove(&self, id: SourceId) -> Result<()> {
+        self.send(Command::Remove(id))
+    }
+
+    /// Change the gain of source `id`, ramped over the next few
+    /// milliseconds.
+    pub fn set_gain(&self, id: SourceId, gain: f32) -> Result<()> {
+        check_gain(gain)?;
+        self.send(Command::SetGain(id, gain))
+    }
+
+    /// Change the balance of source `id`, see [`Source::pan`].
+    pub fn set_pan(&self, id: SourceId, pan: f32) -> Result<()> {
+        check_pan(pan)?;
+        self.send(Command::SetPan(id, pan))
+    }
+
+    /// Number of sources that haven't ended or been removed yet, as far as
+    /// the control thread knows.
+    pub fn active_sources(&self) -> usize {
+        self.collect_garbage();
+        self.live.load(Ordering::Acquire)
+    }
+
+    /// Drop the sources that ended or were removed. This is done
+    /// automatically when adding sources.
+    pub fn collect_garbage(&self) {
+        let mut garbage = self.garbage.lock().unwrap();
+        while garbage.pop().is_some() {
+            self.live.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    // Fails with `Error::Error` if the audio thread isn't keeping up with
+    // the commands.
+    fn send(&self, command: Command<F>) -> Result<()> {
+        self.commands
+            .lock()
+            .unwrap()
+            .push(command)
+            .map_err(|_| Error::Error)
+    }
+}
+
+impl<F> ops::Deref for MixerStream<F> {
+    type Target = Stream<F>;
+
+    fn deref(&self) -> &Stream<F> {
+        &self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonoFrame, StereoFrame};
+
+    type Frame = StereoFrame<f32>;
+
+    type Queues = (Producer<Command<Frame>>, Consumer<Voice<Frame>>);
+
+    fn engine(capacity: usize) -> (Engine<Frame>, Queues) {
+        let (commands, command_rx) = ring::channel(16);
+        let (garbage_tx, garbage) = ring::channel(capacity);
+        (
+            Engine::new(capacity, command_rx, garbage_tx),
+            (commands, garbage),
+        )
+    }
+
+    fn voice(id: u64, source: Source<Frame>) -> Command<Frame> {
+        let gain = source.gain;
+        Command::Add(Voice {
+            id: SourceId(id),
+            source,
+            gain,
+        })
+    }
+
+    fn constant(value: f32) -> Source<Frame> {
+        Source::from_fn(move |out: &mut [Frame]| {
+            out.fill(StereoFrame { l: value, r: value });
+            out.len()
+        })
+    }
+
+    #[test]
+    fn mixes_sources() {
+        let (mut engine, (mut commands, _garbage)) = engine(4);
+        commands.push(voice(0, constant(0.25))).ok().unwrap();
+        commands
+            .push(voice(1, constant(0.5).gain(0.5)))
+            .ok()
+            .unwrap();
+        let mut out = [Frame { l: 1.0, r: 1.0 }; 4];
+        engine.process(&mut out);
+        assert_eq!(out, [Frame { l: 0.5, r: 0.5 }; 4]);
+    }
+
+    #[test]
+    fn buffer_sources_end_or_loop() {
+        let (mut engine, (mut commands, mut garbage)) = engine(4);
+        let clip: Arc<[Frame]> = vec![Frame { l: 1.0, r: 1.0 }; 3].into();
+        commands
+            .push(voice(0, Source::from_buffer(clip.clone())))
+            .ok()
+            .unwrap();
+        commands
+            .push(voice(1, Source::from_buffer(clip).looping(true)))
+            .ok()
+            .unwrap();
+        let mut out = [Frame { l: 0.0, r: 0.0 }; 4];
+        engine.process(&mut out);
+        assert_eq!(out.map(|f| f.l), [2.0, 2.0, 2.0, 1.0]);
+        // The one-shot source ended and is handed back.
+        assert_eq!(garbage.pop().map(|v| v.id), Some(SourceId(0)));
+        engine.process(&mut out);
+        assert_eq!(out.map(|f| f.l), [1.0; 4]);
+        assert!(garbage.pop().is_none());
+    }
+
+    #[test]
+    fn gain_ramps_and_pan() {
+        let (mut engine, (mut commands, mut garbage)) = engine(4);
+        commands
+            .push(voice(0, constant(1.0).pan(1.0)))
+            .ok()
+            .unwrap();
+        let mut out = [Frame { l: 0.0, r: 0.0 }; 4];
+        engine.process(&mut out);
+        assert!(out.iter().all(|f| f.l.abs() < 1e-6 && f.r == 1.0));
+
+        commands
+            .push(Command::SetGain(SourceId(0), 0.0))
+            .ok()
+            .unwrap();
+        commands
+            .push(Command::SetPan(SourceId(0), 0.0))
+            .ok()
+            .unwrap();
+        engine.process(&mut out);
+        assert_eq!(out.map(|f| f.l), [1.0, 0.75, 0.5, 0.25]);
+
+        commands.push(Command::Remove(SourceId(0))).ok().unwrap();
+        engine.process(&mut out);
+        assert_eq!(out, [Frame { l: 0.0, r: 0.0 }; 4]);
+        assert!(garbage.pop().is_some());
+    }
+
+    #[test]
+    fn mono_ignores_pan() {
+        let (commands_tx, command_rx) = ring::channel(4);
+        let (garbage_tx, _garbage) = ring::channel(1);
+        let mut commands = commands_tx;
+        let mut engine = Engine::<MonoFrame<f32>>::new(1, command_rx, garbage_tx);
+        let source = Source::from_buffer(vec![MonoFrame { m: 1.0f32 }; 2]).pan(1.0);
+        commands
+            .push(Command::Add(Voice {
+                id: SourceId(0),
+                source,
+                gain: 1.0,
+            }))
+            .ok()
+            .unwrap();
+        let mut out = [MonoFrame { m: 0.0 }; 2];
+        engine.process(&mut out);
+        assert_eq!(out, [MonoFrame { m: 1.0 }; 2]);
+    }
+}
@@ -0,0 +1,399 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Input processing negotiation and software fallbacks
+
+use crate::{Error, Frame, InputProcessingParams, Result, Sample};
+
+/// Effects [`ProcessingPolicy::SoftwareFallback`] can provide in software.
+pub const SOFTWARE_INPUT_PROCESSING: InputProcessingParams =
+    InputProcessingParams::from_bits_truncate(
+        InputProcessingParams::AUTOMATIC_GAIN_CONTROL.bits()
+            | InputProcessingParams::NOISE_SUPPRESSION.bits(),
+    );
+
+// Level the automatic gain control aims for, -20 dBFS.
+const AGC_TARGET: f32 = 0.1;
+// Largest gain applied by the automatic gain control, +30 dB.
+const AGC_MAX_GAIN: f32 = 31.6;
+// Level below which the automatic gain control stops adapting, -60 dBFS.
+const AGC_SILENCE: f32 = 0.001;
+// Time constants of the level estimate and of gain changes, in seconds.
+const AGC_LEVEL_TIME: f32 = 0.5;
+const AGC_GAIN_TIME: f32 = 0.1;
+// Level below which the noise gate closes, -50 dBFS.
+const GATE_THRESHOLD: f32 = 0.003;
+// Time constants of the envelope release and of the gate opening and
+// closing, in seconds.
+const GATE_RELEASE_TIME: f32 = 0.05;
+const GATE_OPEN_TIME: f32 = 0.001;
+const GATE_CLOSE_TIME: f32 = 0.05;
+
+/// Processing applied to the input of a stream before the data callback,
+/// see [`StreamBuilder::input_processor`](crate::StreamBuilder::input_processor).
+///
+/// Processors work on a mono mix of the input in the range [-1, 1], which
+/// is then written back to every channel. Like the data callback, they run
+/// on the audio thread and shouldn't block or allocate.
+pub trait VoiceProcessor: Send {
+    /// Process `input` in place. `reference` holds the output that was
+    /// playing while each input sample was captured, for echo cancellation,
+    /// and is empty for streams without an output.
+    fn process(&mut self, input: &mut [f32], reference: &[f32]);
+}
+
+/// How [`StreamBuilder::input_processing`](crate::StreamBuilder::input_processing)
+/// handles requested effects that the backend doesn't support.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessingPolicy {
+    /// Fail with [`Error::NotSupported`] unless every requested effect is
+    /// supported by the backend.
+    Strict,
+    /// Enable the requested effects supported by the backend and drop the
+    /// others.
+    BestEffort,
+    /// Enable the requested effects supported by the backend, and emulate
+    /// the others in software when possible, see
+    /// [`SOFTWARE_INPUT_PROCESSING`]. Automatic gain control is emulated
+    /// with a slow gain rider, and noise suppression with a noise gate.
+    SoftwareFallback,
+}
+
+/// Input processing effects enabled for a stream, see
+/// [`Stream::input_processing`](crate::Stream::input_processing).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputProcessingReport {
+    /// Effects requested with
+    /// [`StreamBuilder::input_processing`](crate::StreamBuilder::input_processing).
+    pub requested: InputProcessingParams,
+    /// Effects enabled in the backend.
+    pub native: InputProcessingParams,
+    /// Effects emulated in software by the data callback trampoline.
+    pub software: InputProcessingParams,
+}
+
+impl InputProcessingReport {
+    /// Requested effects which aren't enabled at all.
+    pub fn unavailable(&self) -> InputProcessingParams {
+        self.requested - self.native - self.software
+    }
+}
+
+/// Decide which of the `requested` effects to enable natively and in
+/// software, given the effects `supported` by the backend.
+pub(crate) fn negotiate(
+    requested: InputProcessingParams,
+    supported: InputProcessingParams,
+    policy: ProcessingPolicy,
+) -> Result<InputProcessingReport> {
+    let native = requested & supported;
+    let missing = requested - native;
+    let software = match policy {
+        ProcessingPolicy::Strict if !missing.is_empty() => return Err(Error::NotSupported),
+        ProcessingPolicy::SoftwareFallback => missing & SOFTWARE_INPUT_PROCESSING,
+        _ => InputProcessingParams::NONE,
+    };
+    Ok(InputProcessingReport {
+        requested,
+        native,
+        software,
+    })
+}
+
+// Coefficient of a one-pole smoother with time constant `time`.
+fn coefficient(time: f32, rate: u32) -> f32 {
+    1.0 - (-1.0 / (time * rate.max(1) as f32)).exp()
+}
+
+/// Slowly rides the gain to bring the input level to `AGC_TARGET`.
+pub(crate) struct Agc {
+    // Smoothed mean square level.
+    power: f32,
+    gain: f32,
+    level_coeff: f32,
+    gain_coeff: f32,
+}
+
+impl Agc {
+    pub(crate) fn new(rate: u32) -> Agc {
+        Agc {
+            power: AGC_TARGET * AGC_TARGET,
+            gain: 1.0,
+            level_coeff: coefficient(AGC_LEVEL_TIME, rate),
+            gain_coeff: coefficient(AGC_GAIN_TIME, rate),
+        }
+    }
+
+    // Process the samples of one frame.
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        let power = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+        // Don't bring up silence between words.
+        if power > AGC_SILENCE * AGC_SILENCE {
+            self.power += (power - self.power) * self.level_coeff;
+        }
+        let target = (AGC_TARGET / self.power.sqrt()).min(AGC_MAX_GAIN);
+        self.gain += (target - self.gain) * self.gain_coeff;
+        for sample in samples {
+            *sample = (*sample * self.gain).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// Silences the input while its level stays below `GATE_THRESHOLD`.
+pub(crate) struct NoiseGate {
+    envelope: f32,
+    gain: f32,
+    release_coeff: f32,
+    open_coeff: f32,
+    close_coeff: f32,
+}
+
+impl NoiseGate {
+    pub(crate) fn new(rate: u32) -> NoiseGate {
+        NoiseGate {
+            envelope: 0.0,
+            gain: 0.0,
+            release_coeff: coefficient(GATE_RELEASE_TIME, rate),
+            open_coeff: coefficient(GATE_OPEN_TIME, rate),
+            close_coeff: coefficient(GATE_CLOSE_TIME, rate),
+        }
+    }
+
+    // Process the samples of one frame.
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if peak > self.envelope {
+            self.envelope = peak;
+        } else {
+            self.envelope += (peak - self.envelope) * self.release_coeff;
+        }
+        let (target, coeff) = if self.envelope > GATE_THRESHOLD {
+            (1.0, self.open_coeff)
+        } else {
+            (0.0, self.close_coeff)
+        };
+        self.gain += (target - self.gain) * coeff;
+        for sample in samples {
+            *sample *= self.gain;
+        }
+    }
+}
+
+/// Input processing applied before the data callback, see
+/// [`ProcessingPolicy::SoftwareFallback`] and
+/// [`StreamBuilder::input_processor`](crate::StreamBuilder::input_processor).
+pub(crate) struct InputProcessor<F> {
+    voice: Option<Box<dyn VoiceProcessor>>,
+    agc: Option<Agc>,
+    gate: Option<NoiseGate>,
+    frames: Vec<F>,
+    // Mono mixes of the input and reference for `voice`.
+    mono: Vec<f32>,
+    mono_reference: Vec<f32>,
+}
+
+impl<F> InputProcessor<F> {
+    /// The processor passed to [`InputProcessor::new`].
+    pub(crate) fn into_voice(self) -> Option<Box<dyn VoiceProcessor>> {
+        self.voice
+    }
+}
+
+impl<F: Frame> InputProcessor<F> {
+    /// Create a processor running `voice` then the `software` effects at
+    /// `rate`, preallocating buffers for `frames` frames. `None` if there's
+    /// nothing to do.
+    pub(crate) fn new(
+        voice: Option<Box<dyn VoiceProcessor>>,
+        software: InputProcessingParams,
+        rate: u32,
+        frames: usize,
+    ) -> Option<InputProcessor<F>> {
+        if voice.is_none() && software.is_empty() {
+            return None;
+        }
+        let mono = if voice.is_some() { frames } else { 0 };
+        Some(InputProcessor {
+            voice,
+            agc: software
+                .contains(InputProcessingParams::AUTOMATIC_GAIN_CONTROL)
+                .then(|| Agc::new(rate)),
+            gate: software
+                .contains(InputProcessingParams::NOISE_SUPPRESSION)
+                .then(|| NoiseGate::new(rate)),
+            frames: vec![F::silence(); frames],
+            mono: vec![0.0; mono],
+            mono_reference: vec![0.0; mono],
+        })
+    }
+
+    /// Processed copy of `input`. `reference` is the output played while
+    /// it was captured, or empty.
+    pub(crate) fn process(&mut self, input: &[F], reference: &[F]) -> &[F] {
+        if self.frames.len() < input.len() {
+            // Only allocates if the backend asks for more frames than the
+            // latency the stream was created with.
+            self.frames.resize(input.len(), F::silence());
+        }
+        let frames = &mut self.frames[..input.len()];
+        frames.copy_from_slice(input);
+
+        if let Some(voice) = &mut self.voice {
+            let len = input.len().max(reference.len());
+            if self.mono.len() < len {
+                self.mono.resize(len, 0.0);
+                self.mono_reference.resize(len, 0.0);
+            }
+            let mono = &mut self.mono[..input.len()];
+            let mono_reference = &mut self.mono_reference[..reference.len()];
+            downmix(frames, mono);
+            downmix(reference, mono_reference);
+            voice.process(mono, mono_reference);
+            for (frame, sample) in frames.iter_mut().zip(mono.iter()) {
+                frame.channels_mut().fill(F::Sample::from_float(*sample));
+            }
+        }
+
+        if self.gate.is_none() && self.agc.is_none() {
+            return frames;
+        }
+        let mut samples = [0.0f32; 32];
+        let channels = F::CHANNELS.min(samples.len());
+        for frame in frames.iter_mut() {
+            let samples = &mut samples[..channels];
+            for (sample, channel) in samples.iter_mut().zip(frame.channels()) {
+                *sample = channel.to_float();
+            }
+            if let Some(gate) = &mut self.gate {
+                gate.process(samples);
+            }
+            if let Some(agc) = &mut self.agc {
+                agc.process(samples);
+            }
+            for (channel, sample) in frame.channels_mut().iter_mut().zip(samples.iter()) {
+                *channel = F::Sample::from_float(*sample);
+            }
+        }
+        frames
+    }
+}
+
+// Average the channels of `frames` into `mono`.
+fn downmix<F: Frame>(frames: &[F], mono: &mut [f32]) {
+    for (frame, sample) in frames.iter().zip(mono.iter_mut()) {
+        let channels = frame.channels();
+        let sum: f32 = channels.iter().map(|channel| channel.to_float()).sum();
+        *sample = sum / channels.len().max(1) as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonoFrame, StereoFrame};
+    use std::f32::consts::PI;
+
+    const AEC: InputProcessingParams = InputProcessingParams::ECHO_CANCELLATION;
+    const AGC: InputProcessingParams = InputProcessingParams::AUTOMATIC_GAIN_CONTROL;
+    const NS: InputProcessingParams = InputProcessingParams::NOISE_SUPPRESSION;
+
+    fn sine(level: f32, frames: usize) -> Vec<MonoFrame<f32>> {
+        (0..frames)
+            .map(|i| MonoFrame {
+                m: level * (2.0 * PI * 440.0 * i as f32 / 48_000.0).sin(),
+            })
+            .collect()
+    }
+
+    fn peak(frames: &[MonoFrame<f32>]) -> f32 {
+        frames.iter().fold(0.0, |peak, f| peak.max(f.m.abs()))
+    }
+
+    #[test]
+    fn negotiate_policies() {
+        let requested = AEC | AGC | NS;
+        assert_eq!(
+            negotiate(requested, AEC, ProcessingPolicy::Strict),
+            Err(Error::NotSupported)
+        );
+        let report = negotiate(AEC, AEC | AGC, ProcessingPolicy::Strict).unwrap();
+        assert_eq!(report.native, AEC);
+
+        let report = negotiate(requested, AEC, ProcessingPolicy::BestEffort).unwrap();
+        assert_eq!(report.native, AEC);
+        assert_eq!(report.software, InputProcessingParams::NONE);
+        assert_eq!(report.unavailable(), AGC | NS);
+
+        let report = negotiate(requested, AGC, ProcessingPolicy::SoftwareFallback).unwrap();
+        assert_eq!(report.native, AGC);
+        assert_eq!(report.software, NS);
+        assert_eq!(report.unavailable(), AEC);
+    }
+
+    #[test]
+    fn agc_brings_up_quiet_input() {
+        let mut processor = InputProcessor::new(None, AGC, 48_000, 480).unwrap();
+        let input = sine(0.01, 480);
+        let mut output = Vec::new();
+        // Ten seconds of a -40 dBFS tone.
+        for _ in 0..1000 {
+            output = processor.process(&input, &[]).to_vec();
+        }
+        let level = peak(&output) / 2f32.sqrt();
+        assert!((level - AGC_TARGET).abs() < 0.01, "{}", level);
+    }
+
+    #[test]
+    fn gate_silences_noise_floor() {
+        let mut processor = InputProcessor::new(None, NS, 48_000, 480).unwrap();
+        let noise = sine(0.001, 480);
+        for _ in 0..50 {
+            processor.process(&noise, &[]);
+        }
+        assert!(peak(processor.process(&noise, &[])) < 0.0001);
+
+        let speech = sine(0.5, 480);
+        processor.process(&speech, &[]);
+        assert!(peak(processor.process(&speech, &[])) > 0.45);
+        assert!(InputProcessor::<MonoFrame<f32>>::new(
+            None,
+            InputProcessingParams::NONE,
+            48_000,
+            480
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn voice_processor_gets_mono_mix_and_reference() {
+        struct SubtractReference;
+        impl VoiceProcessor for SubtractReference {
+            fn process(&mut self, input: &mut [f32], reference: &[f32]) {
+                for (sample, reference) in input.iter_mut().zip(reference) {
+                    *sample -= reference;
+                }
+            }
+        }
+        let voice = Box::new(SubtractReference);
+        let mut processor =
+            InputProcessor::new(Some(voice), InputProcessingParams::NONE, 48_000, 2).unwrap();
+        let input = [
+            StereoFrame { l: 0.5, r: 0.3 },
+            StereoFrame { l: 0.0, r: 1.0 },
+        ];
+        let reference = [
+            StereoFrame { l: 0.2, r: 0.2 },
+            StereoFrame { l: 0.0, r: 0.0 },
+        ];
+        let output = processor.process(&input, &reference);
+        assert_eq!(
+            output,
+            [
+                StereoFrame { l: 0.2, r: 0.2 },
+                StereoFrame { l: 0.5, r: 0.5 }
+            ]
+        );
+    }
+}
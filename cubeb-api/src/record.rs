@@ -0,0 +1,347 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Encoding of recorded stream audio to files
+
+use crate::tap::Taps;
+use crate::{Direction, Error, Frame, Result, Sample, TapId};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// File format of [`Stream::start_recording`](crate::Stream::start_recording).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// WAV file with 16-bit signed integer samples.
+    WavPcm16,
+    /// WAV file with 32-bit float samples.
+    WavFloat32,
+    /// Headerless interleaved 16-bit little-endian signed integer samples.
+    RawPcm16,
+    /// Headerless interleaved 32-bit little-endian float samples.
+    RawFloat32,
+    /// Core Audio Format file with 16-bit signed integer samples.
+    CafPcm16,
+    /// Core Audio Format file with 32-bit float samples.
+    CafFloat32,
+}
+
+impl RecordFormat {
+    fn is_float(self) -> bool {
+        matches!(
+            self,
+            RecordFormat::WavFloat32 | RecordFormat::RawFloat32 | RecordFormat::CafFloat32
+        )
+    }
+
+    fn sample_bytes(self) -> u32 {
+        if self.is_float() {
+            4
+        } else {
+            2
+        }
+    }
+}
+
+// Sizes of the headers preceding the samples.
+const WAV_HEADER: u64 = 44;
+const CAF_HEADER: u64 = 68;
+
+/// Writes frames to `W` in a [`RecordFormat`], finalizing the header
+/// sizes in [`Recorder::finish`].
+pub(crate) struct Recorder<W> {
+    writer: W,
+    format: RecordFormat,
+    channels: u32,
+    rate: u32,
+    data_bytes: u64,
+    // Sample bytes of the frames being written.
+    buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> Recorder<W> {
+    pub(crate) fn new(
+        writer: W,
+        format: RecordFormat,
+        channels: u32,
+        rate: u32,
+    ) -> io::Result<Recorder<W>> {
+        let mut recorder = Recorder {
+            writer,
+            format,
+            channels,
+            rate,
+            data_bytes: 0,
+            buffer: Vec::new(),
+        };
+        recorder.write_header()?;
+        Ok(recorder)
+    }
+
+    pub(crate) fn write<F: Frame>(&mut self, frames: &[F]) -> io::Result<()> {
+        self.buffer.clear();
+        for frame in frames {
+            for sample in frame.channels() {
+                let x = sample.to_float();
+                if self.format.is_float() {
+                    self.buffer.extend_from_slice(&x.to_le_bytes());
+                } else {
+                    let x = (x.clamp(-1.0, 1.0) * f32::from(i16::MAX)).round() as i16;
+                    self.buffer.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+        }
+        self.writer.write_all(&self.buffer)?;
+        self.data_bytes += self.buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Rewrite the header with the final sizes and flush, returning the
+    /// writer.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.format {
+            RecordFormat::WavPcm16 | RecordFormat::WavFloat32 => self.write_wav_header(),
+            RecordFormat::CafPcm16 | RecordFormat::CafFloat32 => self.write_caf_header(),
+            RecordFormat::RawPcm16 | RecordFormat::RawFloat32 => Ok(()),
+        }
+    }
+
+    fn write_wav_header(&mut self) -> io::Result<()> {
+        // Sizes saturate for files over 4GB, which WAV can't describe.
+        let data = u32::try_from(self.data_bytes).unwrap_or(u32::MAX);
+        let riff = data.saturating_add((WAV_HEADER - 8) as u32);
+        let sample_bytes = self.format.sample_bytes();
+        let block_align = self.channels * sample_bytes;
+        // WAVE_FORMAT_IEEE_FLOAT or WAVE_FORMAT_PCM.
+        let tag: u16 = if self.format.is_float() { 3 } else { 1 };
+
+        let mut header = Vec::with_capacity(WAV_HEADER as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&riff.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&tag.to_le_bytes());
+        header.extend_from_slice(&(self.channels as u16).to_le_bytes());
+        header.extend_from_slice(&self.rate.to_le_bytes());
+        header.extend_from_slice(&(self.rate * block_align).to_le_bytes());
+        header.extend_from_slice(&(block_align as u16).to_le_bytes());
+        header.extend_from_slice(&(sample_bytes as u16 * 8).to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data.to_le_bytes());
+        self.writer.write_all(&header)
+    }
+
+    fn write_caf_header(&mut self) -> io::Result<()> {
+        let sample_bytes = self.format.sample_bytes();
+        // kCAFLinearPCMFormatFlagIsLittleEndian, and IsFloat.
+        let flags: u32 = if self.format.is_float() { 3 } else { 2 };
+
+        let mut header = Vec::with_capacity(CAF_HEADER as usize);
+        header.extend_from_slice(b"caff");
+        header.extend_from_slice(&1u16.to_be_bytes());
+        header.extend_from_slice(&0u16.to_be_bytes());
+        header.extend_from_slice(b"desc");
+        header.extend_from_slice(&32i64.to_be_bytes());
+        header.extend_from_slice(&f64::from(self.rate).to_be_bytes());
+        header.extend_from_slice(b"lpcm");
+        header.extend_from_slice(&flags.to_be_bytes());
+        header.extend_from_slice(&(self.channels * sample_bytes).to_be_bytes());
+        header.extend_from_slice(&1u32.to_be_bytes());
+        header.extend_from_slice(&self.channels.to_be_bytes());
+        header.extend_from_slice(&(sample_bytes * 8).to_be_bytes());
+        // The data chunk starts with a 4 byte edit count.
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&(self.data_bytes as i64 + 4).to_be_bytes());
+        header.extend_from_slice(&0u32.to_be_bytes());
+        self.writer.write_all(&header)
+    }
+}
+
+// Encoder shared with the tap writing to it.
+struct RecordState {
+    recorder: Option<Recorder<BufWriter<File>>>,
+    // First write error, reported when the recording stops.
+    error: Option<io::Error>,
+}
+
+/// A recording started with
+/// [`Stream::start_recording`](crate::Stream::start_recording).
+pub(crate) struct Recording {
+    tap: TapId,
+    state: Arc<Mutex<RecordState>>,
+}
+
+impl Recording {
+    /// Record the output of the stream, or the input of input-only
+    /// streams, to a new file at `path`.
+    pub(crate) fn start<F: Frame + 'static>(
+        taps: &Taps<F>,
+        path: &Path,
+        format: RecordFormat,
+    ) -> Result<Recording> {
+        let direction = if taps.has(Direction::Output) {
+            Direction::Output
+        } else if taps.has(Direction::Input) {
+            Direction::Input
+        } else {
+            return Err(Error::InvalidParameter);
+        };
+        let file = File::create(path).map_err(|_| Error::Error)?;
+        let recorder = Recorder::new(
+            BufWriter::new(file),
+            format,
+            F::CHANNELS as u32,
+            taps.rate(),
+        )
+        .map_err(|_| Error::Error)?;
+        let state = Arc::new(Mutex::new(RecordState {
+            recorder: Some(recorder),
+            error: None,
+        }));
+        let writer = state.clone();
+        let tap = taps.add(
+            direction,
+            Box::new(move |frames: &[F]| {
+                let mut state = writer.lock().unwrap();
+                let RecordState { recorder, error } = &mut *state;
+                if let (Some(recorder), None) = (recorder, &error) {
+                    *error = recorder.write(frames).err();
+                }
+            }),
+        )?;
+        Ok(Recording { tap, state })
+    }
+
+    /// Write the audio queued so far, stop recording from `taps` and
+    /// finalize the file, see [`Recording::finish`].
+    pub(crate) fn stop<F>(self, taps: &Taps<F>) -> Result<()> {
+        taps.flush();
+        taps.remove(self.tap);
+        self.finish()
+    }
+
+    /// Finalize the header and close the file, failing with
+    /// [`Error::Error`] if any write failed. The taps must be shut down
+    /// unless stopping with [`Recording::stop`].
+    pub(crate) fn finish(self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let finished = match state.recorder.take() {
+            Some(recorder) => recorder.finish().and_then(|writer| {
+                writer.into_inner().map_err(|e| e.into_error())?;
+                Ok(())
+            }),
+            None => Ok(()),
+        };
+        match (state.error.take(), finished) {
+            (None, Ok(())) => Ok(()),
+            _ => Err(Error::Error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonoFrame, StereoFrame};
+    use std::io::Cursor;
+
+    fn record<F: Frame>(format: RecordFormat, rate: u32, frames: &[F]) -> Vec<u8> {
+        let mut recorder =
+            Recorder::new(Cursor::new(Vec::new()), format, F::CHANNELS as u32, rate).unwrap();
+        for chunk in frames.chunks(2) {
+            recorder.write(chunk).unwrap();
+        }
+        recorder.finish().unwrap().into_inner()
+    }
+
+    fn u32_le(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn wav_pcm16() {
+        let frames = [
+            StereoFrame { l: 1.0, r: -1.0 },
+            StereoFrame { l: 0.5, r: 0.0 },
+            StereoFrame { l: 0.0, r: 2.0 },
+        ];
+        let bytes = record(RecordFormat::WavPcm16, 44_100, &frames);
+        assert_eq!(bytes.len(), 44 + 12);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_le(&bytes, 4), 36 + 12);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        // PCM, 2 channels, 44.1kHz, 4 bytes per frame, 16 bits.
+        assert_eq!(u16::from_le_bytes([bytes[20], bytes[21]]), 1);
+        assert_eq!(u16::from_le_bytes([bytes[22], bytes[23]]), 2);
+        assert_eq!(u32_le(&bytes, 24), 44_100);
+        assert_eq!(u32_le(&bytes, 28), 44_100 * 4);
+        assert_eq!(u16::from_le_bytes([bytes[34], bytes[35]]), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_le(&bytes, 40), 12);
+        let samples: Vec<i16> = bytes[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        // Out of range samples are clipped.
+        assert_eq!(samples, [32767, -32767, 16384, 0, 0, 32767]);
+    }
+
+    #[test]
+    fn wav_float32_and_raw() {
+        let frames = [MonoFrame { m: 0.25f32 }, MonoFrame { m: -0.75 }];
+        let bytes = record(RecordFormat::WavFloat32, 48_000, &frames);
+        assert_eq!(u16::from_le_bytes([bytes[20], bytes[21]]), 3);
+        assert_eq!(u32_le(&bytes, 40), 8);
+        assert_eq!(&bytes[44..48], &0.25f32.to_le_bytes());
+
+        let raw = record(RecordFormat::RawFloat32, 48_000, &frames);
+        assert_eq!(raw, &bytes[44..]);
+        let raw = record(RecordFormat::RawPcm16, 48_000, &[MonoFrame { m: 1i16 }]);
+        assert_eq!(raw, [1, 0]);
+    }
+
+    #[test]
+    fn stopped_recording_keeps_queued_frames() {
+        let path = std::env::temp_dir().join(format!("cubeb-record-{}.wav", std::process::id()));
+        let (taps, mut writer) = crate::tap::taps::<MonoFrame<f32>>(48_000, false, true);
+        let recording = Recording::start(&taps, &path, RecordFormat::WavPcm16).unwrap();
+        for _ in 0..10 {
+            writer.write(Direction::Output, &[MonoFrame { m: 0.5 }; 480]);
+        }
+        recording.stop(&taps).unwrap();
+        // Frames written after stopping aren't recorded.
+        writer.write(Direction::Output, &[MonoFrame { m: 0.5 }; 480]);
+        taps.shutdown();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(u32_le(&bytes, 40), 10 * 480 * 2);
+        assert_eq!(bytes.len(), 44 + 10 * 480 * 2);
+    }
+
+    #[test]
+    fn caf() {
+        let frames = [StereoFrame { l: 0.5f32, r: -0.5 }; 3];
+        let bytes = record(RecordFormat::CafFloat32, 48_000, &frames);
+        assert_eq!(bytes.len(), 68 + 24);
+        assert_eq!(&bytes[0..4], b"caff");
+        assert_eq!(&bytes[8..12], b"desc");
+        assert_eq!(&bytes[20..28], &48_000f64.to_be_bytes());
+        assert_eq!(&bytes[28..32], b"lpcm");
+        assert_eq!(&bytes[52..56], b"data");
+        assert_eq!(i64::from_be_bytes(bytes[56..64].try_into().unwrap()), 28);
+        assert_eq!(&bytes[68..72], &0.5f32.to_le_bytes());
+    }
+}
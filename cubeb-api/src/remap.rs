@@ -0,0 +1,232 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Channel up/down-mixing between user frames and the stream layout
+
+use crate::{ffi, ChannelLayout, Error, Frame, Result, Sample, StreamParamsRef};
+use std::mem;
+use std::os::raw::c_void;
+use std::slice;
+
+/// Wrapper around libcubeb's channel mixer.
+struct ChannelMixer(*mut ffi::cubeb_mixer);
+
+impl ChannelMixer {
+    fn new(
+        format: ffi::cubeb_sample_format,
+        in_channels: usize,
+        in_layout: ChannelLayout,
+        out_channels: usize,
+        out_layout: ChannelLayout,
+    ) -> Result<ChannelMixer> {
+        let mixer = unsafe {
+            ffi::cubeb_mixer_create(
+                format,
+                in_channels as u32,
+                in_layout.bits(),
+                out_channels as u32,
+                out_layout.bits(),
+            )
+        };
+        if mixer.is_null() {
+            return Err(Error::Error);
+        }
+        Ok(ChannelMixer(mixer))
+    }
+
+    // Sizes are in bytes.
+    unsafe fn mix(
+        &self,
+        frames: usize,
+        input: *const c_void,
+        input_size: usize,
+        output: *mut c_void,
+        output_size: usize,
+    ) -> bool {
+        ffi::cubeb_mixer_mix(self.0, frames, input, input_size, output, output_size) == 0
+    }
+}
+
+impl Drop for ChannelMixer {
+    fn drop(&mut self) {
+        unsafe { ffi::cubeb_mixer_destroy(self.0) };
+    }
+}
+
+/// Converts between user frames in one layout and stream buffers in
+/// another, see [`StreamBuilder::user_layout`](crate::StreamBuilder::user_layout).
+pub(crate) struct Remapper<F> {
+    // Stream input to user frames.
+    input: Option<(ChannelMixer, usize)>,
+    // User frames to stream output.
+    output: Option<(ChannelMixer, usize)>,
+    input_frames: Vec<F>,
+    output_frames: Vec<F>,
+}
+
+impl<F: Frame> Remapper<F> {
+    /// Create a remapper if `input` or `output` have a different layout
+    /// than `layout`, preallocating buffers for `frames` frames.
+    pub(crate) fn new(
+        layout: ChannelLayout,
+        input: Option<&StreamParamsRef>,
+        output: Option<&StreamParamsRef>,
+        frames: usize,
+    ) -> Result<Option<Remapper<F>>> {
+        if layout.num_channels() as usize != F::CHANNELS {
+            return Err(Error::InvalidParameter);
+        }
+        let differs = |params: &&StreamParamsRef| {
+            params.channels() as usize != F::CHANNELS || params.layout() != layout
+        };
+        let (input, output) = (input.filter(differs), output.filter(differs));
+        if input.is_none() && output.is_none() {
+            return Ok(None);
+        }
+
+        let format = |params: &StreamParamsRef| unsafe { (*params.as_ptr()).format };
+        let input = input
+            .map(|params| {
+                let channels = params.channels() as usize;
+                let mixer = ChannelMixer::new(
+                    format(params),
+                    channels,
+                    params.layout(),
+                    F::CHANNELS,
+                    layout,
+                )?;
+                Ok::<_, Error>((mixer, channels))
+            })
+            .transpose()?;
+        let output = output
+            .map(|params| {
+                let channels = params.channels() as usize;
+                let mixer = ChannelMixer::new(
+                    format(params),
+                    F::CHANNELS,
+                    layout,
+                    channels,
+                    params.layout(),
+                )?;
+                Ok::<_, Error>((mixer, channels))
+            })
+            .transpose()?;
+        Ok(Some(Remapper {
+            input_frames: vec![F::silence(); if input.is_some() { frames } else { 0 }],
+            output_frames: vec![F::silence(); if output.is_some() { frames } else { 0 }],
+            input,
+            output,
+        }))
+    }
+
+    /// User frame buffers for a callback of `frames` frames, with the
+    /// input converted from `input`. Directions that aren't remapped use
+    /// the stream buffers directly.
+    ///
+    /// # Safety
+    ///
+    /// The buffers must be valid for `frames` frames of the stream, or null.
+    pub(crate) unsafe fn buffers(
+        &mut self,
+        input: *const c_void,
+        output: *mut c_void,
+        frames: usize,
+    ) -> (&[F], &mut [F]) {
+        let input: &[F] = match (&self.input, input.is_null()) {
+            (_, true) => &[],
+            (None, false) => slice::from_raw_parts(input as *const F, frames),
+            (Some((mixer, channels)), false) => {
+                if self.input_frames.len() < frames {
+                    // Only allocates if the backend asks for more frames
+                    // than the latency the stream was created with.
+                    self.input_frames.resize(frames, F::silence());
+                }
+                let buffer = &mut self.input_frames[..frames];
+                let input_size = frames * channels * mem::size_of::<F::Sample>();
+                if !mixer.mix(
+                    frames,
+                    input,
+                    input_size,
+                    buffer.as_mut_ptr() as *mut c_void,
+                    mem::size_of_val(buffer),
+                ) {
+                    buffer.fill(F::silence());
+                }
+                buffer
+            }
+        };
+        let output: &mut [F] = match (&self.output, output.is_null()) {
+            (_, true) => &mut [],
+            (None, false) => slice::from_raw_parts_mut(output as *mut F, frames),
+            (Some(_), false) => {
+                if self.output_frames.len() < frames {
+                    self.output_frames.resize(frames, F::silence());
+                }
+                &mut self.output_frames[..frames]
+            }
+        };
+        (input, output)
+    }
+
+    /// Mix the user frames returned by [`Remapper::buffers`] into `output`,
+    /// of which the data callback wrote the first `written`. The frames
+    /// after those are silent.
+    ///
+    /// # Safety
+    ///
+    /// `output` must be valid for `frames` frames of the stream, or null.
+    pub(crate) unsafe fn write_output(
+        &mut self,
+        output: *mut c_void,
+        frames: usize,
+        written: usize,
+    ) {
+        let Some((mixer, channels)) = &self.output else {
+            return;
+        };
+        if output.is_null() {
+            return;
+        }
+        let output_size = frames * channels * mem::size_of::<F::Sample>();
+        let buffer = &mut self.output_frames[..frames];
+        buffer[written.min(frames)..].fill(F::silence());
+        if !mixer.mix(
+            frames,
+            buffer.as_ptr() as *const c_void,
+            mem::size_of_val(buffer),
+            output,
+            output_size,
+        ) {
+            slice::from_raw_parts_mut(output as *mut F::Sample, frames * channels)
+                .fill(F::Sample::from_float(0.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonoFrame, SampleFormat, StereoFrame, StreamParamsBuilder};
+
+    fn params(channels: u32, layout: ChannelLayout) -> crate::StreamParams {
+        StreamParamsBuilder::new()
+            .format(SampleFormat::Float32NE)
+            .rate(48_000)
+            .channels(channels)
+            .layout(layout)
+            .take()
+    }
+
+    #[test]
+    fn remapper_only_when_layouts_differ() {
+        let stereo = params(2, ChannelLayout::STEREO);
+        let remap =
+            Remapper::<StereoFrame<f32>>::new(ChannelLayout::STEREO, None, Some(&stereo), 16);
+        assert!(remap.unwrap().is_none());
+        // The user layout must match the frame type.
+        let remap = Remapper::<MonoFrame<f32>>::new(ChannelLayout::STEREO, None, Some(&stereo), 16);
+        assert_eq!(remap.err(), Some(Error::InvalidParameter));
+    }
+}
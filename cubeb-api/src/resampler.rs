@@ -0,0 +1,83 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Wrapper around libcubeb's resampler
+
+use crate::{ffi, Error, Result, StreamParamsRef};
+use std::os::raw::{c_long, c_void};
+use std::ptr;
+
+/// Resamples between the rate of the data callback and the rates of the
+/// stream, calling the data callback from [`Resampler::fill`].
+pub(crate) struct Resampler(*mut ffi::cubeb_resampler);
+
+impl Resampler {
+    /// Create a resampler calling `callback` at `callback_rate` to feed,
+    /// and be fed by, a stream opened with `input` and `output`.
+    ///
+    /// # Safety
+    ///
+    /// `callback` must be safe to call with `stream` and `user_ptr` for as
+    /// long as the resampler lives.
+    pub(crate) unsafe fn new(
+        stream: *mut ffi::cubeb_stream,
+        input: Option<&StreamParamsRef>,
+        output: Option<&StreamParamsRef>,
+        callback_rate: u32,
+        callback: ffi::cubeb_data_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Resampler> {
+        let mut input = input.map(|params| *params.as_ptr());
+        let mut output = output.map(|params| *params.as_ptr());
+        let resampler = ffi::cubeb_resampler_create(
+            stream,
+            input.as_mut().map_or(ptr::null_mut(), |p| p as *mut _),
+            output.as_mut().map_or(ptr::null_mut(), |p| p as *mut _),
+            callback_rate,
+            callback,
+            user_ptr,
+            ffi::CUBEB_RESAMPLER_QUALITY_DEFAULT,
+            ffi::CUBEB_RESAMPLER_RECLOCK_NONE,
+        );
+        if resampler.is_null() {
+            return Err(Error::Error);
+        }
+        Ok(Resampler(resampler))
+    }
+
+    /// Latency added to the output, in frames.
+    pub(crate) fn latency(&self) -> u32 {
+        let latency = unsafe { ffi::cubeb_resampler_latency(self.0) };
+        latency.max(0) as u32
+    }
+
+    /// Pull `frames` frames of output from, and/or push `frames` frames of
+    /// input to, the data callback.
+    ///
+    /// # Safety
+    ///
+    /// The buffers must be valid for `frames` frames, or null if the stream
+    /// has no input or output.
+    pub(crate) unsafe fn fill(
+        &self,
+        input: *const c_void,
+        output: *mut c_void,
+        frames: c_long,
+    ) -> c_long {
+        let mut input_frames = frames;
+        let input_frames = if input.is_null() {
+            ptr::null_mut()
+        } else {
+            &mut input_frames as *mut _
+        };
+        ffi::cubeb_resampler_fill(self.0, input as *mut _, input_frames, output, frames)
+    }
+}
+
+impl Drop for Resampler {
+    fn drop(&mut self) {
+        unsafe { ffi::cubeb_resampler_destroy(self.0) };
+    }
+}
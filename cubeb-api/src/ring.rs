@@ -0,0 +1,148 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Lock-free single producer, single consumer queue
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Index of the next slot to pop, only written by the consumer.
+    head: AtomicUsize,
+    // Index of the next slot to push, only written by the producer.
+    tail: AtomicUsize,
+}
+
+impl<T> Ring<T> {
+    fn next(&self, i: usize) -> usize {
+        (i + 1) % self.slots.len()
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        while head != *self.tail.get_mut() {
+            unsafe { self.slots[head].get_mut().assume_init_drop() };
+            head = self.next(head);
+        }
+    }
+}
+
+/// Create a queue holding up to `capacity` items.
+pub(crate) fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    // One slot is kept empty to tell a full queue from an empty one.
+    let slots = (0..capacity + 1)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let ring = Arc::new(Ring {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (Producer(ring.clone()), Consumer(ring))
+}
+
+/// Pushing end of a queue created by [`channel`].
+pub(crate) struct Producer<T>(Arc<Ring<T>>);
+
+/// Popping end of a queue created by [`channel`].
+pub(crate) struct Consumer<T>(Arc<Ring<T>>);
+
+// Each end is only accessed through `&mut self`, so sharing a reference to
+// it between threads is harmless.
+unsafe impl<T: Send> Send for Producer<T> {}
+unsafe impl<T: Send> Sync for Producer<T> {}
+unsafe impl<T: Send> Send for Consumer<T> {}
+unsafe impl<T: Send> Sync for Consumer<T> {}
+
+impl<T> Producer<T> {
+    /// Push `item`, handing it back if the queue is full. Never blocks or
+    /// allocates.
+    pub(crate) fn push(&mut self, item: T) -> Result<(), T> {
+        let ring = &self.0;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let next = ring.next(tail);
+        if next == ring.head.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        unsafe { (*ring.slots[tail].get()).write(item) };
+        ring.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Pop the oldest item. Never blocks or deallocates.
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        let ring = &self.0;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == ring.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let item = unsafe { (*ring.slots[head].get()).assume_init_read() };
+        ring.head.store(ring.next(head), Ordering::Release);
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn push_pop() {
+        let (mut tx, mut rx) = channel(2);
+        assert_eq!(rx.pop(), None);
+        assert_eq!(tx.push(1), Ok(()));
+        assert_eq!(tx.push(2), Ok(()));
+        assert_eq!(tx.push(3), Err(3));
+        assert_eq!(rx.pop(), Some(1));
+        assert_eq!(tx.push(3), Ok(()));
+        assert_eq!(rx.pop(), Some(2));
+        assert_eq!(rx.pop(), Some(3));
+        assert_eq!(rx.pop(), None);
+    }
+
+    #[test]
+    fn drops_remaining_items() {
+        let item = Arc::new(());
+        let (mut tx, rx) = channel(4);
+        tx.push(item.clone()).unwrap();
+        tx.push(item.clone()).unwrap();
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn across_threads() {
+        let (mut tx, mut rx) = channel(16);
+        let producer = thread::spawn(move || {
+            for i in 0..10_000 {
+                let mut item = i;
+                while let Err(back) = tx.push(item) {
+                    item = back;
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 10_000 {
+            match rx.pop() {
+                Some(i) => {
+                    assert_eq!(i, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+    }
+}
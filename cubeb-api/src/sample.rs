@@ -9,16 +9,26 @@
 pub trait Sample: Send + Copy {
     /// Map f32 in range [-1,1] to sample type
     fn from_float(_: f32) -> Self;
+    /// Map sample type to f32 in range [-1,1]
+    fn to_float(self) -> f32;
 }
 
 impl Sample for i16 {
     fn from_float(x: f32) -> i16 {
         (x * f32::from(i16::MAX)) as i16
     }
+
+    fn to_float(self) -> f32 {
+        f32::from(self) / f32::from(i16::MAX)
+    }
 }
 
 impl Sample for f32 {
     fn from_float(x: f32) -> f32 {
         x
     }
+
+    fn to_float(self) -> f32 {
+        self
+    }
 }
@@ -0,0 +1,483 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Test signal generators
+//!
+//! Generators produce one sample at a time at the rate they were created
+//! with, and fill any [`Frame`] type by writing the same sample to every
+//! channel. They plug directly into
+//! [`StreamBuilder::data_callback`](crate::StreamBuilder::data_callback):
+//!
+//! ```no_run
+//! use cubeb::signal::{Signal, Sine};
+//! use cubeb::MonoFrame;
+//!
+//! let mut builder = cubeb::StreamBuilder::<MonoFrame<f32>>::new();
+//! builder.data_callback(Sine::new(440.0, 48_000).gain(0.5).into_callback());
+//! ```
+
+use crate::{Error, Frame, Result, Sample};
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// A generator of samples at a fixed rate.
+pub trait Signal: Send + Sync {
+    /// The next sample, from -1 to 1.
+    fn next_sample(&mut self) -> f32;
+
+    /// Write the next samples to every channel of `frames`.
+    fn fill<F: Frame>(&mut self, frames: &mut [F])
+    where
+        Self: Sized,
+    {
+        for frame in frames {
+            let sample = F::Sample::from_float(self.next_sample());
+            frame.channels_mut().fill(sample);
+        }
+    }
+
+    /// Scale the samples by `gain`.
+    fn gain(self, gain: f32) -> Gain<Self>
+    where
+        Self: Sized,
+    {
+        Gain { signal: self, gain }
+    }
+
+    /// Add the samples of `other`.
+    fn mix<S: Signal>(self, other: S) -> Mix<Self, S>
+    where
+        Self: Sized,
+    {
+        Mix(self, other)
+    }
+
+    /// A data callback playing the signal, ignoring the input.
+    fn into_callback<F: Frame>(mut self) -> impl FnMut(&[F], &mut [F]) -> isize + Send + Sync
+    where
+        Self: Sized + 'static,
+    {
+        move |_, output| {
+            self.fill(output);
+            output.len() as isize
+        }
+    }
+}
+
+/// Phase accumulator, in cycles. Kept in double precision so long
+/// signals don't drift.
+#[derive(Clone, Copy, Debug)]
+struct Phase {
+    phase: f64,
+    increment: f64,
+}
+
+impl Phase {
+    fn new(frequency: f32, rate: u32) -> Phase {
+        Phase {
+            phase: 0.0,
+            increment: f64::from(frequency) / f64::from(rate.max(1)),
+        }
+    }
+
+    // Return the current phase and advance it by one sample.
+    fn advance(&mut self) -> f64 {
+        let phase = self.phase;
+        self.phase = (self.phase + self.increment).fract();
+        phase
+    }
+}
+
+/// Sine wave.
+#[derive(Clone, Copy, Debug)]
+pub struct Sine(Phase);
+
+impl Sine {
+    pub fn new(frequency: f32, rate: u32) -> Sine {
+        Sine(Phase::new(frequency, rate))
+    }
+}
+
+impl Signal for Sine {
+    fn next_sample(&mut self) -> f32 {
+        (2.0 * PI * self.0.advance()).sin() as f32
+    }
+}
+
+/// Square wave, starting high.
+#[derive(Clone, Copy, Debug)]
+pub struct Square(Phase);
+
+impl Square {
+    pub fn new(frequency: f32, rate: u32) -> Square {
+        Square(Phase::new(frequency, rate))
+    }
+}
+
+impl Signal for Square {
+    fn next_sample(&mut self) -> f32 {
+        if self.0.advance() < 0.5 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+}
+
+/// Rising sawtooth wave, starting at -1.
+#[derive(Clone, Copy, Debug)]
+pub struct Saw(Phase);
+
+impl Saw {
+    pub fn new(frequency: f32, rate: u32) -> Saw {
+        Saw(Phase::new(frequency, rate))
+    }
+}
+
+impl Signal for Saw {
+    fn next_sample(&mut self) -> f32 {
+        (2.0 * self.0.advance() - 1.0) as f32
+    }
+}
+
+/// Uniform white noise from a xorshift generator, reproducible for a
+/// given seed.
+#[derive(Clone, Copy, Debug)]
+pub struct WhiteNoise {
+    state: u32,
+}
+
+impl WhiteNoise {
+    pub fn new(seed: u32) -> WhiteNoise {
+        // Xorshift gets stuck at zero.
+        WhiteNoise { state: seed.max(1) }
+    }
+}
+
+impl Signal for WhiteNoise {
+    fn next_sample(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (f64::from(self.state) / f64::from(u32::MAX) * 2.0 - 1.0) as f32
+    }
+}
+
+/// Pink noise, with a power density falling by 3dB per octave, filtered
+/// from [`WhiteNoise`].
+#[derive(Clone, Copy, Debug)]
+pub struct PinkNoise {
+    white: WhiteNoise,
+    // State of Paul Kellet's economy filter.
+    b: [f32; 3],
+}
+
+impl PinkNoise {
+    pub fn new(seed: u32) -> PinkNoise {
+        PinkNoise {
+            white: WhiteNoise::new(seed),
+            b: [0.0; 3],
+        }
+    }
+}
+
+impl Signal for PinkNoise {
+    fn next_sample(&mut self) -> f32 {
+        let white = self.white.next_sample();
+        self.b[0] = 0.99765 * self.b[0] + white * 0.0990460;
+        self.b[1] = 0.96300 * self.b[1] + white * 0.2965164;
+        self.b[2] = 0.57000 * self.b[2] + white * 1.0526913;
+        // The filter has a gain of about 4.5 at low frequencies.
+        ((self.b[0] + self.b[1] + self.b[2] + white * 0.1848) * 0.2).clamp(-1.0, 1.0)
+    }
+}
+
+/// Exponential sine sweep from `from` to `to` Hz over `duration`,
+/// repeated.
+#[derive(Clone, Copy, Debug)]
+pub struct Sweep {
+    phase: Phase,
+    // Phase increment at the start of the sweep.
+    start: f64,
+    // Frequency ratio between consecutive samples.
+    ratio: f64,
+    frames: u64,
+    position: u64,
+}
+
+impl Sweep {
+    pub fn new(from: f32, to: f32, duration: Duration, rate: u32) -> Sweep {
+        let frames = ((duration.as_secs_f64() * f64::from(rate)) as u64).max(1);
+        let from = f64::from(from.max(f32::MIN_POSITIVE));
+        let to = f64::from(to.max(f32::MIN_POSITIVE));
+        let start = from / f64::from(rate.max(1));
+        Sweep {
+            phase: Phase {
+                phase: 0.0,
+                increment: start,
+            },
+            start,
+            ratio: (to / from).powf(1.0 / frames as f64),
+            frames,
+            position: 0,
+        }
+    }
+}
+
+impl Signal for Sweep {
+    fn next_sample(&mut self) -> f32 {
+        let sample = (2.0 * PI * self.phase.advance()).sin() as f32;
+        self.position += 1;
+        if self.position == self.frames {
+            self.position = 0;
+            self.phase = Phase {
+                phase: 0.0,
+                increment: self.start,
+            };
+        } else {
+            self.phase.increment *= self.ratio;
+        }
+        sample
+    }
+}
+
+/// Unit impulses: a single 1 followed by silence, or one every `period`
+/// frames.
+#[derive(Clone, Copy, Debug)]
+pub struct Impulse {
+    period: Option<u64>,
+    position: u64,
+}
+
+impl Impulse {
+    /// A single impulse on the first sample.
+    pub fn new() -> Impulse {
+        Impulse {
+            period: None,
+            position: 0,
+        }
+    }
+
+    /// An impulse every `period` frames, starting on the first sample.
+    pub fn periodic(period: u64) -> Impulse {
+        Impulse {
+            period: Some(period.max(1)),
+            position: 0,
+        }
+    }
+}
+
+impl Default for Impulse {
+    fn default() -> Impulse {
+        Impulse::new()
+    }
+}
+
+impl Signal for Impulse {
+    fn next_sample(&mut self) -> f32 {
+        let sample = if self.position == 0 { 1.0 } else { 0.0 };
+        self.position = match self.period {
+            Some(period) => (self.position + 1) % period,
+            None => self.position.saturating_add(1),
+        };
+        sample
+    }
+}
+
+// Row and column frequencies of the DTMF keypad.
+const DTMF_ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLUMNS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [&str; 4] = ["123A", "456B", "789C", "*0#D"];
+
+/// Dual-tone multi-frequency signalling of a sequence of keypad digits,
+/// followed by silence.
+#[derive(Clone, Debug)]
+pub struct Dtmf {
+    tones: Vec<(Sine, Sine)>,
+    tone_frames: u64,
+    gap_frames: u64,
+    position: u64,
+}
+
+impl Dtmf {
+    /// Play `digits`, made of `0`-`9`, `*`, `#` and `A`-`D`, as 100ms
+    /// tones separated by 100ms of silence.
+    ///
+    /// Fails with [`Error::InvalidParameter`] on any other character.
+    pub fn new(digits: &str, rate: u32) -> Result<Dtmf> {
+        let tones = digits
+            .chars()
+            .map(|digit| {
+                let digit = digit.to_ascii_uppercase();
+                DTMF_KEYS
+                    .iter()
+                    .enumerate()
+                    .find_map(|(row, keys)| keys.find(digit).map(|column| (row, column)))
+                    .map(|(row, column)| {
+                        (
+                            Sine::new(DTMF_ROWS[row], rate),
+                            Sine::new(DTMF_COLUMNS[column], rate),
+                        )
+                    })
+                    .ok_or(Error::InvalidParameter)
+            })
+            .collect::<Result<_>>()?;
+        let frames = u64::from(rate) / 10;
+        Ok(Dtmf {
+            tones,
+            tone_frames: frames.max(1),
+            gap_frames: frames,
+            position: 0,
+        })
+    }
+
+    /// Change the length of the tones and of the silence between them.
+    pub fn timing(mut self, tone: Duration, gap: Duration, rate: u32) -> Dtmf {
+        let frames = |d: Duration| (d.as_secs_f64() * f64::from(rate)) as u64;
+        self.tone_frames = frames(tone).max(1);
+        self.gap_frames = frames(gap);
+        self
+    }
+}
+
+impl Signal for Dtmf {
+    fn next_sample(&mut self) -> f32 {
+        let slot = self.tone_frames + self.gap_frames;
+        let index = (self.position / slot) as usize;
+        let in_tone = self.position % slot < self.tone_frames;
+        self.position = self.position.saturating_add(1);
+        match self.tones.get_mut(index) {
+            Some((row, column)) if in_tone => 0.5 * (row.next_sample() + column.next_sample()),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Signal scaled by a gain, see [`Signal::gain`].
+#[derive(Clone, Copy, Debug)]
+pub struct Gain<S> {
+    signal: S,
+    gain: f32,
+}
+
+impl<S: Signal> Signal for Gain<S> {
+    fn next_sample(&mut self) -> f32 {
+        self.signal.next_sample() * self.gain
+    }
+}
+
+/// Sum of two signals, see [`Signal::mix`].
+#[derive(Clone, Copy, Debug)]
+pub struct Mix<A, B>(A, B);
+
+impl<A: Signal, B: Signal> Signal for Mix<A, B> {
+    fn next_sample(&mut self) -> f32 {
+        self.0.next_sample() + self.1.next_sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StereoFrame;
+
+    fn take<S: Signal>(signal: &mut S, n: usize) -> Vec<f32> {
+        (0..n).map(|_| signal.next_sample()).collect()
+    }
+
+    // Magnitude of the `frequency` component of `samples`, 1 for a full
+    // scale sine.
+    fn magnitude(samples: &[f32], frequency: f32, rate: u32) -> f32 {
+        let (mut re, mut im) = (0.0f64, 0.0f64);
+        for (i, &x) in samples.iter().enumerate() {
+            let w = 2.0 * PI * f64::from(frequency) * i as f64 / f64::from(rate);
+            re += f64::from(x) * w.cos();
+            im += f64::from(x) * w.sin();
+        }
+        (2.0 * (re * re + im * im).sqrt() / samples.len() as f64) as f32
+    }
+
+    #[test]
+    fn periodic_waveforms() {
+        // 750Hz is a period of 64 frames at 48kHz.
+        let samples = take(&mut Sine::new(750.0, 48_000), 64);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[16], 1.0);
+        // Phase doesn't drift with frequencies which aren't exact in binary.
+        let mut sine = Sine::new(1000.0, 48_000);
+        for _ in 0..48_000 * 60 {
+            sine.next_sample();
+        }
+        assert!(sine.next_sample().abs() < 1e-6);
+
+        let square = take(&mut Square::new(750.0, 48_000), 64);
+        assert!(square[..32].iter().all(|&x| x == 1.0));
+        assert!(square[32..].iter().all(|&x| x == -1.0));
+        let saw = take(&mut Saw::new(750.0, 48_000), 65);
+        assert_eq!(saw[0], -1.0);
+        assert_eq!(saw[32], 0.0);
+        assert_eq!(saw[64], -1.0);
+    }
+
+    #[test]
+    fn noise() {
+        let white = take(&mut WhiteNoise::new(1), 48_000);
+        assert_eq!(white, take(&mut WhiteNoise::new(1), 48_000));
+        assert!(white.iter().all(|x| (-1.0..=1.0).contains(x)));
+        let mean = white.iter().sum::<f32>() / white.len() as f32;
+        assert!(mean.abs() < 0.02);
+
+        // Pink noise has more energy an octave down.
+        let pink = take(&mut PinkNoise::new(1), 480_000);
+        let low = magnitude(&pink, 100.0, 48_000);
+        let high = magnitude(&pink, 6400.0, 48_000);
+        assert!(low > 2.0 * high, "{} {}", low, high);
+    }
+
+    #[test]
+    fn sweep_and_impulse() {
+        let mut sweep = Sweep::new(100.0, 10_000.0, Duration::from_secs(1), 48_000);
+        let samples = take(&mut sweep, 48_000);
+        // Halfway through, a logarithmic sweep is at the geometric mean.
+        let middle = &samples[23_760..24_240];
+        assert!(magnitude(middle, 1000.0, 48_000) > magnitude(middle, 5050.0, 48_000));
+        // Then it starts over.
+        assert_eq!(sweep.next_sample(), 0.0);
+
+        assert_eq!(take(&mut Impulse::new(), 4), [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(take(&mut Impulse::periodic(2), 4), [1.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn dtmf() {
+        let mut dtmf = Dtmf::new("5#", 8000).unwrap();
+        let samples = take(&mut dtmf, 3200);
+        // 5 is 770Hz and 1336Hz, # is 941Hz and 1477Hz.
+        let five = &samples[..800];
+        assert!((magnitude(five, 770.0, 8000) - 0.5).abs() < 0.01);
+        assert!((magnitude(five, 1336.0, 8000) - 0.5).abs() < 0.01);
+        assert!(magnitude(five, 941.0, 8000) < 0.05);
+        assert!(samples[800..1600].iter().all(|&x| x == 0.0));
+        assert!((magnitude(&samples[1600..2400], 1477.0, 8000) - 0.5).abs() < 0.01);
+        assert!(samples[2400..].iter().all(|&x| x == 0.0));
+        assert_eq!(Dtmf::new("12x", 8000).err(), Some(Error::InvalidParameter));
+    }
+
+    #[test]
+    fn fill_frames() {
+        let mut frames = [StereoFrame { l: 0i16, r: 0 }; 2];
+        Impulse::new().gain(0.5).fill(&mut frames);
+        let half = i16::from_float(0.5);
+        assert_eq!(frames[0], StereoFrame { l: half, r: half });
+        assert_eq!(frames[1], StereoFrame { l: 0, r: 0 });
+
+        let mut callback = Saw::new(1000.0, 48_000)
+            .mix(Impulse::new())
+            .into_callback::<StereoFrame<f32>>();
+        let mut output = [StereoFrame { l: 1.0, r: 1.0 }; 3];
+        assert_eq!(callback(&[], &mut output), 3);
+        assert_eq!(output[0], StereoFrame { l: 0.0, r: 0.0 });
+    }
+}
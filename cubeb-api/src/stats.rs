@@ -0,0 +1,252 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Real-time callback instrumentation
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of buckets in a [`Histogram`].
+pub const HISTOGRAM_BUCKETS: usize = 20;
+
+/// Histogram of durations with power of two microsecond buckets.
+///
+/// Bucket `i` counts durations in `[2^i, 2^(i+1))` microseconds, except
+/// the first bucket which also counts anything shorter and the last bucket
+/// which also counts anything longer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Number of durations in each bucket.
+    pub buckets: [u64; HISTOGRAM_BUCKETS],
+    /// Number of recorded durations.
+    pub count: u64,
+    /// Sum of the recorded durations.
+    pub total: Duration,
+    /// Longest recorded duration.
+    pub max: Duration,
+}
+
+impl Histogram {
+    /// Mean of the recorded durations, `None` if empty.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let nanos = self.total.as_nanos() / u128::from(self.count);
+        Some(Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        ))
+    }
+
+    /// Range of durations counted by bucket `i`.
+    pub fn bucket_range(i: usize) -> (Duration, Duration) {
+        assert!(i < HISTOGRAM_BUCKETS);
+        let lo = if i == 0 { 0 } else { 1 << i };
+        let hi = if i == HISTOGRAM_BUCKETS - 1 {
+            u64::MAX
+        } else {
+            1 << (i + 1)
+        };
+        (Duration::from_micros(lo), Duration::from_micros(hi))
+    }
+
+    /// Smallest duration such that at least `quantile` of the recorded
+    /// durations fall in lower buckets, e.g. `0.99` for the 99th
+    /// percentile. Resolution is limited to the bucket bounds.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let target = (quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target.max(1) {
+                return Some(Histogram::bucket_range(i).1.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
+
+/// Snapshot of data callback performance counters, see
+/// [`Stream::stats`](crate::Stream::stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallbackStats {
+    /// Number of data callbacks.
+    pub callbacks: u64,
+    /// Number of data callbacks that returned fewer frames than
+    /// requested.
+    pub short_returns: u64,
+    /// Number of data callbacks that took longer than the duration of the
+    /// audio they were asked for, i.e. `nframes / rate`.
+    pub deadline_misses: u64,
+    /// Time spent in the data callback.
+    pub durations: Histogram,
+    /// Time between the start of consecutive data callbacks. The first
+    /// callback after starting the stream isn't counted.
+    pub intervals: Histogram,
+}
+
+#[derive(Default)]
+struct AtomicHistogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    count: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn record(&self, d: Duration) {
+        let micros = d.as_micros() as u64;
+        let bucket = if micros < 2 {
+            0
+        } else {
+            (63 - micros.leading_zeros() as usize).min(HISTOGRAM_BUCKETS - 1)
+        };
+        let ns = d.as_nanos() as u64;
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let mut buckets = [0; HISTOGRAM_BUCKETS];
+        for (b, a) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *b = a.load(Ordering::Relaxed);
+        }
+        Histogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            total: Duration::from_nanos(self.total_ns.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_ns.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn reset(&self) {
+        for b in self.buckets.iter() {
+            b.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.total_ns.store(0, Ordering::Relaxed);
+        self.max_ns.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Lock-free counters written by the data callback trampoline.
+#[derive(Default)]
+pub(crate) struct StatsRecorder {
+    callbacks: AtomicU64,
+    short_returns: AtomicU64,
+    deadline_misses: AtomicU64,
+    durations: AtomicHistogram,
+    intervals: AtomicHistogram,
+}
+
+impl StatsRecorder {
+    pub(crate) fn record(
+        &self,
+        duration: Duration,
+        interval: Option<Duration>,
+        deadline: Option<Duration>,
+        frames: usize,
+        returned: isize,
+    ) {
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        if returned < frames as isize {
+            self.short_returns.fetch_add(1, Ordering::Relaxed);
+        }
+        if deadline.is_some_and(|deadline| duration > deadline) {
+            self.deadline_misses.fetch_add(1, Ordering::Relaxed);
+        }
+        self.durations.record(duration);
+        if let Some(interval) = interval {
+            self.intervals.record(interval);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> CallbackStats {
+        CallbackStats {
+            callbacks: self.callbacks.load(Ordering::Relaxed),
+            short_returns: self.short_returns.load(Ordering::Relaxed),
+            deadline_misses: self.deadline_misses.load(Ordering::Relaxed),
+            durations: self.durations.snapshot(),
+            intervals: self.intervals.snapshot(),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.callbacks.store(0, Ordering::Relaxed);
+        self.short_returns.store(0, Ordering::Relaxed);
+        self.deadline_misses.store(0, Ordering::Relaxed);
+        self.durations.reset();
+        self.intervals.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let h = AtomicHistogram::default();
+        h.record(Duration::from_nanos(500));
+        h.record(Duration::from_micros(3));
+        h.record(Duration::from_micros(1500));
+        h.record(Duration::from_secs(10));
+        let h = h.snapshot();
+        assert_eq!(h.count, 4);
+        assert_eq!(h.buckets[0], 1);
+        assert_eq!(h.buckets[1], 1);
+        assert_eq!(h.buckets[10], 1);
+        assert_eq!(h.buckets[HISTOGRAM_BUCKETS - 1], 1);
+        assert_eq!(h.max, Duration::from_secs(10));
+        assert_eq!(
+            Histogram::bucket_range(10),
+            (Duration::from_micros(1024), Duration::from_micros(2048))
+        );
+        assert_eq!(h.quantile(0.5), Some(Duration::from_micros(4)));
+        assert_eq!(h.quantile(1.0), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn histogram_mean() {
+        let mut h = Histogram::default();
+        assert_eq!(h.mean(), None);
+        h.count = 3;
+        h.total = Duration::from_micros(10);
+        assert_eq!(h.mean(), Some(Duration::from_nanos(3333)));
+        // Counts beyond `u32::MAX` aren't truncated.
+        h.count = 1 << 32;
+        h.total = Duration::from_micros(1 << 32);
+        assert_eq!(h.mean(), Some(Duration::from_micros(1)));
+    }
+
+    #[test]
+    fn recorder_counts() {
+        let r = StatsRecorder::default();
+        let deadline = Some(Duration::from_millis(10));
+        r.record(Duration::from_millis(1), None, deadline, 480, 480);
+        r.record(
+            Duration::from_millis(11),
+            Some(Duration::from_millis(10)),
+            deadline,
+            480,
+            100,
+        );
+        let stats = r.snapshot();
+        assert_eq!(stats.callbacks, 2);
+        assert_eq!(stats.short_returns, 1);
+        assert_eq!(stats.deadline_misses, 1);
+        assert_eq!(stats.durations.count, 2);
+        assert_eq!(stats.durations.mean(), Some(Duration::from_millis(6)));
+        assert_eq!(stats.intervals.count, 1);
+        r.reset();
+        assert_eq!(r.snapshot(), CallbackStats::default());
+    }
+}
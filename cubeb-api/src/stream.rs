@@ -3,13 +3,33 @@
 // This program is made available under an ISC-style license.  See the
 // accompanying file LICENSE for details.
 
+use crate::align::DelayLine;
+use crate::control::AtomicStreamState;
 use crate::ffi;
-use crate::{ContextRef, DeviceId, Error, Result, State, StreamParamsRef};
+use crate::gain::{GainControl, GainStage};
+use crate::glitch::{CadenceDetector, GlitchCounters, PositionDetector};
+use crate::meter::{Meter, MeterLevels};
+use crate::processing::{negotiate, InputProcessor};
+use crate::record::Recording;
+use crate::remap::Remapper;
+use crate::resampler::Resampler;
+use crate::stats::StatsRecorder;
+use crate::tap::{taps, TapWriter, Taps};
+use crate::{
+    CallbackStats, ChannelLayout, ContextRef, ControlError, DeviceId, Direction, Error, Frame,
+    GlitchCallback, GlitchCounts, GlitchEvent, GlitchKind, InputProcessingParams,
+    InputProcessingReport, Meters, PauseMode, ProcessingPolicy, RecordFormat, Result, State,
+    StreamParamsRef, StreamState, TapId, VoiceProcessor,
+};
 use std::ffi::CString;
 use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
 use std::os::raw::{c_long, c_void};
+use std::path::Path;
 use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use std::{ops, panic, ptr};
 
 /// User supplied data callback.
@@ -36,6 +56,47 @@ use std::{ops, panic, ptr};
 /// been read.  In this case, a value less than that will result in the stream being stopped.
 pub type DataCallback<F> = dyn FnMut(&[F], &mut [F]) -> isize + Send + Sync + 'static;
 
+/// User supplied data callback that also receives timing information.
+///
+/// Behaves like [`DataCallback`], with an extra [`CallbackInfo`] describing
+/// this invocation.
+pub type InfoDataCallback<F> =
+    dyn FnMut(&[F], &mut [F], &CallbackInfo) -> isize + Send + Sync + 'static;
+
+/// User supplied data callback for duplex streams that also receives the
+/// output played while the input was captured.
+///
+/// Behaves like [`InfoDataCallback`], with an extra `reference` slice
+/// between the input and the output, see
+/// [`StreamBuilder::aligned_data_callback`].
+pub type AlignedDataCallback<F> =
+    dyn FnMut(&[F], &[F], &mut [F], &CallbackInfo) -> isize + Send + Sync + 'static;
+
+/// Timing information for one invocation of the data callback.
+///
+/// This is computed by the callback trampoline from values sampled on the
+/// control thread, so the data callback doesn't need to query the stream
+/// from the real-time thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallbackInfo {
+    /// Monotonic index of this callback, starting at zero for the first
+    /// callback of the stream.
+    pub index: u64,
+    /// Number of frames requested by the backend.
+    pub frames: usize,
+    /// Estimated time at which the first output frame of this callback will
+    /// be heard. `None` for input-only streams or while the output latency
+    /// is unknown.
+    pub output_presentation_time: Option<Instant>,
+    /// Estimated time at which the first input frame of this callback was
+    /// captured. `None` for output-only streams or while the input latency
+    /// is unknown.
+    pub input_capture_time: Option<Instant>,
+    /// True for the first callback after [`Stream::start`] or
+    /// [`Stream::resume`].
+    pub first_after_start: bool,
+}
+
 /// User supplied state callback.
 ///
 /// # Arguments
@@ -46,10 +107,431 @@ pub type StateCallback = dyn FnMut(State) + Send + Sync + 'static;
 /// User supplied callback called when the underlying device changed.
 pub type DeviceChangedCallback = dyn FnMut() + Send + Sync + 'static;
 
+// The user supplied data callback.
+pub(crate) enum UserCallback<F> {
+    Data(Box<InfoDataCallback<F>>),
+    // See `StreamBuilder::aligned_data_callback`.
+    Aligned(Box<AlignedDataCallback<F>>),
+}
+
 pub struct StreamCallbacks<F> {
-    pub(crate) data: Box<DataCallback<F>>,
+    pub(crate) data: UserCallback<F>,
     pub(crate) state: Box<StateCallback>,
     pub(crate) device_changed: Option<Box<DeviceChangedCallback>>,
+    pub(crate) timing: CallbackTiming,
+    pub(crate) glitch: Option<GlitchDetection>,
+    pub(crate) gain: Option<GainStage>,
+    // Frames left to output before draining, see `Stream::stop_after_drain`.
+    pub(crate) drain: Option<u64>,
+    // Frames of silence left to output before calling the data callback,
+    // see `Stream::start_at`.
+    pub(crate) delay: Option<u64>,
+    // Converts between the data callback rate and the stream rates, see
+    // `StreamBuilder::user_rate`. Owned by `Stream`, apart from the
+    // callbacks that it calls back, or null.
+    pub(crate) resampler: *const Resampler,
+    // Converts between the user layout and the stream layouts, see
+    // `StreamBuilder::user_layout`.
+    pub(crate) remap: Option<Remapper<F>>,
+    // Input processing, see `StreamBuilder::input_processing` and
+    // `StreamBuilder::input_processor`.
+    pub(crate) input_processing: Option<InputProcessor<F>>,
+    // Output played while the input was captured, see
+    // `StreamBuilder::aligned_data_callback`.
+    pub(crate) reference: Option<DelayLine<F>>,
+    // See `StreamBuilder::meters`.
+    pub(crate) input_meter: Option<Meter>,
+    pub(crate) output_meter: Option<Meter>,
+    // See `Stream::add_tap`.
+    pub(crate) taps: TapWriter<F>,
+}
+
+impl<F> StreamCallbacks<F> {
+    // Output the silence left before a scheduled start, then call the data
+    // callback for the rest of the buffer.
+    fn render_scheduled(&mut self, input: &[F], output: &mut [F], info: &CallbackInfo) -> isize {
+        let skip = match &mut self.delay {
+            Some(delay) => {
+                let skip = (*delay).min(info.frames as u64) as usize;
+                *delay -= skip as u64;
+                skip
+            }
+            None => 0,
+        };
+        if self.delay == Some(0) {
+            self.delay = None;
+        }
+        if skip == 0 {
+            return self.render(input, output, info);
+        }
+
+        let len = output.len();
+        silence(&mut output[..skip.min(len)]);
+        if skip == info.frames {
+            return skip as isize;
+        }
+        let offset = Duration::from_secs_f64(skip as f64 / f64::from(self.timing.rate));
+        let info = CallbackInfo {
+            frames: info.frames - skip,
+            output_presentation_time: info
+                .output_presentation_time
+                .and_then(|time| time.checked_add(offset)),
+            input_capture_time: info
+                .input_capture_time
+                .and_then(|time| time.checked_add(offset)),
+            ..*info
+        };
+        let input = input.get(skip..).unwrap_or(&[]);
+        let output = output.get_mut(skip..).unwrap_or(&mut []);
+        skip as isize + self.render(input, output, &info)
+    }
+
+    // Call the data callback, shortening its output when draining.
+    fn render(&mut self, input: &[F], output: &mut [F], info: &CallbackInfo) -> isize {
+        let reference = self
+            .reference
+            .as_ref()
+            .map_or(&[][..], DelayLine::reference);
+        let Some(remaining) = &mut self.drain else {
+            return self.data.call(input, reference, output, info);
+        };
+        let mut returned = 0;
+        if *remaining > 0 {
+            returned = self
+                .data
+                .call(input, reference, output, info)
+                .min(*remaining as isize);
+            *remaining -= returned.max(0) as u64;
+        }
+        // Returning fewer frames than requested starts draining.
+        let written = returned.clamp(0, output.len() as isize) as usize;
+        silence(&mut output[written..]);
+        returned
+    }
+}
+
+impl<F> UserCallback<F> {
+    fn call(
+        &mut self,
+        input: &[F],
+        reference: &[F],
+        output: &mut [F],
+        info: &CallbackInfo,
+    ) -> isize {
+        match self {
+            UserCallback::Data(data) => data(input, output, info),
+            UserCallback::Aligned(data) => {
+                // A scheduled start skips the beginning of the buffers.
+                let reference = &reference[reference.len().saturating_sub(input.len())..];
+                data(input, reference, output, info)
+            }
+        }
+    }
+}
+
+impl<F> StreamCallbacks<F> {
+    // Time the callback and pick up the requests of the control thread.
+    fn begin(&mut self, frames: usize) -> CallbackInfo {
+        let info = self.timing.next(frames);
+        self.update_drain(&info);
+        self.update_schedule(&info);
+        self.detect_glitch(&info);
+        info
+    }
+
+    // Call the data callback, or output silence while paused.
+    fn render_unless_paused(
+        &mut self,
+        input: &[F],
+        output: &mut [F],
+        info: &CallbackInfo,
+    ) -> isize {
+        if self.timing.shared.paused.load(Ordering::Acquire) {
+            silence(output);
+            info.frames as isize
+        } else {
+            self.render_scheduled(input, output, info)
+        }
+    }
+
+    // Pick up drain requests, cancelling any left over from before a
+    // restart.
+    fn update_drain(&mut self, info: &CallbackInfo) {
+        if info.first_after_start {
+            self.drain = None;
+            if let Some(stage) = &mut self.gain {
+                stage.reset_fade();
+            }
+        }
+        let frames = self.timing.shared.drain.swap(NO_DRAIN, Ordering::AcqRel);
+        if frames == NO_DRAIN {
+            return;
+        }
+        self.drain = Some(frames);
+        if let Some(stage) = &mut self.gain {
+            stage.fade_out(frames.min(u64::from(u32::MAX)) as u32);
+        }
+    }
+
+    // Pick up the start schedule when the stream starts.
+    fn update_schedule(&mut self, info: &CallbackInfo) {
+        if !info.first_after_start {
+            return;
+        }
+        let shared = &self.timing.shared;
+        let frames = shared.start_frames.swap(NO_SCHEDULE, Ordering::AcqRel);
+        let nanos = shared.start_time.swap(NO_SCHEDULE, Ordering::AcqRel);
+        self.delay = if frames != NO_SCHEDULE {
+            Some(frames)
+        } else if nanos != NO_SCHEDULE {
+            // Count the silence from the time the first frame of this
+            // callback is heard, or was captured.
+            let when = shared.epoch + Duration::from_nanos(nanos);
+            let first = info
+                .output_presentation_time
+                .or(info.input_capture_time)
+                .or(self.timing.start)
+                .unwrap_or_else(Instant::now);
+            let delay = when.saturating_duration_since(first).as_secs_f64();
+            Some((delay * f64::from(self.timing.rate)).round() as u64)
+        } else {
+            None
+        };
+    }
+
+    fn detect_glitch(&mut self, info: &CallbackInfo) {
+        let (Some(detection), Some(now)) = (&mut self.glitch, self.timing.start) else {
+            return;
+        };
+        if info.first_after_start {
+            detection.cadence.reset();
+        }
+        let shared = &self.timing.shared;
+        shared.period.fetch_max(info.frames, Ordering::Relaxed);
+        if let Some(event) = detection.cadence.observe(now, info.frames) {
+            if let Some(counters) = &shared.glitches {
+                counters.record(&event);
+            }
+            if let Some(cb) = &mut detection.callback {
+                cb(event);
+            }
+        }
+    }
+}
+
+/// Glitch detection state owned by the data callback trampoline.
+pub(crate) struct GlitchDetection {
+    cadence: CadenceDetector,
+    callback: Option<Box<GlitchCallback>>,
+}
+
+// Sentinel for a latency which hasn't been sampled yet.
+const UNKNOWN_LATENCY: u32 = u32::MAX;
+// Sentinel for no pending drain request.
+const NO_DRAIN: u64 = u64::MAX;
+// Longest round-trip latency compensated by
+// `StreamBuilder::aligned_data_callback`.
+const MAX_ROUNDTRIP: Duration = Duration::from_secs(1);
+// Minimum number of frames preallocated for channel remapping.
+const REMAP_FRAMES: usize = 4096;
+// Sentinel for no scheduled start.
+const NO_SCHEDULE: u64 = u64::MAX;
+// Time allowed for the audio buffered by the backend to play out once a
+// fade out completes.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// State shared between a [`Stream`] and its callback trampolines.
+pub(crate) struct StreamShared {
+    output_latency: AtomicU32,
+    input_latency: AtomicU32,
+    starting: AtomicBool,
+    running: AtomicBool,
+    // Largest number of frames requested by a data callback.
+    period: AtomicUsize,
+    stats: Option<StatsRecorder>,
+    glitches: Option<GlitchCounters>,
+    gain: Option<GainControl>,
+    // Frames to output before draining, or `NO_DRAIN`.
+    drain: AtomicU64,
+    // Last state reported by the state callback since a drain request.
+    state: Mutex<Option<State>>,
+    state_changed: Condvar,
+    stream_state: AtomicStreamState,
+    // Output silence instead of calling the data callback, see
+    // `PauseMode::SilenceFill`.
+    paused: AtomicBool,
+    // Scheduled start, either in frames or in nanoseconds since `epoch`,
+    // picked up by the first callback after starting.
+    start_frames: AtomicU64,
+    start_time: AtomicU64,
+    epoch: Instant,
+    // Output latency added by the resampler, in frames.
+    resampler_latency: AtomicU32,
+    input_meter: Option<MeterLevels>,
+    output_meter: Option<MeterLevels>,
+}
+
+impl StreamShared {
+    fn new(instrument: bool, detect_glitches: bool, gain: Option<GainControl>) -> StreamShared {
+        StreamShared {
+            output_latency: AtomicU32::new(UNKNOWN_LATENCY),
+            input_latency: AtomicU32::new(UNKNOWN_LATENCY),
+            starting: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            period: AtomicUsize::new(0),
+            stats: instrument.then(StatsRecorder::default),
+            glitches: detect_glitches.then(GlitchCounters::default),
+            gain,
+            drain: AtomicU64::new(NO_DRAIN),
+            state: Mutex::new(None),
+            state_changed: Condvar::new(),
+            stream_state: AtomicStreamState::new(StreamState::Created),
+            paused: AtomicBool::new(false),
+            start_frames: AtomicU64::new(NO_SCHEDULE),
+            start_time: AtomicU64::new(NO_SCHEDULE),
+            epoch: Instant::now(),
+            resampler_latency: AtomicU32::new(0),
+            input_meter: None,
+            output_meter: None,
+        }
+    }
+
+    fn schedule_start(&self, frames: Option<u64>, when: Option<Instant>) {
+        let nanos = when.map(|when| when.saturating_duration_since(self.epoch).as_nanos() as u64);
+        self.start_frames
+            .store(frames.unwrap_or(NO_SCHEDULE), Ordering::Release);
+        self.start_time
+            .store(nanos.unwrap_or(NO_SCHEDULE), Ordering::Release);
+    }
+
+    fn request_drain(&self, frames: u64) {
+        *self.state.lock().unwrap() = None;
+        self.drain.store(frames, Ordering::Release);
+    }
+
+    fn set_state(&self, state: State) {
+        self.running
+            .store(state == State::Started, Ordering::Release);
+        // Started and stopped are the result of the control methods, which
+        // track them themselves.
+        match state {
+            State::Drained => {
+                self.stream_state
+                    .replace(StreamState::Started, StreamState::Drained);
+            }
+            State::Error => self.stream_state.store(StreamState::Error),
+            _ => {}
+        }
+        *self.state.lock().unwrap() = Some(state);
+        self.state_changed.notify_all();
+    }
+
+    // Wait until the stream stops running, returning its final state or
+    // `None` on timeout.
+    fn wait_for_end(&self, timeout: Duration) -> Option<State> {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .state_changed
+            .wait_timeout_while(state, timeout, |state| {
+                !matches!(state, Some(State::Drained | State::Stopped | State::Error))
+            })
+            .unwrap();
+        *state
+    }
+}
+
+/// Per-callback bookkeeping owned by the data callback trampoline.
+pub(crate) struct CallbackTiming {
+    shared: Arc<StreamShared>,
+    // Rate of the data callback.
+    rate: u32,
+    // Rate of the stream, in which latencies are expressed.
+    stream_rate: u32,
+    has_input: bool,
+    has_output: bool,
+    index: u64,
+    start: Option<Instant>,
+    interval: Option<Duration>,
+}
+
+impl CallbackTiming {
+    fn new(
+        shared: Arc<StreamShared>,
+        rate: u32,
+        stream_rate: u32,
+        has_input: bool,
+        has_output: bool,
+    ) -> Self {
+        CallbackTiming {
+            shared,
+            rate,
+            stream_rate,
+            has_input,
+            has_output,
+            index: 0,
+            start: None,
+            interval: None,
+        }
+    }
+
+    fn next(&mut self, frames: usize) -> CallbackInfo {
+        let now = Instant::now();
+        let shared = &self.shared;
+        let first_after_start = shared.starting.swap(false, Ordering::AcqRel);
+        if first_after_start {
+            // Don't count the time the stream was stopped as an interval.
+            self.start = None;
+        }
+        self.interval = self.start.map(|start| now - start);
+        self.start = Some(now);
+        let output_presentation_time = self
+            .latency(self.has_output, &shared.output_latency)
+            .and_then(|latency| now.checked_add(latency));
+        let input_capture_time = self
+            .latency(self.has_input, &shared.input_latency)
+            .and_then(|latency| now.checked_sub(latency));
+        let info = CallbackInfo {
+            index: self.index,
+            frames,
+            output_presentation_time,
+            input_capture_time,
+            first_after_start,
+        };
+        self.index += 1;
+        info
+    }
+
+    // Record the outcome of the callback started by the last call to `next`.
+    fn finish(&mut self, frames: usize, returned: isize) {
+        let (Some(stats), Some(start)) = (&self.shared.stats, self.start) else {
+            return;
+        };
+        let deadline =
+            (self.rate != 0).then(|| Duration::from_secs_f64(frames as f64 / f64::from(self.rate)));
+        stats.record(start.elapsed(), self.interval, deadline, frames, returned);
+    }
+
+    // Round-trip latency at the callback rate, in frames.
+    fn roundtrip(&self) -> Option<usize> {
+        let output = self.shared.output_latency.load(Ordering::Acquire);
+        let input = self.shared.input_latency.load(Ordering::Acquire);
+        if output == UNKNOWN_LATENCY || input == UNKNOWN_LATENCY || self.stream_rate == 0 {
+            return None;
+        }
+        let frames = (u64::from(output) + u64::from(input)) * u64::from(self.rate)
+            / u64::from(self.stream_rate);
+        Some(frames as usize)
+    }
+
+    fn latency(&self, enabled: bool, frames: &AtomicU32) -> Option<Duration> {
+        let frames = frames.load(Ordering::Acquire);
+        if !enabled || frames == UNKNOWN_LATENCY || self.stream_rate == 0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            f64::from(frames) / f64::from(self.stream_rate),
+        ))
+    }
 }
 
 /// Audio input/output stream
@@ -106,19 +588,446 @@ pub struct StreamCallbacks<F> {
 ///     stream.stop().unwrap();
 /// }
 /// ```
-pub struct Stream<F>(ManuallyDrop<cubeb_core::Stream>, PhantomData<*const F>);
+pub struct Stream<F> {
+    stream: ManuallyDrop<cubeb_core::Stream>,
+    shared: Arc<StreamShared>,
+    position_detector: Option<Mutex<PositionDetector>>,
+    pause_mode: PauseMode,
+    input_processing: Option<InputProcessingReport>,
+    taps: Taps<F>,
+    recording: Mutex<Option<Recording>>,
+    // Dropped after the stream is destroyed, see
+    // `StreamCallbacks::resampler`.
+    _resampler: Option<Box<Resampler>>,
+    // Serializes the control methods.
+    control: Mutex<()>,
+    _frame: PhantomData<*const F>,
+}
 
 impl<F> Stream<F> {
-    fn new(s: cubeb_core::Stream) -> Stream<F> {
-        Stream(ManuallyDrop::new(s), PhantomData)
+    fn new(
+        s: cubeb_core::Stream,
+        shared: Arc<StreamShared>,
+        position_detector: Option<PositionDetector>,
+        pause_mode: PauseMode,
+        input_processing: Option<InputProcessingReport>,
+        taps: Taps<F>,
+        resampler: Option<Box<Resampler>>,
+    ) -> Stream<F> {
+        Stream {
+            stream: ManuallyDrop::new(s),
+            shared,
+            position_detector: position_detector.map(Mutex::new),
+            pause_mode,
+            input_processing,
+            taps,
+            recording: Mutex::new(None),
+            _resampler: resampler,
+            control: Mutex::new(()),
+            _frame: PhantomData,
+        }
+    }
+
+    /// Current state of the stream.
+    ///
+    /// Only changes made through the control methods of `Stream` are
+    /// tracked, not those made by calling [`StreamRef`](crate::StreamRef)
+    /// methods directly, such as `StreamRef::start` and `StreamRef::stop`
+    /// which `Stream::start` and `Stream::stop` shadow.
+    pub fn state(&self) -> StreamState {
+        self.shared.stream_state.load()
+    }
+
+    /// Start playback, see [`Stream::try_start`].
+    pub fn start(&self) -> Result<()> {
+        self.try_start().map_err(Error::from)
+    }
+
+    /// Start playback, tracking the [`StreamState`].
+    ///
+    /// The output and input latencies used to compute [`CallbackInfo`] are
+    /// sampled once the stream has started. Starting a paused stream
+    /// resumes it. A drained stream must be stopped before it can be
+    /// started again.
+    pub fn try_start(&self) -> std::result::Result<(), ControlError> {
+        self.scheduled_start(None, None)
+    }
+
+    /// Start the stream so that its first frame is heard at `when`, or for
+    /// input-only streams so that the data callback receives the frames
+    /// captured from `when`.
+    ///
+    /// The stream is started immediately and outputs silence until `when`,
+    /// computed from the output latency once the stream is running, or
+    /// from the time of the first callback if the latency isn't known. If
+    /// `when` has already passed by the time the first frame could be
+    /// heard, the data callback is called immediately.
+    pub fn start_at(&self, when: Instant) -> std::result::Result<(), ControlError> {
+        self.scheduled_start(None, Some(when))
+    }
+
+    /// Start the stream, outputting `frames` frames of silence before
+    /// calling the data callback.
+    pub fn start_after_frames(&self, frames: u64) -> std::result::Result<(), ControlError> {
+        self.scheduled_start(Some(frames), None)
+    }
+
+    fn scheduled_start(
+        &self,
+        frames: Option<u64>,
+        when: Option<Instant>,
+    ) -> std::result::Result<(), ControlError> {
+        self.transition(StreamState::Started, |from| {
+            self.shared.schedule_start(frames, when);
+            self.restart(from).inspect_err(|_| {
+                self.shared.schedule_start(None, None);
+            })
+        })
+    }
+
+    /// Stop playback, see [`Stream::try_stop`].
+    pub fn stop(&self) -> Result<()> {
+        self.try_stop().map_err(Error::from)
+    }
+
+    /// Stop playback, tracking the [`StreamState`]. The stream position
+    /// isn't reset.
+    pub fn try_stop(&self) -> std::result::Result<(), ControlError> {
+        self.transition(StreamState::Stopped, |_| {
+            self.shared.paused.store(false, Ordering::Release);
+            self.stream.stop().map_err(ControlError::Backend)
+        })
+    }
+
+    /// Pause playback, as configured with [`StreamBuilder::pause_mode`].
+    pub fn pause(&self) -> std::result::Result<(), ControlError> {
+        self.transition(StreamState::Paused, |_| match self.pause_mode {
+            PauseMode::HardStop => self.stream.stop().map_err(ControlError::Backend),
+            PauseMode::SilenceFill => {
+                self.shared.paused.store(true, Ordering::Release);
+                Ok(())
+            }
+        })
+    }
+
+    /// Resume playback after [`Stream::pause`], from the position the
+    /// stream was paused at.
+    pub fn resume(&self) -> std::result::Result<(), ControlError> {
+        self.transition(StreamState::Started, |from| {
+            if from != StreamState::Paused {
+                return Err(ControlError::InvalidTransition {
+                    from,
+                    to: StreamState::Started,
+                });
+            }
+            self.shared.schedule_start(None, None);
+            self.restart(from)
+        })
+    }
+
+    // Move to `to`, then run `op`, restoring the previous state if it
+    // fails. The state is updated first so state callbacks fired by `op`
+    // apply on top of it.
+    fn transition<OP>(&self, to: StreamState, op: OP) -> std::result::Result<(), ControlError>
+    where
+        OP: FnOnce(StreamState) -> std::result::Result<(), ControlError>,
+    {
+        let _control = self.control.lock().unwrap();
+        let state = &self.shared.stream_state;
+        let from = state.load();
+        if !from.can_transition_to(to) || !state.replace(from, to) {
+            return Err(ControlError::InvalidTransition { from, to });
+        }
+        op(from).inspect_err(|_| {
+            state.replace(to, from);
+        })
+    }
+
+    fn restart(&self, from: StreamState) -> std::result::Result<(), ControlError> {
+        let shared = &self.shared;
+        shared.drain.store(NO_DRAIN, Ordering::Release);
+        shared.starting.store(true, Ordering::Release);
+        if shared.paused.swap(false, Ordering::AcqRel) {
+            // Paused with `PauseMode::SilenceFill`, the backend is running.
+            debug_assert_eq!(from, StreamState::Paused);
+            return Ok(());
+        }
+        self.stream.start().map_err(ControlError::Backend)?;
+        self.update_latency();
+        Ok(())
+    }
+
+    /// Snapshot of the data callback performance counters.
+    ///
+    /// Returns `None` unless the stream was built with
+    /// [`StreamBuilder::instrument`]. Reading the counters doesn't block the
+    /// data callback.
+    pub fn stats(&self) -> Option<CallbackStats> {
+        self.shared.stats.as_ref().map(StatsRecorder::snapshot)
+    }
+
+    /// Peak, RMS and loudness levels of the input and output, `None` unless
+    /// enabled with [`StreamBuilder::meters`].
+    ///
+    /// Peak levels are the largest since the previous call.
+    pub fn meters(&self) -> Option<Meters> {
+        let shared = &self.shared;
+        if shared.input_meter.is_none() && shared.output_meter.is_none() {
+            return None;
+        }
+        Some(Meters {
+            input: shared.input_meter.as_ref().map(MeterLevels::read),
+            output: shared.output_meter.as_ref().map(MeterLevels::read),
+        })
+    }
+
+    /// Reset the data callback performance counters.
+    pub fn reset_stats(&self) {
+        if let Some(stats) = &self.shared.stats {
+            stats.reset();
+        }
+    }
+
+    /// Glitch counters.
+    ///
+    /// Returns `None` unless the stream was built with
+    /// [`StreamBuilder::detect_glitches`] or
+    /// [`StreamBuilder::glitch_callback`].
+    pub fn glitches(&self) -> Option<GlitchCounts> {
+        self.shared.glitches.as_ref().map(GlitchCounters::snapshot)
+    }
+
+    /// Compare the progression of the stream position with the system clock
+    /// since the previous call, and report a probable glitch if they
+    /// disagree.
+    ///
+    /// Call this periodically from a non-real-time thread, e.g. every few
+    /// hundred milliseconds. Detected glitches are also added to
+    /// [`Stream::glitches`]. Returns `Ok(None)` if glitch detection isn't
+    /// enabled.
+    pub fn check_position(&self) -> Result<Option<GlitchEvent>> {
+        let Some(detector) = &self.position_detector else {
+            return Ok(None);
+        };
+        let mut detector = detector.lock().unwrap();
+        if !self.shared.running.load(Ordering::Acquire) {
+            detector.reset();
+            return Ok(None);
+        }
+        let position = self.stream.position()?;
+        let period = self.shared.period.load(Ordering::Relaxed);
+        let event = detector.observe(Instant::now(), position, period);
+        if let (Some(event), Some(counters)) = (&event, &self.shared.glitches) {
+            counters.record(event);
+        }
+        Ok(event)
+    }
+
+    /// Ramp the volume applied to the output by the data callback
+    /// trampoline to `volume` over `duration`.
+    ///
+    /// Unlike [`StreamRef::set_volume`](crate::StreamRef::set_volume), which
+    /// changes the volume instantly in the backend, the ramp avoids clicks.
+    /// A zero `duration` changes the volume on the next callback. Returns
+    /// `Error::NotSupported` for input-only streams and unless enabled with
+    /// [`StreamBuilder::gain`].
+    pub fn ramp_volume(&self, volume: f32, duration: Duration) -> Result<()> {
+        if !volume.is_finite() || volume < 0.0 {
+            return Err(Error::InvalidParameter);
+        }
+        self.gain()?.ramp_volume(volume, duration);
+        Ok(())
+    }
+
+    /// The volume last requested with [`Stream::ramp_volume`].
+    pub fn volume(&self) -> Result<f32> {
+        Ok(self.gain()?.volume())
+    }
+
+    /// Fade the output out, over a few milliseconds, and keep it silent
+    /// until [`Stream::unmute`].
+    pub fn mute(&self) -> Result<()> {
+        self.gain()?.set_muted(true);
+        Ok(())
+    }
+
+    /// Fade the output back in after [`Stream::mute`].
+    pub fn unmute(&self) -> Result<()> {
+        self.gain()?.set_muted(false);
+        Ok(())
+    }
+
+    /// True if the output is muted, see [`Stream::mute`].
+    pub fn is_muted(&self) -> bool {
+        self.shared.gain.as_ref().is_some_and(GainControl::is_muted)
+    }
+
+    /// Set the gain of output `channel`, applied on top of the stream
+    /// volume.
+    pub fn set_channel_gain(&self, channel: usize, gain: f32) -> Result<()> {
+        let control = self.gain()?;
+        if channel >= control.channel_count() || !gain.is_finite() || gain < 0.0 {
+            return Err(Error::InvalidParameter);
+        }
+        control.set_channel_gain(channel, gain);
+        Ok(())
+    }
+
+    /// The gain of output `channel`, see [`Stream::set_channel_gain`].
+    pub fn channel_gain(&self, channel: usize) -> Result<f32> {
+        let control = self.gain()?;
+        if channel >= control.channel_count() {
+            return Err(Error::InvalidParameter);
+        }
+        Ok(control.channel_gain(channel))
+    }
+
+    /// Stop feeding the stream, let the audio already buffered by the
+    /// backend play out, then stop it.
+    ///
+    /// The data callback isn't called again once this is requested. Waits
+    /// for the stream to report [`State::Drained`], up to `timeout`, after
+    /// which the stream is stopped immediately. Returns `Ok(true)` if the
+    /// stream drained, `Ok(false)` if it had to be stopped on timeout.
+    pub fn stop_after_drain(&self, timeout: Duration) -> Result<bool> {
+        self.drain(0, timeout)
+    }
+
+    /// Fade the output out over `duration`, then drain and stop the stream
+    /// as [`Stream::stop_after_drain`] does.
+    ///
+    /// The data callback keeps being called during the fade. The fade is
+    /// cancelled if the stream is started again. Returns
+    /// `Error::NotSupported` for input-only streams and unless the gain is
+    /// enabled with [`StreamBuilder::gain`].
+    pub fn fade_out_and_stop(&self, duration: Duration) -> Result<bool> {
+        let frames = self.gain()?.frames(duration);
+        self.drain(u64::from(frames), duration + DRAIN_TIMEOUT)
+    }
+
+    fn drain(&self, frames: u64, timeout: Duration) -> Result<bool> {
+        let shared = &self.shared;
+        if self.state() != StreamState::Started {
+            // Nothing is playing, so there's nothing to drain.
+            self.stop()?;
+            return Ok(true);
+        }
+        shared.request_drain(frames);
+        let state = shared.wait_for_end(timeout);
+        shared.drain.store(NO_DRAIN, Ordering::Release);
+        if state == Some(State::Error) {
+            return Err(Error::Error);
+        }
+        self.stop()?;
+        Ok(state.is_some())
+    }
+
+    /// Round-trip latency of a duplex stream, in frames: the sum of
+    /// [`input_latency`](crate::StreamRef::input_latency) and
+    /// [`latency`](crate::StreamRef::latency), including the latency added by the
+    /// resampler when using [`StreamBuilder::user_rate`].
+    ///
+    /// This is the delay between writing a frame to the output and
+    /// receiving its echo in the input.
+    pub fn roundtrip_latency(&self) -> Result<u32> {
+        let output = self.stream.latency()?;
+        let input = self.stream.input_latency()?;
+        let resampler = self.shared.resampler_latency.load(Ordering::Relaxed);
+        Ok(output + input + resampler)
+    }
+
+    /// Input processing effects enabled by
+    /// [`StreamBuilder::input_processing`], `None` if none were requested.
+    pub fn input_processing(&self) -> Option<InputProcessingReport> {
+        self.input_processing
+    }
+
+    fn gain(&self) -> Result<&GainControl> {
+        self.shared.gain.as_ref().ok_or(Error::NotSupported)
+    }
+
+    // Latency queries may fail or be unsupported, in which case the
+    // corresponding `CallbackInfo` timestamps are left empty.
+    fn update_latency(&self) {
+        let resampler_latency = self.shared.resampler_latency.load(Ordering::Relaxed);
+        let latency = self
+            .stream
+            .latency()
+            .map_or(UNKNOWN_LATENCY, |latency| latency + resampler_latency);
+        self.shared.output_latency.store(latency, Ordering::Release);
+        let latency = self.stream.input_latency().unwrap_or(UNKNOWN_LATENCY);
+        self.shared.input_latency.store(latency, Ordering::Release);
+    }
+}
+
+impl<F: Frame + 'static> Stream<F> {
+    /// Call `tap` with copies of the input or output audio of the stream,
+    /// without slowing down the data callback.
+    ///
+    /// Audio is copied into a lock-free queue by the callback trampoline
+    /// and delivered to the taps on a worker thread, in chunks of up to
+    /// 4096 frames. If the taps fall behind by more than about a second of
+    /// audio, the frames which don't fit are dropped. The input is tapped
+    /// as passed to the data callback and the output after the stream
+    /// volume is applied.
+    ///
+    /// Fails with [`Error::InvalidParameter`] if the stream has no such
+    /// direction or taps weren't enabled with [`StreamBuilder::taps`].
+    pub fn add_tap<T>(&self, direction: Direction, tap: T) -> Result<TapId>
+    where
+        T: FnMut(&[F]) + Send + 'static,
+    {
+        self.taps.add(direction, Box::new(tap))
+    }
+
+    /// Remove a tap added with [`Stream::add_tap`], returning whether it
+    /// existed.
+    pub fn remove_tap(&self, id: TapId) -> bool {
+        self.taps.remove(id)
+    }
+
+    /// Record the output of the stream to a new file at `path`, or its
+    /// input for input-only streams.
+    ///
+    /// The audio is encoded on the tap worker thread, see
+    /// [`Stream::add_tap`]. The file header is finalized by
+    /// [`Stream::stop_recording`], or when the stream is dropped, including
+    /// after the stream stopped with an error.
+    ///
+    /// Fails with [`Error::InvalidParameter`] if the stream is already
+    /// recording or taps weren't enabled with [`StreamBuilder::taps`], and
+    /// with [`Error::Error`] if the file can't be created.
+    pub fn start_recording<P: AsRef<Path>>(&self, path: P, format: RecordFormat) -> Result<()> {
+        let mut recording = self.recording.lock().unwrap();
+        if recording.is_some() {
+            return Err(Error::InvalidParameter);
+        }
+        *recording = Some(Recording::start(&self.taps, path.as_ref(), format)?);
+        Ok(())
+    }
+
+    /// Stop the recording started with [`Stream::start_recording`] and
+    /// finalize the file, after writing the audio queued so far.
+    ///
+    /// Fails with [`Error::InvalidParameter`] if the stream isn't
+    /// recording, and with [`Error::Error`] if writing the file failed.
+    pub fn stop_recording(&self) -> Result<()> {
+        let recording = self.recording.lock().unwrap().take();
+        let recording = recording.ok_or(Error::InvalidParameter)?;
+        recording.stop(&self.taps)
     }
 }
 
 impl<F> Drop for Stream<F> {
     fn drop(&mut self) {
         let user_ptr = self.user_ptr();
-        unsafe { ManuallyDrop::drop(&mut self.0) };
+        unsafe { ManuallyDrop::drop(&mut self.stream) };
         let _ = unsafe { Box::from_raw(user_ptr as *mut StreamCallbacks<F>) };
+        // Deliver the audio queued before the stream was destroyed.
+        self.taps.shutdown();
+        if let Some(recording) = self.recording.get_mut().unwrap().take() {
+            let _ = recording.finish();
+        }
     }
 }
 
@@ -126,7 +1035,7 @@ impl<F> ops::Deref for Stream<F> {
     type Target = cubeb_core::Stream;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.stream
     }
 }
 
@@ -182,9 +1091,57 @@ pub struct StreamBuilder<'a, F> {
     input: Option<(DeviceId, &'a StreamParamsRef)>,
     output: Option<(DeviceId, &'a StreamParamsRef)>,
     latency: Option<u32>,
-    data_cb: Option<Box<DataCallback<F>>>,
+    data_cb: Option<Box<InfoDataCallback<F>>>,
+    aligned_cb: Option<Box<AlignedDataCallback<F>>>,
     state_cb: Option<Box<StateCallback>>,
     device_changed_cb: Option<Box<DeviceChangedCallback>>,
+    instrument: bool,
+    meters: bool,
+    detect_glitches: bool,
+    glitch_cb: Option<Box<GlitchCallback>>,
+    pause_mode: PauseMode,
+    user_rate: Option<u32>,
+    user_layout: Option<ChannelLayout>,
+    input_processing: Option<(InputProcessingParams, ProcessingPolicy)>,
+    input_processor: Option<Box<dyn VoiceProcessor>>,
+    gain: bool,
+    taps: bool,
+    frames: Option<FrameStages<F>>,
+}
+
+// Constructors of the processing stages which need `F: Frame`, captured by
+// the builder methods enabling them so that `init` works with any `F`.
+struct FrameStages<F> {
+    channels: usize,
+    data_callback: ffi::cubeb_data_callback,
+    remapper: RemapperNew<F>,
+    delay_line: fn(usize, usize) -> DelayLine<F>,
+    input_processor: InputProcessorNew<F>,
+}
+
+type RemapperNew<F> = fn(
+    ChannelLayout,
+    Option<&StreamParamsRef>,
+    Option<&StreamParamsRef>,
+    usize,
+) -> Result<Option<Remapper<F>>>;
+type InputProcessorNew<F> = fn(
+    Option<Box<dyn VoiceProcessor>>,
+    InputProcessingParams,
+    u32,
+    usize,
+) -> Option<InputProcessor<F>>;
+
+impl<F: Frame> FrameStages<F> {
+    fn new() -> FrameStages<F> {
+        FrameStages {
+            channels: F::CHANNELS,
+            data_callback: Some(data_cb_c::<F>),
+            remapper: Remapper::new,
+            delay_line: DelayLine::new,
+            input_processor: InputProcessor::new,
+        }
+    }
 }
 
 impl<'a, F> StreamBuilder<'a, F> {
@@ -193,11 +1150,28 @@ impl<'a, F> StreamBuilder<'a, F> {
     }
 
     /// User supplied data callback, see [`DataCallback`]
-    pub fn data_callback<D>(&mut self, cb: D) -> &mut Self
+    pub fn data_callback<D>(&mut self, mut cb: D) -> &mut Self
     where
         D: FnMut(&[F], &mut [F]) -> isize + Send + Sync + 'static,
     {
-        self.data_cb = Some(Box::new(cb) as Box<DataCallback<F>>);
+        self.data_cb = Some(
+            Box::new(move |input: &[F], output: &mut [F], _: &CallbackInfo| cb(input, output))
+                as Box<InfoDataCallback<F>>,
+        );
+        self.aligned_cb = None;
+        self
+    }
+
+    /// User supplied data callback receiving [`CallbackInfo`], see
+    /// [`InfoDataCallback`]
+    ///
+    /// Replaces any callback set with [`data_callback`](Self::data_callback).
+    pub fn data_callback_with_info<D>(&mut self, cb: D) -> &mut Self
+    where
+        D: FnMut(&[F], &mut [F], &CallbackInfo) -> isize + Send + Sync + 'static,
+    {
+        self.data_cb = Some(Box::new(cb) as Box<InfoDataCallback<F>>);
+        self.aligned_cb = None;
         self
     }
 
@@ -269,17 +1243,306 @@ impl<'a, F> StreamBuilder<'a, F> {
         self
     }
 
+    /// Record data callback performance counters, readable with
+    /// [`Stream::stats`].
+    ///
+    /// Optional, disabled by default.
+    pub fn instrument(&mut self, enable: bool) -> &mut Self {
+        self.instrument = enable;
+        self
+    }
+
+    /// Detect probable underruns and overruns, counted in
+    /// [`Stream::glitches`].
+    ///
+    /// Glitches are detected from the arrival times of data callbacks, and
+    /// from the stream position by [`Stream::check_position`].
+    ///
+    /// Optional, disabled by default.
+    pub fn detect_glitches(&mut self, enable: bool) -> &mut Self {
+        self.detect_glitches = enable;
+        self
+    }
+
+    /// User supplied callback notified of glitches detected from the
+    /// callback cadence, see [`GlitchCallback`]. Enables glitch detection.
+    ///
+    /// Optional
+    pub fn glitch_callback<G>(&mut self, cb: G) -> &mut Self
+    where
+        G: FnMut(GlitchEvent) + Send + Sync + 'static,
+    {
+        self.detect_glitches = true;
+        self.glitch_cb = Some(Box::new(cb) as Box<GlitchCallback>);
+        self
+    }
+
+    /// How [`Stream::pause`] pauses the stream.
+    ///
+    /// Optional, defaults to [`PauseMode::HardStop`].
+    pub fn pause_mode(&mut self, mode: PauseMode) -> &mut Self {
+        self.pause_mode = mode;
+        self
+    }
+
+    /// Call the data callback with frames at `rate`, converting from and to
+    /// the rates of the input and output parameters with libcubeb's
+    /// resampler.
+    ///
+    /// [`CallbackInfo::frames`], the stream volume controls and the
+    /// scheduled start frames are at `rate`, while the stream position and
+    /// latencies remain at the stream rate.
+    ///
+    /// Optional, defaults to the stream rate.
+    pub fn user_rate(&mut self, rate: u32) -> &mut Self {
+        self.user_rate = Some(rate);
+        self
+    }
+}
+
+impl<F: Frame> StreamBuilder<'_, F> {
+    /// User supplied data callback for duplex streams, see
+    /// [`AlignedDataCallback`]
+    ///
+    /// The callback receives, along with each input frame, the output
+    /// frame that was playing when it was captured, i.e. the output
+    /// delayed by the [round-trip latency](Stream::roundtrip_latency).
+    /// This is the reference signal needed for echo cancellation. The
+    /// reference is the output as played, after the stream volume is
+    /// applied, and is silent until the latencies are known and for
+    /// round-trip latencies longer than one second.
+    ///
+    /// The stream must have both an input and an output. Replaces any
+    /// callback set with [`data_callback`](Self::data_callback).
+    pub fn aligned_data_callback<D>(&mut self, cb: D) -> &mut Self
+    where
+        D: FnMut(&[F], &[F], &mut [F], &CallbackInfo) -> isize + Send + Sync + 'static,
+    {
+        self.aligned_cb = Some(Box::new(cb) as Box<AlignedDataCallback<F>>);
+        self.data_cb = None;
+        self.frame_stages()
+    }
+
+    /// Measure the peak, RMS and EBU R128 loudness of the input and output
+    /// buffers, readable with [`Stream::meters`].
+    ///
+    /// The input is measured as passed to the data callback and the output
+    /// after the stream volume is applied.
+    ///
+    /// Optional, disabled by default.
+    pub fn meters(&mut self, enable: bool) -> &mut Self {
+        self.meters = enable;
+        self.frame_stages()
+    }
+
+    /// Apply the volume and channel gains set with
+    /// [`Stream::ramp_volume`], [`Stream::mute`] and
+    /// [`Stream::set_channel_gain`] to the output.
+    ///
+    /// Optional, disabled by default.
+    pub fn gain(&mut self, enable: bool) -> &mut Self {
+        self.gain = enable;
+        self.frame_stages()
+    }
+
+    /// Let [`Stream::add_tap`] and [`Stream::start_recording`] copy the
+    /// audio of the stream.
+    ///
+    /// Optional, disabled by default.
+    pub fn taps(&mut self, enable: bool) -> &mut Self {
+        self.taps = enable;
+        self.frame_stages()
+    }
+
+    /// Call the data callback with frames in `layout`, up or down-mixing
+    /// from and to the layouts of the input and output parameters with
+    /// libcubeb's mixer.
+    ///
+    /// This lets the data callback use, e.g., [`StereoFrame`](crate::StereoFrame)
+    /// while the stream is opened with the native layout of the device.
+    /// The stream is still opened with the input and output parameters,
+    /// so the caller picks that layout, e.g. with
+    /// [`DeviceInfo::max_channels`](crate::DeviceInfo::max_channels)
+    /// channels. `layout` must have as many channels as `F`.
+    ///
+    /// Optional, defaults to the layouts of the stream parameters.
+    pub fn user_layout(&mut self, layout: ChannelLayout) -> &mut Self {
+        self.user_layout = Some(layout);
+        self.frame_stages()
+    }
+
+    /// Enable the `requested` input processing effects, such as echo
+    /// cancellation, intersected with the effects supported by the
+    /// backend. `policy` decides what happens to the unsupported ones.
+    ///
+    /// The effects actually enabled are reported by
+    /// [`Stream::input_processing`]. The stream must have an input.
+    pub fn input_processing(
+        &mut self,
+        requested: InputProcessingParams,
+        policy: ProcessingPolicy,
+    ) -> &mut Self {
+        self.input_processing = Some((requested, policy));
+        self.frame_stages()
+    }
+
+    /// Run `processor` on the input before the data callback, see
+    /// [`VoiceProcessor`]. For duplex streams, the processor receives the
+    /// output delayed by the [round-trip latency](Stream::roundtrip_latency)
+    /// as its echo cancellation reference.
+    ///
+    /// Runs before the software effects of
+    /// [`input_processing`](Self::input_processing). The stream must have
+    /// an input.
+    pub fn input_processor<P>(&mut self, processor: P) -> &mut Self
+    where
+        P: VoiceProcessor + 'static,
+    {
+        self.input_processor = Some(Box::new(processor));
+        self.frame_stages()
+    }
+
+    // Capture the constructors of the stages which need `F: Frame`.
+    fn frame_stages(&mut self) -> &mut Self {
+        self.frames.get_or_insert_with(FrameStages::new);
+        self
+    }
+}
+
+impl<F> StreamBuilder<'_, F> {
     /// Build the stream
+    ///
+    /// `F` can be any type laid out as the frames of the stream format.
+    /// The builder methods requiring `F: Frame`, such as
+    /// [`gain`](Self::gain) and [`meters`](Self::meters), enable
+    /// processing stages which look into the frames; streams built
+    /// without them only silence the output while paused, draining or
+    /// waiting for a scheduled start.
     pub fn init(self, ctx: &ContextRef) -> Result<Stream<F>> {
-        if self.data_cb.is_none() || self.state_cb.is_none() {
+        if (self.data_cb.is_none() && self.aligned_cb.is_none()) || self.state_cb.is_none() {
             return Err(Error::Error);
         }
+        if self.aligned_cb.is_some() && (self.input.is_none() || self.output.is_none()) {
+            return Err(Error::InvalidParameter);
+        }
+
+        if self.user_rate == Some(0) {
+            return Err(Error::InvalidParameter);
+        }
+        if (self.input_processing.is_some() || self.input_processor.is_some())
+            && self.input.is_none()
+        {
+            return Err(Error::InvalidParameter);
+        }
+        let mut processing = match self.input_processing {
+            Some((requested, policy)) => {
+                let supported = ctx
+                    .supported_input_processing_params()
+                    .unwrap_or(InputProcessingParams::NONE);
+                Some(negotiate(requested, supported, policy)?)
+            }
+            None => None,
+        };
 
         let has_device_changed = self.device_changed_cb.is_some();
+        let stream_rate = self
+            .output
+            .or(self.input)
+            .map_or(0, |(_, params)| params.rate());
+        let rate = self.user_rate.unwrap_or(stream_rate);
+        let resample = [self.input, self.output]
+            .iter()
+            .flatten()
+            .any(|(_, params)| params.rate() != rate);
+        let frames = self.frames;
+        let channels = frames.as_ref().map_or(0, |frames| frames.channels);
+        let gain = self
+            .output
+            .filter(|_| self.gain)
+            .map(|_| GainControl::new(rate, channels));
+        let gain_stage = gain.as_ref().map(GainStage::new);
+        let mut shared = StreamShared::new(self.instrument, self.detect_glitches, gain);
+        let meter = |params: Option<(DeviceId, &StreamParamsRef)>| {
+            params
+                .filter(|_| self.meters)
+                .map(|_| (Meter::new(channels, rate), MeterLevels::new(channels)))
+                .unzip()
+        };
+        let (input_meter, input_levels) = meter(self.input);
+        let (output_meter, output_levels) = meter(self.output);
+        shared.input_meter = input_levels;
+        shared.output_meter = output_levels;
+        let shared = Arc::new(shared);
+        let remap = match (self.user_layout, &frames) {
+            (Some(layout), Some(frames)) => (frames.remapper)(
+                layout,
+                self.input.map(|(_, params)| params),
+                self.output.map(|(_, params)| params),
+                REMAP_FRAMES.max(self.latency.unwrap_or(0) as usize * 2),
+            )?,
+            _ => None,
+        };
+        let glitch_kind = if self.output.is_some() {
+            GlitchKind::Underrun
+        } else {
+            GlitchKind::Overrun
+        };
+        let glitch = self.detect_glitches.then(|| GlitchDetection {
+            cadence: CadenceDetector::new(glitch_kind, rate),
+            callback: self.glitch_cb,
+        });
+        let position_detector = self
+            .detect_glitches
+            .then(|| PositionDetector::new(glitch_kind, stream_rate, rate));
+        let duplex = self.input.is_some() && self.output.is_some();
+        let reference = frames
+            .as_ref()
+            .filter(|_| self.aligned_cb.is_some() || (duplex && self.input_processor.is_some()))
+            .map(|frames| {
+                (frames.delay_line)(
+                    (MAX_ROUNDTRIP.as_secs_f64() * f64::from(rate)) as usize,
+                    REMAP_FRAMES.max(self.latency.unwrap_or(0) as usize * 2),
+                )
+            });
+        let input_processing = frames.as_ref().and_then(|frames| {
+            (frames.input_processor)(
+                self.input_processor,
+                processing.map_or(InputProcessingParams::NONE, |report| report.software),
+                rate,
+                REMAP_FRAMES,
+            )
+        });
+        let (taps, tap_writer) = taps(
+            rate,
+            self.taps && self.input.is_some(),
+            self.taps && self.output.is_some(),
+        );
+        let data = match self.aligned_cb {
+            Some(cb) => UserCallback::Aligned(cb),
+            None => UserCallback::Data(self.data_cb.unwrap()),
+        };
         let cbs = Box::into_raw(Box::new(StreamCallbacks {
-            data: self.data_cb.unwrap(),
+            data,
             state: self.state_cb.unwrap(),
             device_changed: self.device_changed_cb,
+            timing: CallbackTiming::new(
+                shared.clone(),
+                rate,
+                stream_rate,
+                self.input.is_some(),
+                self.output.is_some(),
+            ),
+            glitch,
+            gain: gain_stage,
+            drain: None,
+            delay: None,
+            resampler: ptr::null(),
+            remap,
+            input_processing,
+            reference,
+            input_meter,
+            output_meter,
+            taps: tap_writer,
         }));
 
         let stream_name = self.name.as_deref();
@@ -289,7 +1552,15 @@ impl<'a, F> StreamBuilder<'a, F> {
             .output
             .map_or((ptr::null(), None), |x| (x.0, Some(x.1)));
         let latency = self.latency.unwrap_or(1);
-        let data_callback: ffi::cubeb_data_callback = Some(data_cb_c::<F>);
+        let stage_callback: ffi::cubeb_data_callback = match &frames {
+            Some(frames) => frames.data_callback,
+            None => Some(plain_data_cb_c::<F>),
+        };
+        let data_callback: ffi::cubeb_data_callback = if resample {
+            Some(resampled_data_cb_c::<F>)
+        } else {
+            stage_callback
+        };
         let state_callback: ffi::cubeb_state_callback = Some(state_cb_c::<F>);
 
         let stream = unsafe {
@@ -305,12 +1576,75 @@ impl<'a, F> StreamBuilder<'a, F> {
                 cbs as *mut _,
             )?
         };
+        let mut resampler = None;
+        if resample {
+            // The stream isn't started yet, so the callbacks can't be
+            // running.
+            let created = unsafe {
+                Resampler::new(
+                    stream.as_ptr(),
+                    input_stream_params,
+                    output_stream_params,
+                    rate,
+                    stage_callback,
+                    cbs as *mut _,
+                )
+            };
+            match created {
+                Ok(created) => {
+                    shared
+                        .resampler_latency
+                        .store(created.latency(), Ordering::Relaxed);
+                    let created = Box::new(created);
+                    unsafe { (*cbs).resampler = &*created };
+                    resampler = Some(created);
+                }
+                Err(e) => {
+                    drop(stream);
+                    let _ = unsafe { Box::from_raw(cbs) };
+                    return Err(e);
+                }
+            }
+        }
+        if let Some(report) = processing.filter(|report| !report.native.is_empty()) {
+            if let Err(e) = stream.set_input_processing_params(report.native) {
+                // Fall back as if the backend supported nothing.
+                let (requested, policy) = self.input_processing.unwrap();
+                let Ok(report) = negotiate(requested, InputProcessingParams::NONE, policy) else {
+                    drop(stream);
+                    let _ = unsafe { Box::from_raw(cbs) };
+                    return Err(e);
+                };
+                // The stream isn't started yet, so the callbacks can't be
+                // running. Input processing was requested, so the frame
+                // stages were captured.
+                if let Some(frames) = &frames {
+                    unsafe {
+                        let voice = (*cbs)
+                            .input_processing
+                            .take()
+                            .and_then(InputProcessor::into_voice);
+                        (*cbs).input_processing =
+                            (frames.input_processor)(voice, report.software, rate, REMAP_FRAMES);
+                    }
+                }
+                processing = Some(report);
+            }
+        }
         if has_device_changed {
             let device_changed_callback: ffi::cubeb_device_changed_callback =
                 Some(device_changed_cb_c::<F>);
             stream.register_device_changed_callback(device_changed_callback)?;
         }
-        Ok(Stream::new(stream))
+        Ok(Stream::new(
+            stream,
+            shared,
+            position_detector,
+            self.pause_mode,
+            processing,
+            taps,
+            resampler,
+        ))
     }
 }
 
@@ -322,14 +1656,52 @@ impl<F> Default for StreamBuilder<'_, F> {
             output: None,
             latency: None,
             data_cb: None,
+            aligned_cb: None,
             state_cb: None,
             device_changed_cb: None,
+            instrument: false,
+            meters: false,
+            detect_glitches: false,
+            glitch_cb: None,
+            pause_mode: PauseMode::default(),
+            user_rate: None,
+            user_layout: None,
+            input_processing: None,
+            input_processor: None,
+            gain: false,
+            taps: false,
+            frames: None,
         }
     }
 }
 
+// Silence `frames`, which hold samples of the stream format, all zero
+// bits being silence in every format.
+fn silence<F>(frames: &mut [F]) {
+    unsafe { ptr::write_bytes(frames.as_mut_ptr(), 0, frames.len()) };
+}
+
+// The frames of the buffers passed to a data callback.
+unsafe fn buffers<'a, F>(
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: c_long,
+) -> (&'a [F], &'a mut [F]) {
+    let input: &[F] = if input_buffer.is_null() {
+        &[]
+    } else {
+        from_raw_parts(input_buffer as *const _, nframes as usize)
+    };
+    let output: &mut [F] = if output_buffer.is_null() {
+        &mut []
+    } else {
+        from_raw_parts_mut(output_buffer as *mut _, nframes as usize)
+    };
+    (input, output)
+}
+
 // C callable callbacks
-unsafe extern "C" fn data_cb_c<F>(
+unsafe extern "C" fn data_cb_c<F: Frame>(
     _: *mut ffi::cubeb_stream,
     user_ptr: *mut c_void,
     input_buffer: *const c_void,
@@ -338,17 +1710,132 @@ unsafe extern "C" fn data_cb_c<F>(
 ) -> c_long {
     let ok = panic::catch_unwind(|| {
         let cbs = &mut *(user_ptr as *mut StreamCallbacks<F>);
-        let input: &[F] = if input_buffer.is_null() {
-            &[]
-        } else {
-            from_raw_parts(input_buffer as *const _, nframes as usize)
+        let mut remap = Taken::new(cbs, |cbs| &mut cbs.remap);
+        let Taken { cbs, value, .. } = &mut remap;
+        let Some(remap) = value else {
+            let (input, output) = buffers(input_buffer, output_buffer, nframes);
+            return process(cbs, input, output, nframes);
         };
-        let output: &mut [F] = if output_buffer.is_null() {
-            &mut []
-        } else {
-            from_raw_parts_mut(output_buffer as *mut _, nframes as usize)
-        };
-        (cbs.data)(input, output) as c_long
+        let (input, output) = remap.buffers(input_buffer, output_buffer, nframes as usize);
+        let returned = process(cbs, input, output, nframes);
+        let written = returned.clamp(0, nframes) as usize;
+        remap.write_output(output_buffer, nframes as usize, written);
+        returned
+    });
+    ok.unwrap_or(0)
+}
+
+// Run the data callback and the processing stages around it.
+fn process<F: Frame>(
+    cbs: &mut StreamCallbacks<F>,
+    input: &[F],
+    output: &mut [F],
+    nframes: c_long,
+) -> c_long {
+    let info = cbs.begin(nframes as usize);
+    if let Some(delay) = &mut cbs.reference {
+        if let Some(roundtrip) = cbs.timing.roundtrip() {
+            delay.set_delay(roundtrip);
+        }
+        delay.prepare(nframes as usize);
+    }
+    let mut processor = Taken::new(cbs, |cbs| &mut cbs.input_processing);
+    let Taken {
+        cbs,
+        value: processor,
+        ..
+    } = &mut processor;
+    let input = match processor {
+        Some(processor) => {
+            let reference = cbs.reference.as_ref().map_or(&[][..], DelayLine::reference);
+            processor.process(input, reference)
+        }
+        None => input,
+    };
+    if let (Some(meter), Some(levels)) = (&mut cbs.input_meter, &cbs.timing.shared.input_meter) {
+        meter.process(input, levels);
+    }
+    cbs.taps.write(Direction::Input, input);
+    let returned = cbs.render_unless_paused(input, output, &info);
+    if let (Some(stage), Some(control)) = (&mut cbs.gain, &cbs.timing.shared.gain) {
+        let written = returned.clamp(0, output.len() as isize) as usize;
+        stage.process(control, &mut output[..written]);
+    }
+    if let (Some(meter), Some(levels)) = (&mut cbs.output_meter, &cbs.timing.shared.output_meter) {
+        let written = returned.clamp(0, output.len() as isize) as usize;
+        meter.process(&output[..written], levels);
+    }
+    let written = returned.clamp(0, output.len() as isize) as usize;
+    cbs.taps.write(Direction::Output, &output[..written]);
+    if let Some(delay) = &mut cbs.reference {
+        let written = returned.clamp(0, output.len() as isize) as usize;
+        delay.write(&output[..written]);
+    }
+    cbs.timing.finish(nframes as usize, returned);
+    returned as c_long
+}
+
+// Data callback of streams built without the processing stages which
+// need `F: Frame`, see `FrameStages`.
+unsafe extern "C" fn plain_data_cb_c<F>(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: c_long,
+) -> c_long {
+    let ok = panic::catch_unwind(|| {
+        let cbs = &mut *(user_ptr as *mut StreamCallbacks<F>);
+        let (input, output) = buffers(input_buffer, output_buffer, nframes);
+        let info = cbs.begin(nframes as usize);
+        let returned = cbs.render_unless_paused(input, output, &info);
+        cbs.timing.finish(nframes as usize, returned);
+        returned as c_long
+    });
+    ok.unwrap_or(0)
+}
+
+// A stage taken out of the callbacks while the frames it borrows are
+// processed, put back even if the data callback panics.
+struct Taken<'a, F, T> {
+    cbs: &'a mut StreamCallbacks<F>,
+    value: Option<T>,
+    slot: fn(&mut StreamCallbacks<F>) -> &mut Option<T>,
+}
+
+impl<'a, F, T> Taken<'a, F, T> {
+    fn new(
+        cbs: &'a mut StreamCallbacks<F>,
+        slot: fn(&mut StreamCallbacks<F>) -> &mut Option<T>,
+    ) -> Self {
+        let value = slot(cbs).take();
+        Taken { cbs, value, slot }
+    }
+}
+
+impl<F, T> Drop for Taken<'_, F, T> {
+    fn drop(&mut self) {
+        *(self.slot)(self.cbs) = self.value.take();
+    }
+}
+
+// Data callback used with `StreamBuilder::user_rate`, which calls
+// `data_cb_c` through the resampler.
+unsafe extern "C" fn resampled_data_cb_c<F>(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input_buffer: *const c_void,
+    output_buffer: *mut c_void,
+    nframes: c_long,
+) -> c_long {
+    let ok = panic::catch_unwind(|| {
+        // Only copy the pointer out of the callbacks, which `data_cb_c`
+        // borrows mutably while the resampler runs.
+        let resampler = (*(user_ptr as *const StreamCallbacks<F>)).resampler;
+        match resampler.as_ref() {
+            Some(resampler) => resampler.fill(input_buffer, output_buffer, nframes),
+            None => 0,
+        }
     });
     ok.unwrap_or(0)
 }
@@ -361,6 +1848,7 @@ unsafe extern "C" fn state_cb_c<F>(
     let ok = panic::catch_unwind(|| {
         let state = State::from(state);
         let cbs = &mut *(user_ptr as *mut StreamCallbacks<F>);
+        cbs.timing.shared.set_state(state);
         (cbs.state)(state);
     });
     ok.expect("State callback panicked");
@@ -375,3 +1863,499 @@ unsafe extern "C" fn device_changed_cb_c<F>(user_ptr: *mut c_void) {
     });
     ok.expect("Device changed callback panicked");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonoFrame, SampleFormat, StereoFrame, StreamParams, StreamParamsBuilder};
+    use std::sync::Mutex;
+
+    type Frame = MonoFrame<f32>;
+
+    fn callbacks(
+        data: Box<InfoDataCallback<Frame>>,
+        shared: &Arc<StreamShared>,
+    ) -> Box<StreamCallbacks<Frame>> {
+        callbacks_of(data, shared)
+    }
+
+    fn callbacks_of<F>(
+        data: Box<InfoDataCallback<F>>,
+        shared: &Arc<StreamShared>,
+    ) -> Box<StreamCallbacks<F>> {
+        Box::new(StreamCallbacks {
+            data: UserCallback::Data(data),
+            state: Box::new(|_| {}),
+            device_changed: None,
+            timing: CallbackTiming::new(shared.clone(), 48_000, 48_000, false, true),
+            glitch: shared.glitches.as_ref().map(|_| GlitchDetection {
+                cadence: CadenceDetector::new(GlitchKind::Underrun, 48_000),
+                callback: None,
+            }),
+            gain: shared.gain.as_ref().map(GainStage::new),
+            drain: None,
+            delay: None,
+            resampler: ptr::null(),
+            remap: None,
+            input_processing: None,
+            reference: None,
+            input_meter: None,
+            output_meter: None,
+            taps: taps(48_000, false, true).1,
+        })
+    }
+
+    fn run(cbs: &mut StreamCallbacks<Frame>, output: &mut [Frame]) -> c_long {
+        unsafe {
+            data_cb_c::<Frame>(
+                ptr::null_mut(),
+                cbs as *mut _ as *mut c_void,
+                ptr::null(),
+                output.as_mut_ptr() as *mut c_void,
+                output.len() as c_long,
+            )
+        }
+    }
+
+    #[test]
+    fn stages_survive_panics() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let mut cbs = callbacks(Box::new(|_, _, _| panic!("user callback")), &shared);
+        cbs.input_processing = InputProcessor::new(
+            None,
+            InputProcessingParams::AUTOMATIC_GAIN_CONTROL,
+            48_000,
+            REMAP_FRAMES,
+        );
+        let mut output = [Frame { m: 0.0 }; 16];
+        assert_eq!(run(&mut cbs, &mut output), 0);
+        assert!(cbs.input_processing.is_some());
+    }
+
+    #[test]
+    fn data_callback_info() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let log = infos.clone();
+        let mut cbs = callbacks(
+            Box::new(move |input, output, info| {
+                assert!(input.is_empty());
+                log.lock().unwrap().push(*info);
+                output.len() as isize
+            }),
+            &shared,
+        );
+        let mut output = [Frame { m: 0.0 }; 128];
+
+        assert_eq!(run(&mut cbs, &mut output), 128);
+        shared.starting.store(true, Ordering::Release);
+        shared.output_latency.store(480, Ordering::Release);
+        let before = Instant::now();
+        assert_eq!(run(&mut cbs, &mut output[..64]), 64);
+        assert_eq!(run(&mut cbs, &mut output), 128);
+
+        let infos = infos.lock().unwrap();
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos[0].index, 0);
+        assert_eq!(infos[0].output_presentation_time, None);
+        assert!(!infos[0].first_after_start);
+        assert_eq!(infos[1].index, 1);
+        assert_eq!(infos[1].frames, 64);
+        assert!(infos[1].first_after_start);
+        assert!(infos[1].output_presentation_time.unwrap() >= before + Duration::from_millis(10));
+        assert_eq!(infos[1].input_capture_time, None);
+        assert!(!infos[2].first_after_start);
+    }
+
+    #[test]
+    fn data_callback_stats() {
+        let shared = Arc::new(StreamShared::new(true, false, None));
+        let mut cbs = callbacks(
+            Box::new(|_, output, info| {
+                if info.index == 1 {
+                    std::thread::sleep(Duration::from_millis(5));
+                    return 0;
+                }
+                output.len() as isize
+            }),
+            &shared,
+        );
+        let mut output = [Frame { m: 0.0 }; 48];
+
+        for _ in 0..3 {
+            run(&mut cbs, &mut output);
+        }
+
+        let stats = shared.stats.as_ref().unwrap().snapshot();
+        assert_eq!(stats.callbacks, 3);
+        assert_eq!(stats.short_returns, 1);
+        // 48 frames at 48kHz gives a 1ms deadline.
+        assert_eq!(stats.deadline_misses, 1);
+        assert_eq!(stats.durations.count, 3);
+        assert_eq!(stats.intervals.count, 2);
+        assert!(stats.durations.max >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn data_callback_glitches() {
+        let shared = Arc::new(StreamShared::new(false, true, None));
+        let mut cbs = callbacks(Box::new(|_, output, _| output.len() as isize), &shared);
+        let mut output = [Frame { m: 0.0 }; 48];
+
+        // 48 frames at 48kHz is a 1ms period.
+        run(&mut cbs, &mut output);
+        std::thread::sleep(Duration::from_millis(20));
+        run(&mut cbs, &mut output);
+
+        let glitches = shared.glitches.as_ref().unwrap().snapshot();
+        assert_eq!(glitches.underruns, 1);
+        assert!(glitches.frames_lost >= 18 * 48);
+        assert_eq!(shared.period.load(Ordering::Relaxed), 48);
+    }
+
+    #[test]
+    fn data_callback_gain() {
+        let shared = Arc::new(StreamShared::new(
+            false,
+            false,
+            Some(GainControl::new(48_000, 1)),
+        ));
+        let mut cbs = callbacks(
+            Box::new(|_, output, _| {
+                for f in output.iter_mut() {
+                    f.m = 1.0;
+                }
+                output.len() as isize - 1
+            }),
+            &shared,
+        );
+        let control = shared.gain.as_ref().unwrap();
+        control.ramp_volume(0.5, Duration::ZERO);
+        let mut output = [Frame { m: 0.0 }; 4];
+        run(&mut cbs, &mut output);
+        // Only the frames returned by the callback are scaled.
+        assert_eq!(output.map(|f| f.m), [0.5, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn data_callback_fade_out_and_drain() {
+        let shared = Arc::new(StreamShared::new(
+            false,
+            false,
+            Some(GainControl::new(48_000, 1)),
+        ));
+        let mut cbs = callbacks(
+            Box::new(|_, output, _| {
+                for f in output.iter_mut() {
+                    f.m = 1.0;
+                }
+                output.len() as isize
+            }),
+            &shared,
+        );
+        let mut output = [Frame { m: 0.0 }; 4];
+        assert_eq!(run(&mut cbs, &mut output), 4);
+
+        shared.request_drain(6);
+        assert_eq!(run(&mut cbs, &mut output), 4);
+        let fade = [1.0, 5.0 / 6.0, 4.0 / 6.0, 0.5];
+        for (f, expected) in output.iter().zip(fade) {
+            assert!((f.m - expected).abs() < 1e-6);
+        }
+        // The fade ends mid-buffer and the rest is silent.
+        output = [Frame { m: 1.0 }; 4];
+        assert_eq!(run(&mut cbs, &mut output), 2);
+        assert!(output[2..].iter().all(|f| f.m == 0.0));
+
+        // Restarting cancels the drain.
+        shared.starting.store(true, Ordering::Release);
+        assert_eq!(run(&mut cbs, &mut output), 4);
+        assert!(output.iter().all(|f| f.m == 1.0));
+    }
+
+    #[test]
+    fn wait_for_drained() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        shared.set_state(State::Started);
+        shared.request_drain(0);
+        assert_eq!(shared.wait_for_end(Duration::from_millis(10)), None);
+
+        let notifier = shared.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            notifier.set_state(State::Drained);
+        });
+        assert_eq!(
+            shared.wait_for_end(Duration::from_secs(10)),
+            Some(State::Drained)
+        );
+        assert!(!shared.running.load(Ordering::Acquire));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn data_callback_silence_fill() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut cbs = callbacks(
+            Box::new(move |_, output, _| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                output.fill(Frame { m: 1.0 });
+                output.len() as isize
+            }),
+            &shared,
+        );
+        let mut output = [Frame { m: 1.0 }; 4];
+        shared.paused.store(true, Ordering::Release);
+        assert_eq!(run(&mut cbs, &mut output), 4);
+        assert!(output.iter().all(|f| f.m == 0.0));
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+
+        shared.paused.store(false, Ordering::Release);
+        assert_eq!(run(&mut cbs, &mut output), 4);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn data_callback_plain() {
+        // Not a `Frame`, so there are no processing stages.
+        type Raw = [i16; 2];
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let mut cbs = callbacks_of::<Raw>(
+            Box::new(|_, output, _| {
+                output.fill([1, -1]);
+                output.len() as isize
+            }),
+            &shared,
+        );
+        let mut run = |output: &mut [Raw]| unsafe {
+            plain_data_cb_c::<Raw>(
+                ptr::null_mut(),
+                &mut *cbs as *mut _ as *mut c_void,
+                ptr::null(),
+                output.as_mut_ptr() as *mut c_void,
+                output.len() as c_long,
+            )
+        };
+        let mut output = [[0; 2]; 4];
+        assert_eq!(run(&mut output), 4);
+        assert_eq!(output, [[1, -1]; 4]);
+
+        shared.paused.store(true, Ordering::Release);
+        assert_eq!(run(&mut output), 4);
+        assert_eq!(output, [[0; 2]; 4]);
+    }
+
+    fn float_params(channels: u32, layout: ChannelLayout) -> StreamParams {
+        StreamParamsBuilder::new()
+            .format(SampleFormat::Float32NE)
+            .rate(48_000)
+            .channels(channels)
+            .layout(layout)
+            .take()
+    }
+
+    #[test]
+    #[cfg_attr(feature = "gecko-in-tree", ignore)]
+    fn data_callback_remap_mono_to_stereo() {
+        let device = float_params(2, ChannelLayout::STEREO);
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let mut cbs = callbacks(
+            Box::new(|_, output, _| {
+                for (i, f) in output.iter_mut().enumerate() {
+                    f.m = 0.1 * (i + 1) as f32;
+                }
+                output.len() as isize - 1
+            }),
+            &shared,
+        );
+        cbs.remap = Remapper::new(ChannelLayout::MONO, None, Some(&device), REMAP_FRAMES).unwrap();
+        assert!(cbs.remap.is_some());
+        let mut output = [StereoFrame { l: 1.0f32, r: 1.0 }; 4];
+        let returned = unsafe {
+            data_cb_c::<Frame>(
+                ptr::null_mut(),
+                &mut *cbs as *mut _ as *mut c_void,
+                ptr::null(),
+                output.as_mut_ptr() as *mut c_void,
+                output.len() as c_long,
+            )
+        };
+        assert_eq!(returned, 3);
+        // Mono is played on both channels, scaled by the mixer.
+        let gain = output[0].l / 0.1;
+        assert!(gain > 0.0);
+        for (i, f) in output[..3].iter().enumerate() {
+            assert_eq!(f.l, f.r);
+            assert!((f.l - gain * 0.1 * (i + 1) as f32).abs() < 1e-6);
+        }
+        // The frames the callback didn't write are silent.
+        assert_eq!(output[3], StereoFrame { l: 0.0, r: 0.0 });
+    }
+
+    #[test]
+    #[cfg_attr(feature = "gecko-in-tree", ignore)]
+    fn data_callback_remap_stereo_to_mono() {
+        type Stereo = StereoFrame<f32>;
+        let device = float_params(1, ChannelLayout::MONO);
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let mut cbs = callbacks_of::<Stereo>(
+            Box::new(|_, output, _| {
+                output[0] = Stereo { l: 0.4, r: 0.0 };
+                output[1] = Stereo { l: 0.0, r: 0.4 };
+                output[2] = Stereo { l: 0.4, r: 0.4 };
+                3
+            }),
+            &shared,
+        );
+        cbs.remap =
+            Remapper::new(ChannelLayout::STEREO, None, Some(&device), REMAP_FRAMES).unwrap();
+        assert!(cbs.remap.is_some());
+        let mut output = [Frame { m: 1.0 }; 4];
+        let returned = unsafe {
+            data_cb_c::<Stereo>(
+                ptr::null_mut(),
+                &mut *cbs as *mut _ as *mut c_void,
+                ptr::null(),
+                output.as_mut_ptr() as *mut c_void,
+                output.len() as c_long,
+            )
+        };
+        assert_eq!(returned, 3);
+        // Both channels are mixed in equally.
+        assert!(output[0].m > 0.0);
+        assert_eq!(output[0].m, output[1].m);
+        assert!((output[2].m - 2.0 * output[0].m).abs() < 1e-6);
+        // The frames the callback didn't write are silent.
+        assert_eq!(output[3].m, 0.0);
+    }
+
+    #[test]
+    fn state_callback_drained() {
+        let shared = StreamShared::new(false, false, None);
+        shared.set_state(State::Drained);
+        // Only a started stream can drain.
+        assert_eq!(shared.stream_state.load(), StreamState::Created);
+        shared.stream_state.store(StreamState::Started);
+        shared.set_state(State::Stopped);
+        assert_eq!(shared.stream_state.load(), StreamState::Started);
+        shared.set_state(State::Drained);
+        assert_eq!(shared.stream_state.load(), StreamState::Drained);
+        assert!(!shared
+            .stream_state
+            .load()
+            .can_transition_to(StreamState::Started));
+        shared.set_state(State::Error);
+        assert_eq!(shared.stream_state.load(), StreamState::Error);
+    }
+
+    #[test]
+    fn data_callback_start_after_frames() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let log = infos.clone();
+        let mut cbs = callbacks(
+            Box::new(move |_, output, info| {
+                log.lock().unwrap().push(*info);
+                output.fill(Frame { m: 1.0 });
+                output.len() as isize
+            }),
+            &shared,
+        );
+        shared.schedule_start(Some(6), None);
+        shared.starting.store(true, Ordering::Release);
+        let mut output = [Frame { m: 1.0 }; 4];
+        assert_eq!(run(&mut cbs, &mut output), 4);
+        assert!(output.iter().all(|f| f.m == 0.0));
+        assert!(infos.lock().unwrap().is_empty());
+
+        assert_eq!(run(&mut cbs, &mut output), 4);
+        assert_eq!(output.map(|f| f.m), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(infos.lock().unwrap()[0].frames, 2);
+
+        assert_eq!(run(&mut cbs, &mut output), 4);
+        assert_eq!(infos.lock().unwrap()[1].frames, 4);
+    }
+
+    #[test]
+    fn data_callback_start_at() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        let mut cbs = callbacks(
+            Box::new(|_, output, _| {
+                output.fill(Frame { m: 1.0 });
+                output.len() as isize
+            }),
+            &shared,
+        );
+        // 480 frames of latency, so the first frame is heard 10ms from now.
+        shared.output_latency.store(480, Ordering::Release);
+        let when = Instant::now() + Duration::from_millis(20);
+        shared.schedule_start(None, Some(when));
+        shared.starting.store(true, Ordering::Release);
+        let mut output = [Frame { m: 1.0 }; 1024];
+        assert_eq!(run(&mut cbs, &mut output), 1024);
+        let silent = output.iter().take_while(|f| f.m == 0.0).count();
+        assert!(silent > 400 && silent <= 480, "{silent}");
+        assert!(output[silent..].iter().all(|f| f.m == 1.0));
+    }
+
+    #[test]
+    fn callback_timing_user_rate() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        // 441 frames of latency at the stream rate of 44.1 kHz.
+        shared.output_latency.store(441, Ordering::Release);
+        let mut timing = CallbackTiming::new(shared, 48_000, 44_100, false, true);
+        let before = Instant::now();
+        let info = timing.next(480);
+        let latency = info.output_presentation_time.unwrap() - before;
+        assert!(latency >= Duration::from_millis(10));
+        assert!(latency < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn data_callback_aligned() {
+        let shared = Arc::new(StreamShared::new(false, false, None));
+        // 96 frames of round-trip latency.
+        shared.output_latency.store(64, Ordering::Release);
+        shared.input_latency.store(32, Ordering::Release);
+        let references = Arc::new(Mutex::new(Vec::new()));
+        let log = references.clone();
+        let mut cbs = callbacks(Box::new(|_, _, _| 0), &shared);
+        cbs.timing = CallbackTiming::new(shared.clone(), 48_000, 48_000, true, true);
+        cbs.reference = Some(DelayLine::new(48_000, 64));
+        cbs.data = UserCallback::Aligned(Box::new(move |input, reference, output, _| {
+            assert_eq!(reference.len(), input.len());
+            log.lock().unwrap().extend(reference.iter().map(|f| f.m));
+            for (i, frame) in output.iter_mut().enumerate() {
+                frame.m = (i / 16) as f32;
+            }
+            output.len() as isize
+        }));
+        let input = [Frame { m: 0.0 }; 64];
+        let mut output = [Frame { m: 0.0 }; 64];
+
+        for _ in 0..3 {
+            let returned = unsafe {
+                data_cb_c::<Frame>(
+                    ptr::null_mut(),
+                    &mut *cbs as *mut _ as *mut c_void,
+                    input.as_ptr() as *const c_void,
+                    output.as_mut_ptr() as *mut c_void,
+                    64,
+                )
+            };
+            assert_eq!(returned, 64);
+        }
+
+        // Each callback outputs 16 frames each of 0, 1, 2 and 3, which
+        // come back 96 frames later.
+        let references = references.lock().unwrap();
+        let expected: Vec<f32> = [0.0; 96]
+            .into_iter()
+            .chain((0..64).map(|i| (i / 16) as f32))
+            .chain((0..32).map(|i| (i / 16) as f32))
+            .collect();
+        assert_eq!(*references, expected);
+    }
+}
@@ -0,0 +1,407 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Observers receiving copies of stream audio on a worker thread
+
+use crate::ring::{self, Consumer, Producer};
+use crate::{Error, Frame, Result};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle, Thread};
+
+// Frames buffered for each direction, about 1.4s at 48kHz.
+const TAP_FRAMES: usize = 1 << 16;
+// Frames passed to each tap call at most.
+const TAP_CHUNK: usize = 4096;
+
+/// Audio observed by a tap, see [`Stream::add_tap`](crate::Stream::add_tap).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The input passed to the data callback.
+    Input,
+    /// The output, after the stream volume is applied.
+    Output,
+}
+
+/// User supplied tap callback, receiving copies of the audio of a stream.
+pub type TapCallback<F> = dyn FnMut(&[F]) + Send + 'static;
+
+/// Identifies a tap added with [`Stream::add_tap`](crate::Stream::add_tap).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TapId(u64);
+
+struct Tap<F> {
+    id: TapId,
+    direction: Direction,
+    // Called by the worker without holding the list of taps, so taps can
+    // be added and removed while it runs.
+    callback: Arc<Mutex<Box<TapCallback<F>>>>,
+}
+
+// State shared between the control thread and the worker.
+struct Shared<F> {
+    taps: Mutex<Vec<Tap<F>>>,
+    // Queues handed over to the worker by `Taps::add`.
+    queues: Mutex<Vec<(Direction, Consumer<F>)>>,
+    stop: AtomicBool,
+    flush: Mutex<Flush>,
+    flushed: Condvar,
+}
+
+// Flushes requested by `Taps::flush`, and the last one the worker
+// completed.
+#[derive(Default)]
+struct Flush {
+    requested: u64,
+    completed: u64,
+}
+
+// Releases the flushes waiting on the worker when it ends, including by
+// a panicking tap.
+struct Exit<'a, F>(&'a Shared<F>);
+
+impl<F> Drop for Exit<'_, F> {
+    fn drop(&mut self) {
+        self.0.complete(u64::MAX);
+    }
+}
+
+impl<F> Shared<F> {
+    fn complete(&self, flush: u64) {
+        let mut state = match self.flush.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        state.completed = state.completed.max(flush);
+        self.flushed.notify_all();
+    }
+}
+
+impl<F: Frame> Shared<F> {
+    // Deliver the queued frames until the queues are empty.
+    fn deliver(&self, queues: &mut [(Direction, Consumer<F>)], chunk: &mut Vec<F>) {
+        for (direction, consumer) in queues {
+            loop {
+                chunk.clear();
+                while chunk.len() < TAP_CHUNK {
+                    match consumer.pop() {
+                        Some(frame) => chunk.push(frame),
+                        None => break,
+                    }
+                }
+                if chunk.is_empty() {
+                    break;
+                }
+                let callbacks: Vec<_> = self
+                    .taps
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|tap| tap.direction == *direction)
+                    .map(|tap| tap.callback.clone())
+                    .collect();
+                for callback in callbacks {
+                    (callback.lock().unwrap())(chunk);
+                }
+            }
+        }
+    }
+
+    fn run(&self) {
+        let _exit = Exit(self);
+        let mut queues = Vec::new();
+        let mut chunk = Vec::with_capacity(TAP_CHUNK);
+        loop {
+            // Deliver what was queued before stopping or flushing.
+            let stopping = self.stop.load(Ordering::Acquire);
+            let flush = self.flush.lock().unwrap().requested;
+            queues.append(&mut self.queues.lock().unwrap());
+            self.deliver(&mut queues, &mut chunk);
+            self.complete(flush);
+            if stopping {
+                break;
+            }
+            // Woken up by the trampoline once it queued frames.
+            thread::park();
+        }
+    }
+}
+
+/// Tap state of a [`Stream`](crate::Stream), on the control thread.
+pub(crate) struct Taps<F> {
+    // Rate of the tapped frames.
+    rate: u32,
+    has_input: bool,
+    has_output: bool,
+    // Hands the frame queues over to the trampoline, along with the worker
+    // to wake up.
+    install: Mutex<Producer<(Direction, Producer<F>, Thread)>>,
+    shared: Arc<Shared<F>>,
+    worker: Mutex<Worker>,
+    next_id: AtomicU64,
+}
+
+// The worker thread, started by the first tap.
+#[derive(Default)]
+struct Worker {
+    thread: Option<JoinHandle<()>>,
+    // Directions whose queue was handed over.
+    input: bool,
+    output: bool,
+}
+
+/// Tap state owned by the data callback trampoline.
+pub(crate) struct TapWriter<F> {
+    install: Consumer<(Direction, Producer<F>, Thread)>,
+    input: Option<Producer<F>>,
+    output: Option<Producer<F>>,
+    worker: Option<Thread>,
+}
+
+/// Create the tap state of a stream with the given directions, calling
+/// the data callback at `rate`.
+pub(crate) fn taps<F>(rate: u32, has_input: bool, has_output: bool) -> (Taps<F>, TapWriter<F>) {
+    let (install, installed) = ring::channel(2);
+    let taps = Taps {
+        rate,
+        has_input,
+        has_output,
+        install: Mutex::new(install),
+        shared: Arc::new(Shared {
+            taps: Mutex::new(Vec::new()),
+            queues: Mutex::new(Vec::new()),
+            stop: AtomicBool::new(false),
+            flush: Mutex::new(Flush::default()),
+            flushed: Condvar::new(),
+        }),
+        worker: Mutex::new(Worker::default()),
+        next_id: AtomicU64::new(0),
+    };
+    let writer = TapWriter {
+        install: installed,
+        input: None,
+        output: None,
+        worker: None,
+    };
+    (taps, writer)
+}
+
+impl<F: Frame + 'static> Taps<F> {
+    pub(crate) fn add(&self, direction: Direction, callback: Box<TapCallback<F>>) -> Result<TapId> {
+        if !self.has(direction) {
+            return Err(Error::InvalidParameter);
+        }
+
+        let id = TapId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut worker = self.worker.lock().unwrap();
+        if worker.thread.is_none() {
+            let shared = self.shared.clone();
+            worker.thread = Some(thread::spawn(move || shared.run()));
+        }
+        let thread = worker.thread.as_ref().unwrap().thread().clone();
+        let installed = match direction {
+            Direction::Input => &mut worker.input,
+            Direction::Output => &mut worker.output,
+        };
+        if !*installed {
+            // The first tap of a direction allocates its queue.
+            let (producer, queue) = ring::channel(TAP_FRAMES);
+            if self
+                .install
+                .lock()
+                .unwrap()
+                .push((direction, producer, thread.clone()))
+                .is_err()
+            {
+                return Err(Error::Error);
+            }
+            self.shared.queues.lock().unwrap().push((direction, queue));
+            *installed = true;
+        }
+        self.shared.taps.lock().unwrap().push(Tap {
+            id,
+            direction,
+            callback: Arc::new(Mutex::new(callback)),
+        });
+        Ok(id)
+    }
+}
+
+impl<F> Taps<F> {
+    pub(crate) fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub(crate) fn has(&self, direction: Direction) -> bool {
+        match direction {
+            Direction::Input => self.has_input,
+            Direction::Output => self.has_output,
+        }
+    }
+
+    /// Remove a tap, returning whether it existed. Frames queued for it
+    /// are dropped, except for a chunk the worker may be delivering.
+    pub(crate) fn remove(&self, id: TapId) -> bool {
+        let mut taps = self.shared.taps.lock().unwrap();
+        let count = taps.len();
+        taps.retain(|tap| tap.id != id);
+        taps.len() != count
+    }
+
+    /// Wait for the frames queued so far to be delivered. Returns
+    /// immediately when called from a tap.
+    pub(crate) fn flush(&self) {
+        let worker = match &self.worker.lock().unwrap().thread {
+            Some(worker) => worker.thread().clone(),
+            None => return,
+        };
+        if worker.id() == thread::current().id() {
+            return;
+        }
+        let mut flush = self.shared.flush.lock().unwrap();
+        flush.requested += 1;
+        let request = flush.requested;
+        worker.unpark();
+        while flush.completed < request {
+            flush = self.shared.flushed.wait(flush).unwrap();
+        }
+    }
+
+    /// Deliver the remaining frames and stop the worker. The trampoline
+    /// must not run anymore.
+    pub(crate) fn shutdown(&self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.lock().unwrap().thread.take() {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<F> Drop for Taps<F> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl<F: Frame> TapWriter<F> {
+    /// Queue copies of `frames` for the taps of `direction`. Frames which
+    /// don't fit in the queue are dropped.
+    pub(crate) fn write(&mut self, direction: Direction, frames: &[F]) {
+        while let Some((installed, producer, worker)) = self.install.pop() {
+            match installed {
+                Direction::Input => self.input = Some(producer),
+                Direction::Output => self.output = Some(producer),
+            }
+            self.worker = Some(worker);
+        }
+        let producer = match direction {
+            Direction::Input => &mut self.input,
+            Direction::Output => &mut self.output,
+        };
+        let Some(producer) = producer else {
+            return;
+        };
+        for frame in frames {
+            if producer.push(*frame).is_err() {
+                break;
+            }
+        }
+        if let (Some(worker), false) = (&self.worker, frames.is_empty()) {
+            worker.unpark();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonoFrame;
+    use std::sync::mpsc;
+
+    type Frame = MonoFrame<f32>;
+
+    #[test]
+    fn taps_receive_copies() {
+        let (taps, mut writer) = taps::<Frame>(48_000, true, true);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let id = taps
+            .add(
+                Direction::Output,
+                Box::new(move |frames: &[Frame]| {
+                    log.lock().unwrap().extend(frames.iter().map(|f| f.m));
+                }),
+            )
+            .unwrap();
+        let frames: Vec<Frame> = (0..10_000).map(|i| Frame { m: i as f32 }).collect();
+        for chunk in frames.chunks(512) {
+            writer.write(Direction::Output, chunk);
+            // Input isn't tapped, so is dropped.
+            writer.write(Direction::Input, chunk);
+        }
+        taps.shutdown();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 10_000);
+        assert!(received.iter().enumerate().all(|(i, &m)| m == i as f32));
+        assert!(taps.remove(id));
+        assert!(!taps.remove(id));
+    }
+
+    #[test]
+    fn taps_change_while_delivering() {
+        let (taps, mut writer) = taps::<Frame>(48_000, true, true);
+        let (entered, delivering) = mpsc::channel();
+        let (resume, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        taps.add(
+            Direction::Output,
+            Box::new(move |_: &[Frame]| {
+                let _ = entered.send(());
+                let _ = blocked.lock().unwrap().recv();
+            }),
+        )
+        .unwrap();
+        writer.write(Direction::Output, &[Frame { m: 0.0 }]);
+        delivering.recv().unwrap();
+
+        // The control thread isn't blocked by a tap being called.
+        let id = taps
+            .add(Direction::Input, Box::new(|_: &[Frame]| {}))
+            .unwrap();
+        assert!(taps.remove(id));
+        drop(resume);
+        taps.shutdown();
+    }
+
+    #[test]
+    fn flush_delivers_queued_frames() {
+        let (taps, mut writer) = taps::<Frame>(48_000, false, true);
+        let received = Arc::new(AtomicU64::new(0));
+        let count = received.clone();
+        taps.add(
+            Direction::Output,
+            Box::new(move |frames: &[Frame]| {
+                count.fetch_add(frames.len() as u64, Ordering::Relaxed);
+            }),
+        )
+        .unwrap();
+        for _ in 0..20 {
+            writer.write(Direction::Output, &[Frame { m: 0.0 }; 512]);
+            taps.flush();
+            // Nothing is left queued once flushed.
+            assert_eq!(received.load(Ordering::Relaxed) % 512, 0);
+        }
+        assert_eq!(received.load(Ordering::Relaxed), 20 * 512);
+    }
+
+    #[test]
+    fn missing_direction() {
+        let (taps, _) = taps::<Frame>(48_000, false, true);
+        let tap = taps.add(Direction::Input, Box::new(|_: &[Frame]| {}));
+        assert_eq!(tap.err(), Some(Error::InvalidParameter));
+    }
+}
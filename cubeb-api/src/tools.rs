@@ -0,0 +1,393 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Measurement utilities for validating backends and devices
+
+use crate::{
+    ChannelLayout, ContextRef, DeviceId, Error, MonoFrame, Result, SampleFormat, StreamBuilder,
+    StreamParamsBuilder,
+};
+use std::sync::mpsc;
+use std::time::Duration;
+
+// Order of the maximum length sequence, giving 4095 frames.
+const MLS_ORDER: u32 = 12;
+// Feedback taps of the Galois LFSR for x^12 + x^11 + x^10 + x^4 + 1.
+const MLS_TAPS: u32 = 0xE08;
+// Amplitude of the played sequence.
+const MLS_LEVEL: f32 = 0.5;
+// Silence played before the sequence, letting the devices settle.
+const LEAD_IN: Duration = Duration::from_millis(250);
+// Longest round-trip latency searched for.
+const MAX_ROUNDTRIP: Duration = Duration::from_secs(1);
+// Normalized correlation below which no echo is considered found.
+const MIN_CORRELATION: f32 = 0.1;
+
+/// Result of [`measure_roundtrip`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoundtripMeasurement {
+    /// Rate of the measurement stream, in which frames are counted.
+    pub rate: u32,
+    /// Measured round-trip latency, in frames.
+    pub measured: u32,
+    /// Round-trip latency reported by the backend, in frames, see
+    /// [`Stream::roundtrip_latency`](crate::Stream::roundtrip_latency).
+    /// `None` if the backend doesn't report both latencies.
+    pub reported: Option<u32>,
+    /// Normalized correlation between the played and captured sequences,
+    /// from 0 to 1. Low values indicate a noisy or attenuated loopback.
+    pub correlation: f32,
+}
+
+impl RoundtripMeasurement {
+    /// Measured round-trip latency.
+    pub fn measured_duration(&self) -> Duration {
+        Duration::from_secs_f64(f64::from(self.measured) / f64::from(self.rate))
+    }
+
+    /// Difference between the reported and measured latencies, in frames.
+    /// Positive values mean the backend overestimates its latency.
+    pub fn reporting_error(&self) -> Option<i64> {
+        self.reported
+            .map(|reported| i64::from(reported) - i64::from(self.measured))
+    }
+}
+
+/// Measure the round-trip latency between `output_device` and
+/// `input_device` by playing a maximum length sequence through a duplex
+/// stream and cross-correlating the captured input with it.
+///
+/// The output must be looped back to the input, either with a cable, a
+/// loopback device or acoustically in a quiet room. Null device ids select
+/// the default devices.
+///
+/// Fails with [`Error::Error`] if the sequence isn't found in the captured
+/// input within one second.
+pub fn measure_roundtrip(
+    ctx: &ContextRef,
+    input_device: DeviceId,
+    output_device: DeviceId,
+) -> Result<RoundtripMeasurement> {
+    let rate = ctx.preferred_sample_rate().unwrap_or(48_000);
+    let params = StreamParamsBuilder::new()
+        .format(SampleFormat::Float32NE)
+        .rate(rate)
+        .channels(1)
+        .layout(ChannelLayout::MONO)
+        .take();
+    let latency = ctx.min_latency(&params).unwrap_or(256);
+
+    let sequence = mls(MLS_ORDER, MLS_TAPS);
+    let lead_in = frames(LEAD_IN, rate);
+    let total = lead_in + sequence.len() + frames(MAX_ROUNDTRIP, rate);
+    let played = sequence.clone();
+    let mut written = 0usize;
+    let mut captured = Vec::with_capacity(total);
+    let (tx, rx) = mpsc::sync_channel(1);
+    let mut tx = Some(tx);
+
+    let mut builder = StreamBuilder::<MonoFrame<f32>>::new();
+    builder
+        .name("cubeb round-trip latency measurement")
+        .input(input_device, &params)
+        .output(output_device, &params)
+        .latency(latency)
+        .data_callback(move |input, output| {
+            for frame in output.iter_mut() {
+                frame.m = written
+                    .checked_sub(lead_in)
+                    .and_then(|i| played.get(i))
+                    .map_or(0.0, |sample| MLS_LEVEL * sample);
+                written += 1;
+            }
+            let room = total - captured.len();
+            captured.extend(input.iter().take(room).map(|frame| frame.m));
+            if captured.len() == total {
+                if let Some(tx) = tx.take() {
+                    let _ = tx.send(std::mem::take(&mut captured));
+                }
+            }
+            output.len() as isize
+        })
+        .state_callback(|_| {});
+    let stream = builder.init(ctx)?;
+
+    stream.start()?;
+    let timeout = Duration::from_secs_f64(total as f64 / f64::from(rate)) + MAX_ROUNDTRIP;
+    let captured = rx.recv_timeout(timeout);
+    let reported = stream.roundtrip_latency().ok();
+    stream.stop()?;
+    let captured = captured.map_err(|_| Error::Error)?;
+
+    let (measured, correlation) =
+        find_delay(&sequence, &captured[lead_in..]).ok_or(Error::Error)?;
+    if correlation < MIN_CORRELATION {
+        return Err(Error::Error);
+    }
+    Ok(RoundtripMeasurement {
+        rate,
+        measured: measured as u32,
+        reported,
+        correlation,
+    })
+}
+
+fn frames(duration: Duration, rate: u32) -> usize {
+    (duration.as_secs_f64() * f64::from(rate)) as usize
+}
+
+// Maximum length sequence of `2^order - 1` samples of ±1, generated by a
+// Galois LFSR with the given feedback taps.
+fn mls(order: u32, taps: u32) -> Vec<f32> {
+    let mut state = 1u32;
+    (0..(1 << order) - 1)
+        .map(|_| {
+            let bit = state & 1;
+            state >>= 1;
+            if bit == 1 {
+                state ^= taps;
+                1.0
+            } else {
+                -1.0
+            }
+        })
+        .collect()
+}
+
+// Offset of `reference` in `captured` with the highest normalized
+// correlation, along with that correlation. `None` if `captured` is
+// shorter than `reference`.
+fn find_delay(reference: &[f32], captured: &[f32]) -> Option<(usize, f32)> {
+    let lags = (captured.len() + 1).checked_sub(reference.len())?;
+    let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+    let reference_energy = energy(reference);
+    (0..lags)
+        .map(|lag| {
+            let window = &captured[lag..lag + reference.len()];
+            let dot: f32 = reference.iter().zip(window).map(|(r, c)| r * c).sum();
+            let norm = (reference_energy * energy(window)).sqrt();
+            let correlation = if norm > 0.0 { dot / norm } else { 0.0 };
+            (lag, correlation)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Context;
+    use cubeb_backend::{
+        capi_new, ffi, ContextOps, DeviceId as BackendDeviceId, Ops, OptionalOps, Stream,
+        StreamOps, StreamParamsRef,
+    };
+    use std::collections::VecDeque;
+    use std::ffi::CStr;
+    use std::os::raw::{c_long, c_void};
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+
+    // Frames between writing to the output of the loopback backend and
+    // reading them back from its input.
+    const LOOPBACK_DELAY: usize = 700;
+    const LOOPBACK_PERIOD: usize = 256;
+    const LOOPBACK_RATE: u32 = 8000;
+
+    const LOOPBACK_OPS: Ops = capi_new!(LoopbackContext, LoopbackStream);
+
+    // A backend whose mono float streams capture what they played
+    // `LOOPBACK_DELAY` frames earlier. The layouts match the headers of
+    // libcubeb's `cubeb` and `cubeb_stream`.
+    #[repr(C)]
+    struct LoopbackContext {
+        ops: *const Ops,
+    }
+
+    impl ContextOps for LoopbackContext {
+        const UNSUPPORTED: OptionalOps =
+            OptionalOps::all().difference(OptionalOps::PREFERRED_SAMPLE_RATE);
+
+        fn init(_context_name: Option<&CStr>) -> cubeb_backend::Result<Box<Self>> {
+            Ok(Box::new(LoopbackContext { ops: &LOOPBACK_OPS }))
+        }
+        fn backend_id(&mut self) -> &'static CStr {
+            c"loopback"
+        }
+        fn preferred_sample_rate(&mut self) -> cubeb_backend::Result<u32> {
+            Ok(LOOPBACK_RATE)
+        }
+        fn stream_init(
+            &mut self,
+            _stream_name: Option<&CStr>,
+            _input_device: BackendDeviceId,
+            _input_stream_params: Option<&StreamParamsRef>,
+            _output_device: BackendDeviceId,
+            _output_stream_params: Option<&StreamParamsRef>,
+            _latency_frames: u32,
+            data_callback: ffi::cubeb_data_callback,
+            state_callback: ffi::cubeb_state_callback,
+            user_ptr: *mut c_void,
+        ) -> cubeb_backend::Result<Stream> {
+            let stm = Box::new(LoopbackStream {
+                context: self as *mut _ as *mut ffi::cubeb,
+                user_ptr,
+                data_callback,
+                state_callback,
+                stop: Arc::new(AtomicBool::new(false)),
+                position: Arc::new(AtomicU64::new(0)),
+                thread: None,
+            });
+            Ok(unsafe { Stream::from_ptr(Box::into_raw(stm) as *mut _) })
+        }
+    }
+
+    #[repr(C)]
+    struct LoopbackStream {
+        context: *mut ffi::cubeb,
+        user_ptr: *mut c_void,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        stop: Arc<AtomicBool>,
+        position: Arc<AtomicU64>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl StreamOps for LoopbackStream {
+        const UNSUPPORTED: OptionalOps = OptionalOps::all()
+            .difference(OptionalOps::LATENCY)
+            .difference(OptionalOps::INPUT_LATENCY);
+
+        fn start(&mut self) -> cubeb_backend::Result<()> {
+            let stm = self as *mut Self as usize;
+            let user_ptr = self.user_ptr as usize;
+            let data_callback = self.data_callback.unwrap();
+            let (stop, position) = (self.stop.clone(), self.position.clone());
+            unsafe {
+                self.state_callback.unwrap()(stm as *mut _, self.user_ptr, ffi::CUBEB_STATE_STARTED)
+            };
+            self.thread = Some(thread::spawn(move || {
+                let mut line = VecDeque::from(vec![0.0f32; LOOPBACK_DELAY]);
+                let mut input = [0.0f32; LOOPBACK_PERIOD];
+                let mut output = [0.0f32; LOOPBACK_PERIOD];
+                while !stop.load(Ordering::Acquire) {
+                    for sample in input.iter_mut() {
+                        *sample = line.pop_front().unwrap();
+                    }
+                    unsafe {
+                        data_callback(
+                            stm as *mut _,
+                            user_ptr as *mut _,
+                            input.as_ptr() as *const c_void,
+                            output.as_mut_ptr() as *mut c_void,
+                            LOOPBACK_PERIOD as c_long,
+                        )
+                    };
+                    line.extend(output);
+                    position.fetch_add(LOOPBACK_PERIOD as u64, Ordering::AcqRel);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }));
+            Ok(())
+        }
+        fn stop(&mut self) -> cubeb_backend::Result<()> {
+            self.stop.store(true, Ordering::Release);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+            self.stop.store(false, Ordering::Release);
+            unsafe {
+                self.state_callback.unwrap()(
+                    self as *mut Self as *mut _,
+                    self.user_ptr,
+                    ffi::CUBEB_STATE_STOPPED,
+                )
+            };
+            Ok(())
+        }
+        fn position(&mut self) -> cubeb_backend::Result<u64> {
+            Ok(self.position.load(Ordering::Acquire))
+        }
+        fn latency(&mut self) -> cubeb_backend::Result<u32> {
+            Ok(LOOPBACK_DELAY as u32 / 2)
+        }
+        fn input_latency(&mut self) -> cubeb_backend::Result<u32> {
+            Ok(LOOPBACK_DELAY as u32 - LOOPBACK_DELAY as u32 / 2)
+        }
+    }
+
+    impl Drop for LoopbackStream {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Release);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    // Needs libcubeb to dispatch the calls to the backend.
+    #[test]
+    #[cfg_attr(feature = "gecko-in-tree", ignore)]
+    fn measures_loopback_roundtrip() {
+        let mut c: *mut ffi::cubeb = ptr::null_mut();
+        let rv = unsafe { LOOPBACK_OPS.init.unwrap()(&mut c, ptr::null()) };
+        assert_eq!(rv, ffi::CUBEB_OK);
+        let ctx = unsafe { Context::from_ptr(c) };
+
+        let measurement = measure_roundtrip(&ctx, ptr::null(), ptr::null()).unwrap();
+        assert_eq!(measurement.rate, LOOPBACK_RATE);
+        assert_eq!(measurement.measured, LOOPBACK_DELAY as u32);
+        assert_eq!(measurement.reported, Some(LOOPBACK_DELAY as u32));
+        assert_eq!(measurement.reporting_error(), Some(0));
+        assert!(measurement.correlation > 0.99);
+    }
+
+    #[test]
+    fn mls_is_maximal() {
+        let sequence = mls(MLS_ORDER, MLS_TAPS);
+        assert_eq!(sequence.len(), 4095);
+        // A maximum length sequence has one more 1 than -1, and its
+        // circular autocorrelation is -1 at every non-zero lag.
+        assert_eq!(sequence.iter().sum::<f32>(), 1.0);
+        for lag in [1, 17, 2048] {
+            let autocorrelation: f32 = (0..sequence.len())
+                .map(|i| sequence[i] * sequence[(i + lag) % sequence.len()])
+                .sum();
+            assert_eq!(autocorrelation, -1.0);
+        }
+    }
+
+    #[test]
+    fn finds_attenuated_noisy_echo() {
+        let sequence = mls(MLS_ORDER, MLS_TAPS);
+        let delay = 1234;
+        // Deterministic noise, decimating the sequence.
+        let noise = mls(MLS_ORDER, MLS_TAPS);
+        let mut captured = vec![0.0; delay + sequence.len() + 500];
+        for (i, sample) in sequence.iter().enumerate() {
+            captured[delay + i] = 0.1 * sample;
+        }
+        for (i, sample) in captured.iter_mut().enumerate() {
+            *sample += 0.05 * noise[(i * 7 + 3) % noise.len()];
+        }
+
+        let (measured, correlation) = find_delay(&sequence, &captured).unwrap();
+        assert_eq!(measured, delay);
+        assert!(correlation > 0.5);
+        assert_eq!(find_delay(&sequence, &captured[..100]), None);
+    }
+
+    #[test]
+    fn reporting_error() {
+        let measurement = RoundtripMeasurement {
+            rate: 48_000,
+            measured: 4800,
+            reported: Some(4320),
+            correlation: 0.9,
+        };
+        assert_eq!(measurement.measured_duration(), Duration::from_millis(100));
+        assert_eq!(measurement.reporting_error(), Some(-480));
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Pure-Rust voice processors for input streams
+
+use crate::processing::{Agc, NoiseGate as Gate};
+use crate::VoiceProcessor;
+use std::f32::consts::PI;
+
+// Regularization of the NLMS step, avoiding large updates in silence.
+const NLMS_EPSILON: f32 = 1e-3;
+
+/// Second order Butterworth high-pass filter, removing rumble and DC
+/// offsets below the cutoff frequency.
+pub struct HighPass {
+    b: [f32; 3],
+    a: [f32; 2],
+    // Transposed direct form II state.
+    state: [f32; 2],
+}
+
+impl HighPass {
+    /// Filter out the frequencies below `cutoff` Hz of an input at `rate`.
+    pub fn new(cutoff: f32, rate: u32) -> HighPass {
+        let w0 = 2.0 * PI * cutoff / rate as f32;
+        let alpha = w0.sin() / 2f32.sqrt();
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        HighPass {
+            b: [
+                (1.0 + cos) / 2.0 / a0,
+                -(1.0 + cos) / a0,
+                (1.0 + cos) / 2.0 / a0,
+            ],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            state: [0.0; 2],
+        }
+    }
+}
+
+impl VoiceProcessor for HighPass {
+    fn process(&mut self, input: &mut [f32], _: &[f32]) {
+        let (b, a) = (self.b, self.a);
+        for sample in input {
+            let x = *sample;
+            let y = b[0] * x + self.state[0];
+            self.state[0] = b[1] * x - a[0] * y + self.state[1];
+            self.state[1] = b[2] * x - a[1] * y;
+            *sample = y;
+        }
+    }
+}
+
+/// Noise gate silencing the input while its level stays below -50 dBFS.
+pub struct NoiseGate(Gate);
+
+impl NoiseGate {
+    /// Gate an input at `rate`.
+    pub fn new(rate: u32) -> NoiseGate {
+        NoiseGate(Gate::new(rate))
+    }
+}
+
+impl VoiceProcessor for NoiseGate {
+    fn process(&mut self, input: &mut [f32], _: &[f32]) {
+        for sample in input {
+            self.0.process(std::slice::from_mut(sample));
+        }
+    }
+}
+
+/// Automatic gain control slowly bringing the input level to -20 dBFS,
+/// with up to 30 dB of gain.
+pub struct AutomaticGainControl(Agc);
+
+impl AutomaticGainControl {
+    /// Control the gain of an input at `rate`.
+    pub fn new(rate: u32) -> AutomaticGainControl {
+        AutomaticGainControl(Agc::new(rate))
+    }
+}
+
+impl VoiceProcessor for AutomaticGainControl {
+    fn process(&mut self, input: &mut [f32], _: &[f32]) {
+        for sample in input {
+            self.0.process(std::slice::from_mut(sample));
+        }
+    }
+}
+
+/// Normalized least mean squares echo canceller, removing the reference
+/// signal from the input.
+///
+/// The filter models the echo path over `taps` samples of the reference.
+/// Since [`StreamBuilder::input_processor`](crate::StreamBuilder::input_processor)
+/// aligns the reference using the reported latencies, the taps only need to
+/// cover the error of the reported latencies and the room reverberation.
+pub struct EchoCanceller {
+    weights: Vec<f32>,
+    // Reference history, stored twice so the last `taps` samples are
+    // contiguous from `position`, most recent first.
+    history: Vec<f32>,
+    position: usize,
+    // Energy of the last `taps` reference samples.
+    energy: f32,
+    step: f32,
+}
+
+impl EchoCanceller {
+    /// Cancel an echo path of up to `taps` samples, at least 1.
+    pub fn new(taps: usize) -> EchoCanceller {
+        let taps = taps.max(1);
+        EchoCanceller {
+            weights: vec![0.0; taps],
+            history: vec![0.0; 2 * taps],
+            position: 0,
+            energy: 0.0,
+            step: 0.5,
+        }
+    }
+
+    /// Adaptation step size, between 0 and 2. Larger values converge
+    /// faster but track less precisely. Defaults to 0.5.
+    pub fn step_size(mut self, step: f32) -> EchoCanceller {
+        self.step = step;
+        self
+    }
+}
+
+impl VoiceProcessor for EchoCanceller {
+    fn process(&mut self, input: &mut [f32], reference: &[f32]) {
+        let taps = self.weights.len();
+        for (i, sample) in input.iter_mut().enumerate() {
+            let x = reference.get(i).copied().unwrap_or(0.0);
+            self.position = (self.position + taps - 1) % taps;
+            let oldest = self.history[self.position];
+            self.history[self.position] = x;
+            self.history[self.position + taps] = x;
+            self.energy = (self.energy + x * x - oldest * oldest).max(0.0);
+
+            let window = &self.history[self.position..self.position + taps];
+            let echo: f32 = self.weights.iter().zip(window).map(|(w, x)| w * x).sum();
+            let error = *sample - echo;
+            let step = self.step * error / (self.energy + NLMS_EPSILON);
+            for (w, x) in self.weights.iter_mut().zip(window) {
+                *w += step * x;
+            }
+            *sample = error;
+        }
+    }
+}
+
+/// Processors run one after the other.
+#[derive(Default)]
+pub struct VoiceChain {
+    processors: Vec<Box<dyn VoiceProcessor>>,
+}
+
+impl VoiceChain {
+    /// An empty chain, leaving the input unchanged.
+    pub fn new() -> VoiceChain {
+        Default::default()
+    }
+
+    /// A chain suitable for voice calls at `rate`: an 80 Hz high-pass
+    /// filter, a 1024 tap echo canceller, a noise gate and an automatic
+    /// gain control.
+    pub fn voice(rate: u32) -> VoiceChain {
+        VoiceChain::new()
+            .push(HighPass::new(80.0, rate))
+            .push(EchoCanceller::new(1024))
+            .push(NoiseGate::new(rate))
+            .push(AutomaticGainControl::new(rate))
+    }
+
+    /// Append `processor` to the chain.
+    pub fn push<P>(mut self, processor: P) -> VoiceChain
+    where
+        P: VoiceProcessor + 'static,
+    {
+        self.processors.push(Box::new(processor));
+        self
+    }
+}
+
+impl VoiceProcessor for VoiceChain {
+    fn process(&mut self, input: &mut [f32], reference: &[f32]) {
+        for processor in &mut self.processors {
+            processor.process(input, reference);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency * i as f32 / 48_000.0).sin())
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    // Deterministic white noise in [-1, 1].
+    fn noise(frames: usize) -> Vec<f32> {
+        let mut state = 0x1234_5678u32;
+        (0..frames)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn high_pass() {
+        let mut filter = HighPass::new(100.0, 48_000);
+        let mut rumble = sine(20.0, 48_000);
+        filter.process(&mut rumble, &[]);
+        assert!(rms(&rumble[24_000..]) < 0.1 * rms(&sine(20.0, 48_000)));
+
+        let mut filter = HighPass::new(100.0, 48_000);
+        let mut voice = sine(1000.0, 48_000);
+        filter.process(&mut voice, &[]);
+        assert!(rms(&voice[24_000..]) > 0.69);
+    }
+
+    #[test]
+    fn echo_canceller_removes_delayed_reference() {
+        let reference = noise(48_000);
+        // The echo is attenuated and arrives 10 samples late.
+        let mut input: Vec<f32> = (0..reference.len())
+            .map(|i| 0.5 * i.checked_sub(10).map_or(0.0, |j| reference[j]))
+            .collect();
+        let echo = rms(&input[40_000..]);
+
+        let mut canceller = EchoCanceller::new(64);
+        for (input, reference) in input.chunks_mut(480).zip(reference.chunks(480)) {
+            canceller.process(input, reference);
+        }
+        // At least 40 dB of echo return loss enhancement once converged.
+        assert!(rms(&input[40_000..]) < 0.01 * echo);
+    }
+
+    #[test]
+    fn chain_runs_in_order() {
+        struct ScaleAndAdd(f32);
+        impl VoiceProcessor for ScaleAndAdd {
+            fn process(&mut self, input: &mut [f32], _: &[f32]) {
+                input.iter_mut().for_each(|s| *s = *s * 2.0 + self.0);
+            }
+        }
+        let mut chain = VoiceChain::new()
+            .push(ScaleAndAdd(1.0))
+            .push(ScaleAndAdd(0.5));
+        let mut input = [0.0, 1.0];
+        chain.process(&mut input, &[]);
+        assert_eq!(input, [2.5, 6.5]);
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Safe traits to implement backends without raw callbacks.
+//!
+//! A [`BackendContext`] creates its streams with a [`StreamCallbacks`]
+//! handle calling the user back, and [`capi_new_safe!`](crate::capi_new_safe)
+//! makes the [`Ops`](crate::Ops) table of the backend:
+//!
+//! ```ignore
+//! pub const OPS: Ops = capi_new_safe!(MyContext);
+//! ```
+
+use crate::util::{frame_bytes, unsupported};
+use crate::{ContextOps, OptionalOps, StreamOps};
+use cubeb_core::{
+    ffi, DeviceId, DeviceInfo, DeviceRef, DeviceType, Error, InputProcessingParams, Result, State,
+    Stream, StreamParams, StreamParamsRef,
+};
+use std::ffi::CStr;
+use std::os::raw::{c_long, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// What a stream is created with.
+pub struct StreamInitParams<'a> {
+    /// Name of the stream, for display.
+    pub stream_name: Option<&'a CStr>,
+    /// Input device, or null for the default one.
+    pub input_device: DeviceId,
+    /// Input parameters, `None` for an output only stream.
+    pub input_stream_params: Option<&'a StreamParamsRef>,
+    /// Output device, or null for the default one.
+    pub output_device: DeviceId,
+    /// Output parameters, `None` for an input only stream.
+    pub output_stream_params: Option<&'a StreamParamsRef>,
+    /// Requested latency, in frames.
+    pub latency_frames: u32,
+}
+
+/// A backend context, made into a [`ContextOps`] by [`SafeContext`].
+///
+/// Optional operations the backend doesn't implement must be listed in
+/// [`BackendContext::UNSUPPORTED`], as with [`ContextOps`].
+pub trait BackendContext: Sized + 'static {
+    type Stream: BackendStream + 'static;
+
+    /// Optional operations left out of the [`Ops`](crate::Ops) table.
+    const UNSUPPORTED: OptionalOps = OptionalOps::empty();
+
+    fn init(context_name: Option<&CStr>) -> Result<Self>;
+    fn backend_id(&mut self) -> &'static CStr;
+    fn max_channel_count(&mut self) -> Result<u32> {
+        unsupported!(MAX_CHANNEL_COUNT)
+    }
+    fn min_latency(&mut self, _params: StreamParams) -> Result<u32> {
+        unsupported!(MIN_LATENCY)
+    }
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        unsupported!(PREFERRED_SAMPLE_RATE)
+    }
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        unsupported!(SUPPORTED_INPUT_PROCESSING_PARAMS)
+    }
+    fn enumerate_devices(&mut self, _devtype: DeviceType) -> Result<Vec<DeviceInfo>> {
+        unsupported!(ENUMERATE_DEVICES)
+    }
+    /// Create a stream, which calls the user back through `callbacks`.
+    fn stream_init(
+        &mut self,
+        params: &StreamInitParams,
+        callbacks: StreamCallbacks,
+    ) -> Result<Self::Stream>;
+    /// Notify `callback` of changes of the devices of `devtype`, or stop
+    /// notifying them if `None`.
+    fn register_device_collection_changed(
+        &mut self,
+        _devtype: DeviceType,
+        _callback: Option<CollectionChanged>,
+    ) -> Result<()> {
+        Err(Error::NotSupported)
+    }
+}
+
+/// A backend stream, made into a [`StreamOps`] by [`SafeStream`].
+///
+/// Device changes are reported with [`StreamNotifier::device_changed`],
+/// whether the user registered a callback or not. Optional operations the
+/// backend doesn't implement must be listed in
+/// [`BackendStream::UNSUPPORTED`], as with [`StreamOps`].
+pub trait BackendStream {
+    /// Optional operations left out of the [`Ops`](crate::Ops) table.
+    /// Registering a device changed callback is always supported.
+    const UNSUPPORTED: OptionalOps = OptionalOps::empty();
+
+    fn start(&mut self) -> Result<()>;
+    fn stop(&mut self) -> Result<()>;
+    fn position(&mut self) -> Result<u64>;
+    fn latency(&mut self) -> Result<u32> {
+        unsupported!(LATENCY)
+    }
+    fn input_latency(&mut self) -> Result<u32> {
+        unsupported!(INPUT_LATENCY)
+    }
+    fn set_volume(&mut self, _volume: f32) -> Result<()> {
+        unsupported!(SET_VOLUME)
+    }
+    fn set_name(&mut self, _name: &CStr) -> Result<()> {
+        unsupported!(SET_NAME)
+    }
+    fn current_device(&mut self) -> Result<&DeviceRef> {
+        unsupported!(CURRENT_DEVICE)
+    }
+    fn set_input_mute(&mut self, _mute: bool) -> Result<()> {
+        unsupported!(SET_INPUT_MUTE)
+    }
+    fn set_input_processing_params(&mut self, _params: InputProcessingParams) -> Result<()> {
+        unsupported!(SET_INPUT_PROCESSING_PARAMS)
+    }
+    /// Release a device returned by `current_device`, doing nothing by
+    /// default.
+    fn device_destroy(&mut self, _device: &DeviceRef) -> Result<()> {
+        Ok(())
+    }
+}
+
+// The user callbacks of a stream.
+struct StreamShared {
+    // The `SafeStream`, as seen by the user.
+    stream: AtomicUsize,
+    data_callback: ffi::cubeb_data_callback,
+    state_callback: ffi::cubeb_state_callback,
+    user_ptr: usize,
+    device_changed: Mutex<ffi::cubeb_device_changed_callback>,
+    input_frame_bytes: usize,
+    output_frame_bytes: usize,
+}
+
+impl StreamShared {
+    fn state(&self, state: State) {
+        if let Some(callback) = self.state_callback {
+            unsafe {
+                callback(
+                    self.stream.load(Ordering::Acquire) as *mut _,
+                    self.user_ptr as *mut c_void,
+                    state.into(),
+                )
+            };
+        }
+    }
+
+    fn device_changed(&self) {
+        let callback = *self.device_changed.lock().unwrap();
+        if let Some(callback) = callback {
+            unsafe { callback(self.user_ptr as *mut c_void) };
+        }
+    }
+}
+
+/// Calls the user back for a stream.
+///
+/// Data callbacks may not run concurrently, so this handle can't be
+/// cloned; state changes can be reported from other threads through
+/// [`StreamCallbacks::notifier`].
+pub struct StreamCallbacks {
+    shared: Arc<StreamShared>,
+}
+
+impl StreamCallbacks {
+    /// Bytes per frame of the input, 0 without input.
+    pub fn input_frame_bytes(&self) -> usize {
+        self.shared.input_frame_bytes
+    }
+
+    /// Bytes per frame of the output, 0 without output.
+    pub fn output_frame_bytes(&self) -> usize {
+        self.shared.output_frame_bytes
+    }
+
+    /// Ask the user for `output`, passing `input`, returning the number of
+    /// frames the user produced. Fewer frames than asked for mean the
+    /// stream is draining.
+    ///
+    /// The buffers are in the format of the stream parameters, and are
+    /// empty for the directions the stream doesn't have. A duplex stream
+    /// must pass as many input as output frames. When the user fails,
+    /// [`State::Error`] is reported and 0 is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a buffer doesn't hold a whole number of frames, or if a
+    /// duplex stream passes different numbers of input and output frames.
+    pub fn data(&mut self, input: &[u8], output: &mut [u8]) -> usize {
+        self.try_data(input, output).unwrap_or(0)
+    }
+
+    // `data`, returning `None` when the user failed.
+    pub(crate) fn try_data(&mut self, input: &[u8], output: &mut [u8]) -> Option<usize> {
+        let shared = &*self.shared;
+        let input_frames = whole_frames(input.len(), shared.input_frame_bytes, "input");
+        let output_frames = whole_frames(output.len(), shared.output_frame_bytes, "output");
+        let nframes = match (input_frames, output_frames) {
+            (Some(input_frames), Some(output_frames)) => {
+                assert_eq!(
+                    input_frames, output_frames,
+                    "duplex input and output frame counts differ"
+                );
+                output_frames
+            }
+            (input_frames, output_frames) => output_frames.or(input_frames).unwrap_or(0),
+        };
+        let Some(callback) = shared.data_callback else {
+            return Some(nframes);
+        };
+        let input = if shared.input_frame_bytes == 0 {
+            ptr::null()
+        } else {
+            input.as_ptr() as *const c_void
+        };
+        let output = if shared.output_frame_bytes == 0 {
+            ptr::null_mut()
+        } else {
+            output.as_mut_ptr() as *mut c_void
+        };
+        let returned = unsafe {
+            callback(
+                shared.stream.load(Ordering::Acquire) as *mut _,
+                shared.user_ptr as *mut c_void,
+                input,
+                output,
+                nframes as c_long,
+            )
+        };
+        if returned < 0 {
+            shared.state(State::Error);
+            return None;
+        }
+        Some((returned as usize).min(nframes))
+    }
+
+    /// Report a state change of the stream.
+    pub fn state(&self, state: State) {
+        self.shared.state(state);
+    }
+
+    /// Report a change of the device of the stream.
+    pub fn device_changed(&self) {
+        self.shared.device_changed();
+    }
+
+    /// A handle reporting state and device changes.
+    pub fn notifier(&self) -> StreamNotifier {
+        StreamNotifier {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+// Number of frames of `frame_bytes` in a buffer of `len` bytes, `None`
+// for a direction the stream doesn't have.
+fn whole_frames(len: usize, frame_bytes: usize, direction: &str) -> Option<usize> {
+    if frame_bytes == 0 {
+        return None;
+    }
+    assert!(
+        len.is_multiple_of(frame_bytes),
+        "{direction} buffer of {len} bytes isn't a whole number of {frame_bytes} byte frames"
+    );
+    Some(len / frame_bytes)
+}
+
+/// Reports state and device changes of a stream.
+#[derive(Clone)]
+pub struct StreamNotifier {
+    shared: Arc<StreamShared>,
+}
+
+impl StreamNotifier {
+    /// Report a state change of the stream.
+    pub fn state(&self, state: State) {
+        self.shared.state(state);
+    }
+
+    /// Report a change of the device of the stream.
+    pub fn device_changed(&self) {
+        self.shared.device_changed();
+    }
+}
+
+/// Notifies the user of changes of the device collection.
+#[derive(Clone)]
+pub struct CollectionChanged {
+    // The `SafeContext`, as seen by the user.
+    context: usize,
+    callback: unsafe extern "C" fn(*mut ffi::cubeb, *mut c_void),
+    user_ptr: usize,
+}
+
+impl CollectionChanged {
+    /// Call the user's callback.
+    pub fn notify(&self) {
+        unsafe { (self.callback)(self.context as *mut _, self.user_ptr as *mut c_void) };
+    }
+}
+
+/// [`ContextOps`] of a [`BackendContext`], whose streams are
+/// [`SafeStream`]s of its [`BackendContext::Stream`].
+pub struct SafeContext<C> {
+    inner: C,
+}
+
+impl<C: BackendContext> SafeContext<C> {
+    /// The backend context.
+    pub fn inner(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+impl<C: BackendContext> ContextOps for SafeContext<C> {
+    const UNSUPPORTED: OptionalOps = C::UNSUPPORTED;
+
+    fn init(context_name: Option<&CStr>) -> Result<Box<Self>> {
+        Ok(Box::new(SafeContext {
+            inner: C::init(context_name)?,
+        }))
+    }
+
+    fn backend_id(&mut self) -> &CStr {
+        self.inner.backend_id()
+    }
+
+    fn max_channel_count(&mut self) -> Result<u32> {
+        self.inner.max_channel_count()
+    }
+
+    fn min_latency(&mut self, params: StreamParams) -> Result<u32> {
+        self.inner.min_latency(params)
+    }
+
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        self.inner.preferred_sample_rate()
+    }
+
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        self.inner.supported_input_processing_params()
+    }
+
+    fn enumerate_devices(&mut self, devtype: DeviceType) -> Result<Box<[DeviceInfo]>> {
+        Ok(self.inner.enumerate_devices(devtype)?.into_boxed_slice())
+    }
+
+    fn device_collection_destroy(&mut self, _collection: Box<[DeviceInfo]>) -> Result<()> {
+        Ok(())
+    }
+
+    fn stream_init(
+        &mut self,
+        stream_name: Option<&CStr>,
+        input_device: DeviceId,
+        input_stream_params: Option<&StreamParamsRef>,
+        output_device: DeviceId,
+        output_stream_params: Option<&StreamParamsRef>,
+        latency_frames: u32,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        let shared = Arc::new(StreamShared {
+            stream: AtomicUsize::new(0),
+            data_callback,
+            state_callback,
+            user_ptr: user_ptr as usize,
+            device_changed: Mutex::new(None),
+            input_frame_bytes: input_stream_params.map_or(0, frame_bytes),
+            output_frame_bytes: output_stream_params.map_or(0, frame_bytes),
+        });
+        let params = StreamInitParams {
+            stream_name,
+            input_device,
+            input_stream_params,
+            output_device,
+            output_stream_params,
+            latency_frames,
+        };
+        let callbacks = StreamCallbacks {
+            shared: shared.clone(),
+        };
+        let inner = self.inner.stream_init(&params, callbacks)?;
+        let stream = Box::into_raw(Box::new(SafeStream {
+            inner,
+            shared: shared.clone(),
+        }));
+        shared.stream.store(stream as usize, Ordering::Release);
+        Ok(unsafe { Stream::from_ptr(stream as *mut _) })
+    }
+
+    fn register_device_collection_changed(
+        &mut self,
+        devtype: DeviceType,
+        cb: ffi::cubeb_device_collection_changed_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<()> {
+        let callback = cb.map(|callback| CollectionChanged {
+            context: self as *mut Self as usize,
+            callback,
+            user_ptr: user_ptr as usize,
+        });
+        self.inner
+            .register_device_collection_changed(devtype, callback)
+    }
+}
+
+/// [`StreamOps`] of the streams of a [`SafeContext`].
+pub struct SafeStream<S> {
+    // Destroyed first, so the backend stream stops calling back before
+    // the callbacks go away.
+    inner: S,
+    shared: Arc<StreamShared>,
+}
+
+impl<S: BackendStream> StreamOps for SafeStream<S> {
+    const UNSUPPORTED: OptionalOps =
+        S::UNSUPPORTED.difference(OptionalOps::REGISTER_DEVICE_CHANGED_CALLBACK);
+
+    fn start(&mut self) -> Result<()> {
+        self.inner.start()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.inner.stop()
+    }
+
+    fn position(&mut self) -> Result<u64> {
+        self.inner.position()
+    }
+
+    fn latency(&mut self) -> Result<u32> {
+        self.inner.latency()
+    }
+
+    fn input_latency(&mut self) -> Result<u32> {
+        self.inner.input_latency()
+    }
+
+    fn set_volume(&mut self, volume: f32) -> Result<()> {
+        self.inner.set_volume(volume)
+    }
+
+    fn set_name(&mut self, name: &CStr) -> Result<()> {
+        self.inner.set_name(name)
+    }
+
+    fn current_device(&mut self) -> Result<&DeviceRef> {
+        self.inner.current_device()
+    }
+
+    fn set_input_mute(&mut self, mute: bool) -> Result<()> {
+        self.inner.set_input_mute(mute)
+    }
+
+    fn set_input_processing_params(&mut self, params: InputProcessingParams) -> Result<()> {
+        self.inner.set_input_processing_params(params)
+    }
+
+    fn device_destroy(&mut self, device: &DeviceRef) -> Result<()> {
+        self.inner.device_destroy(device)
+    }
+
+    fn register_device_changed_callback(
+        &mut self,
+        device_changed_callback: ffi::cubeb_device_changed_callback,
+    ) -> Result<()> {
+        *self.shared.device_changed.lock().unwrap() = device_changed_callback;
+        Ok(())
+    }
+}
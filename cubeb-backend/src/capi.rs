@@ -33,38 +33,77 @@ macro_rules! as_opt_ref {
 
 #[macro_export]
 macro_rules! capi_new(
-    ($ctx:ident, $stm:ident) => (
-        Ops {
+    ($ctx:ty, $stm:ty) => (
+        $crate::Ops {
             init: Some($crate::capi::capi_init::<$ctx>),
             get_backend_id: Some($crate::capi::capi_get_backend_id::<$ctx>),
-            get_max_channel_count: Some($crate::capi::capi_get_max_channel_count::<$ctx>),
-            get_min_latency: Some($crate::capi::capi_get_min_latency::<$ctx>),
-            get_preferred_sample_rate: Some($crate::capi::capi_get_preferred_sample_rate::<$ctx>),
+            get_max_channel_count: $crate::capi_optional!($ctx, ContextOps, MAX_CHANNEL_COUNT,
+                $crate::capi::capi_get_max_channel_count::<$ctx>),
+            get_min_latency: $crate::capi_optional!($ctx, ContextOps, MIN_LATENCY,
+                $crate::capi::capi_get_min_latency::<$ctx>),
+            get_preferred_sample_rate: $crate::capi_optional!($ctx, ContextOps, PREFERRED_SAMPLE_RATE,
+                $crate::capi::capi_get_preferred_sample_rate::<$ctx>),
             get_supported_input_processing_params:
-                Some($crate::capi::capi_get_supported_input_processing_params::<$ctx>),
-            enumerate_devices: Some($crate::capi::capi_enumerate_devices::<$ctx>),
-            device_collection_destroy: Some($crate::capi::capi_device_collection_destroy::<$ctx>),
+                $crate::capi_optional!($ctx, ContextOps, SUPPORTED_INPUT_PROCESSING_PARAMS,
+                    $crate::capi::capi_get_supported_input_processing_params::<$ctx>),
+            enumerate_devices: $crate::capi_optional!($ctx, ContextOps, ENUMERATE_DEVICES,
+                $crate::capi::capi_enumerate_devices::<$ctx>),
+            device_collection_destroy: $crate::capi_optional!($ctx, ContextOps, ENUMERATE_DEVICES,
+                $crate::capi::capi_device_collection_destroy::<$ctx>),
             destroy: Some($crate::capi::capi_destroy::<$ctx>),
             stream_init: Some($crate::capi::capi_stream_init::<$ctx>),
             stream_destroy: Some($crate::capi::capi_stream_destroy::<$stm>),
             stream_start: Some($crate::capi::capi_stream_start::<$stm>),
             stream_stop: Some($crate::capi::capi_stream_stop::<$stm>),
             stream_get_position: Some($crate::capi::capi_stream_get_position::<$stm>),
-            stream_get_latency: Some($crate::capi::capi_stream_get_latency::<$stm>),
-            stream_get_input_latency: Some($crate::capi::capi_stream_get_input_latency::<$stm>),
-            stream_set_volume: Some($crate::capi::capi_stream_set_volume::<$stm>),
-            stream_set_name: Some($crate::capi::capi_stream_set_name::<$stm>),
-            stream_get_current_device: Some($crate::capi::capi_stream_get_current_device::<$stm>),
-            stream_set_input_mute: Some($crate::capi::capi_stream_set_input_mute::<$stm>),
+            stream_get_latency: $crate::capi_optional!($stm, StreamOps, LATENCY,
+                $crate::capi::capi_stream_get_latency::<$stm>),
+            stream_get_input_latency: $crate::capi_optional!($stm, StreamOps, INPUT_LATENCY,
+                $crate::capi::capi_stream_get_input_latency::<$stm>),
+            stream_set_volume: $crate::capi_optional!($stm, StreamOps, SET_VOLUME,
+                $crate::capi::capi_stream_set_volume::<$stm>),
+            stream_set_name: $crate::capi_optional!($stm, StreamOps, SET_NAME,
+                $crate::capi::capi_stream_set_name::<$stm>),
+            stream_get_current_device: $crate::capi_optional!($stm, StreamOps, CURRENT_DEVICE,
+                $crate::capi::capi_stream_get_current_device::<$stm>),
+            stream_set_input_mute: $crate::capi_optional!($stm, StreamOps, SET_INPUT_MUTE,
+                $crate::capi::capi_stream_set_input_mute::<$stm>),
             stream_set_input_processing_params:
-                Some($crate::capi::capi_stream_set_input_processing_params::<$stm>),
-            stream_device_destroy: Some($crate::capi::capi_stream_device_destroy::<$stm>),
+                $crate::capi_optional!($stm, StreamOps, SET_INPUT_PROCESSING_PARAMS,
+                    $crate::capi::capi_stream_set_input_processing_params::<$stm>),
+            stream_device_destroy: $crate::capi_optional!($stm, StreamOps, CURRENT_DEVICE,
+                $crate::capi::capi_stream_device_destroy::<$stm>),
             stream_register_device_changed_callback:
-                Some($crate::capi::capi_stream_register_device_changed_callback::<$stm>),
+                $crate::capi_optional!($stm, StreamOps, REGISTER_DEVICE_CHANGED_CALLBACK,
+                    $crate::capi::capi_stream_register_device_changed_callback::<$stm>),
             register_device_collection_changed:
-                Some($crate::capi::capi_register_device_collection_changed::<$ctx>)
+                $crate::capi_optional!($ctx, ContextOps, REGISTER_DEVICE_COLLECTION_CHANGED,
+                    $crate::capi::capi_register_device_collection_changed::<$ctx>)
         }));
 
+// Entry of an optional operation of `capi_new!`, empty when listed in the
+// `UNSUPPORTED` operations of `$ty`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! capi_optional(
+    ($ty:ty, $ops:ident, $op:ident, $f:expr) => (
+        if <$ty as $crate::$ops>::UNSUPPORTED.contains($crate::OptionalOps::$op) {
+            None
+        } else {
+            Some($f)
+        }
+    ));
+
+/// Make the [`Ops`](crate::Ops) of a [`BackendContext`](crate::BackendContext).
+#[macro_export]
+macro_rules! capi_new_safe(
+    ($ctx:ty) => (
+        $crate::capi_new!(
+            $crate::SafeContext<$ctx>,
+            $crate::SafeStream<<$ctx as $crate::BackendContext>::Stream>
+        )
+    ));
+
 /// # Safety
 ///
 /// Entry point from C code.
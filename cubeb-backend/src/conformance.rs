@@ -0,0 +1,440 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Conformance suite for backends implementing [`ContextOps`] and
+//! [`StreamOps`].
+//!
+//! ```ignore
+//! #[test]
+//! fn conformance() {
+//!     let report = unsafe { conformance::run::<MyContext, MyStream>(&Config::default()) };
+//!     assert!(report.passed(), "{}", report);
+//! }
+//! ```
+
+use crate::{ContextOps, OptionalOps, StreamOps};
+use cubeb_core::{
+    ffi, ChannelLayout, DeviceType, Error, SampleFormat, State, StreamParamsBuilder,
+    StreamParamsRef,
+};
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_long, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Interval between position and latency samples while running.
+const POLL: Duration = Duration::from_millis(10);
+
+// Rate of the streams when the context has no preferred rate.
+const DEFAULT_RATE: u32 = 48_000;
+
+/// Parameters of the streams opened by [`run`].
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Rate of the streams, or the preferred rate of the context if `None`,
+    /// falling back to 48 kHz if it has none.
+    pub rate: Option<u32>,
+    pub channels: u32,
+    pub latency_frames: u32,
+    /// How long each stream plays.
+    pub run_time: Duration,
+    /// How long to wait for callbacks to stop after stopping a stream.
+    pub settle_time: Duration,
+    /// Also run an input stream.
+    pub input: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rate: None,
+            channels: 2,
+            latency_frames: 512,
+            run_time: Duration::from_millis(500),
+            settle_time: Duration::from_millis(100),
+            input: false,
+        }
+    }
+}
+
+/// Outcome of one check of the suite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    /// Why the check failed.
+    pub result: Result<(), String>,
+}
+
+/// Outcome of [`run`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether every check passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    /// The checks which failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| check.result.is_err())
+    }
+
+    fn check(&mut self, name: &'static str, result: Result<(), String>) {
+        self.checks.push(Check { name, result });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in &self.checks {
+            match &check.result {
+                Ok(()) => writeln!(f, "ok      {}", check.name)?,
+                Err(reason) => writeln!(f, "FAILED  {}: {}", check.name, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// What the harness callbacks observed, shared through the user pointer.
+struct Observed {
+    // Bytes per frame of the output buffer.
+    output_frame_bytes: usize,
+    callbacks: AtomicU64,
+    frames: AtomicU64,
+    // Set when a callback was asked for a negative number of frames.
+    negative_frames: AtomicBool,
+    states: Mutex<Vec<State>>,
+}
+
+unsafe extern "C" fn data_cb(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    _: *const c_void,
+    output: *mut c_void,
+    nframes: c_long,
+) -> c_long {
+    let observed = &*(user_ptr as *const Observed);
+    observed.callbacks.fetch_add(1, Ordering::AcqRel);
+    if nframes < 0 {
+        observed.negative_frames.store(true, Ordering::Release);
+        return 0;
+    }
+    if !output.is_null() {
+        let bytes = nframes as usize * observed.output_frame_bytes;
+        slice::from_raw_parts_mut(output as *mut u8, bytes).fill(0);
+    }
+    observed.frames.fetch_add(nframes as u64, Ordering::AcqRel);
+    nframes
+}
+
+unsafe extern "C" fn state_cb(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    state: ffi::cubeb_state,
+) {
+    let observed = &*(user_ptr as *const Observed);
+    observed.states.lock().unwrap().push(state.into());
+}
+
+/// Run the conformance suite against a backend.
+///
+/// The suite checks that enumerated devices are consistent, and opens an
+/// output stream, and an input stream if [`Config::input`] is set, to
+/// check that:
+///
+/// - The position is monotonic and doesn't run ahead of the frames the
+///   data callbacks produced.
+/// - The latencies can be queried while running.
+/// - Data callbacks run while started, and stop once stopped.
+/// - The state callback reports `Started` then `Stopped`, and nothing
+///   else.
+///
+/// Checks of optional operations listed in `UNSUPPORTED`, or returning
+/// [`Error::NotSupported`], are skipped.
+///
+/// # Safety
+///
+/// `STM` must be the type of the streams created by
+/// `CTX::stream_init`, as with [`capi_new!`](crate::capi_new). The suite
+/// casts the streams to `STM` to run and destroy them, so any other type
+/// is undefined behavior.
+pub unsafe fn run<CTX: ContextOps, STM: StreamOps>(config: &Config) -> Report {
+    let mut report = Report::default();
+    let name = c"conformance";
+    let mut ctx = match CTX::init(Some(name)) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            report.check("context init", Err(format!("{:?}", e)));
+            return report;
+        }
+    };
+    report.check("context init", Ok(()));
+
+    let empty_id = ctx.backend_id().to_bytes().is_empty();
+    report.check(
+        "backend id",
+        if empty_id {
+            Err("empty".to_string())
+        } else {
+            Ok(())
+        },
+    );
+
+    check_devices(&mut *ctx, DeviceType::INPUT, &mut report);
+    check_devices(&mut *ctx, DeviceType::OUTPUT, &mut report);
+
+    let rate = match config.rate {
+        Some(rate) => rate,
+        None if CTX::UNSUPPORTED.contains(OptionalOps::PREFERRED_SAMPLE_RATE) => DEFAULT_RATE,
+        None => match ctx.preferred_sample_rate() {
+            Ok(rate) if rate > 0 => rate,
+            Ok(rate) => {
+                report.check("preferred rate", Err(format!("{} Hz", rate)));
+                DEFAULT_RATE
+            }
+            Err(Error::NotSupported) => DEFAULT_RATE,
+            Err(e) => {
+                report.check("preferred rate", Err(format!("{:?}", e)));
+                DEFAULT_RATE
+            }
+        },
+    };
+    let layout = match config.channels {
+        1 => ChannelLayout::MONO,
+        2 => ChannelLayout::STEREO,
+        _ => ChannelLayout::UNDEFINED,
+    };
+    let params = StreamParamsBuilder::new()
+        .format(SampleFormat::Float32NE)
+        .rate(rate)
+        .channels(config.channels)
+        .layout(layout)
+        .take();
+
+    check_stream::<CTX, STM>(&mut *ctx, None, Some(&params), config, &mut report);
+    if config.input {
+        check_stream::<CTX, STM>(&mut *ctx, Some(&params), None, config, &mut report);
+    }
+    report
+}
+
+fn check_devices<CTX: ContextOps>(ctx: &mut CTX, devtype: DeviceType, report: &mut Report) {
+    let name = if devtype == DeviceType::INPUT {
+        "input devices"
+    } else {
+        "output devices"
+    };
+    if CTX::UNSUPPORTED.contains(OptionalOps::ENUMERATE_DEVICES) {
+        return;
+    }
+    let devices = match ctx.enumerate_devices(devtype) {
+        Ok(devices) => devices,
+        Err(Error::NotSupported) => return,
+        Err(e) => {
+            report.check(name, Err(format!("enumeration failed: {:?}", e)));
+            return;
+        }
+    };
+
+    let mut problems = Vec::new();
+    for (i, device) in devices.iter().enumerate() {
+        if !devtype.contains(device.device_type()) || device.device_type().is_empty() {
+            problems.push(format!("{}: type {:?}", i, device.device_type()));
+        }
+        let (min, default, max) = (device.min_rate(), device.default_rate(), device.max_rate());
+        if !(min <= default && default <= max) {
+            problems.push(format!(
+                "{}: rates {} <= {} <= {} don't hold",
+                i, min, default, max
+            ));
+        }
+        if device.latency_lo() > device.latency_hi() {
+            problems.push(format!(
+                "{}: latency_lo {} > latency_hi {}",
+                i,
+                device.latency_lo(),
+                device.latency_hi()
+            ));
+        }
+    }
+    if let Err(e) = ctx.device_collection_destroy(devices) {
+        problems.push(format!("destroy failed: {:?}", e));
+    }
+    report.check(
+        name,
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join(", "))
+        },
+    );
+}
+
+unsafe fn check_stream<CTX: ContextOps, STM: StreamOps>(
+    ctx: &mut CTX,
+    input: Option<&StreamParamsRef>,
+    output: Option<&StreamParamsRef>,
+    config: &Config,
+    report: &mut Report,
+) {
+    let output_stream = output.is_some();
+    let name = |output_name, input_name| {
+        if output_stream {
+            output_name
+        } else {
+            input_name
+        }
+    };
+    let observed = Box::new(Observed {
+        output_frame_bytes: config.channels as usize * mem::size_of::<f32>(),
+        callbacks: AtomicU64::new(0),
+        frames: AtomicU64::new(0),
+        negative_frames: AtomicBool::new(false),
+        states: Mutex::new(Vec::new()),
+    });
+    let user_ptr = &*observed as *const Observed as *mut c_void;
+
+    let stream = match ctx.stream_init(
+        Some(c"conformance"),
+        ptr::null(),
+        input,
+        ptr::null(),
+        output,
+        config.latency_frames,
+        Some(data_cb),
+        Some(state_cb),
+        user_ptr,
+    ) {
+        Ok(stream) => stream,
+        Err(e) => {
+            report.check(
+                name("output stream init", "input stream init"),
+                Err(format!("{:?}", e)),
+            );
+            return;
+        }
+    };
+    // The stream is a `STM`, destroyed like `capi_stream_destroy` does.
+    let stm = stream.as_ptr() as *mut STM;
+    mem::forget(stream);
+    let stm = &mut *stm;
+
+    let started = stm.start();
+    report.check(
+        name("output stream start", "input stream start"),
+        started.map_err(|e| format!("{:?}", e)),
+    );
+
+    let mut position_problems = Vec::new();
+    let mut latency_problems = Vec::new();
+    let mut latency_supported = !STM::UNSUPPORTED.contains(if output_stream {
+        OptionalOps::LATENCY
+    } else {
+        OptionalOps::INPUT_LATENCY
+    });
+    let mut last = 0;
+    let deadline = Instant::now() + config.run_time;
+    while Instant::now() < deadline {
+        thread::sleep(POLL);
+        match stm.position() {
+            Ok(position) => {
+                if position < last {
+                    position_problems.push(format!("went back from {} to {}", last, position));
+                }
+                // Output can't be played before it is produced.
+                let produced = observed.frames.load(Ordering::Acquire);
+                if output_stream && position > produced {
+                    position_problems.push(format!(
+                        "{} ahead of the {} frames produced",
+                        position, produced
+                    ));
+                }
+                last = position;
+            }
+            Err(e) => position_problems.push(format!("{:?}", e)),
+        }
+        if !latency_supported {
+            continue;
+        }
+        let latency = if output_stream {
+            stm.latency()
+        } else {
+            stm.input_latency()
+        };
+        // Latencies are unsigned, backends may only fail to report them.
+        match latency {
+            Err(Error::NotSupported) => latency_supported = false,
+            Err(e) => latency_problems.push(format!("{:?}", e)),
+            Ok(_) => {}
+        }
+    }
+    position_problems.dedup();
+    latency_problems.dedup();
+    let problems = |problems: Vec<String>| {
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join(", "))
+        }
+    };
+    report.check(
+        name("output position", "input position"),
+        problems(position_problems),
+    );
+    if latency_supported {
+        report.check(
+            name("output latency", "input latency"),
+            problems(latency_problems),
+        );
+    }
+
+    let callbacks = observed.callbacks.load(Ordering::Acquire);
+    report.check(
+        name("output callbacks", "input callbacks"),
+        if callbacks == 0 {
+            Err("no data callback while started".to_string())
+        } else if observed.negative_frames.load(Ordering::Acquire) {
+            Err("negative frame count".to_string())
+        } else {
+            Ok(())
+        },
+    );
+
+    let stopped = stm.stop();
+    let stopped_callbacks = observed.callbacks.load(Ordering::Acquire);
+    thread::sleep(config.settle_time);
+    let late = observed.callbacks.load(Ordering::Acquire) - stopped_callbacks;
+    report.check(
+        name("output stop", "input stop"),
+        match stopped {
+            Err(e) => Err(format!("{:?}", e)),
+            Ok(()) if late > 0 => Err(format!("{} data callbacks after stop", late)),
+            Ok(()) => Ok(()),
+        },
+    );
+
+    let states = observed.states.lock().unwrap().clone();
+    report.check(
+        name("output states", "input states"),
+        if states == [State::Started, State::Stopped] {
+            Ok(())
+        } else {
+            Err(format!("expected [Started, Stopped], got {:?}", states))
+        },
+    );
+
+    drop(Box::from_raw(stm as *mut STM));
+    // Callbacks may only run until the stream is destroyed.
+    drop(observed);
+}
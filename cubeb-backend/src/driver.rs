@@ -0,0 +1,229 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Thread calling a stream back every period.
+//!
+//! A [`CallbackDriver`] owns the audio thread of a stream: it wakes every
+//! period, reads the input from the device, calls the data callback,
+//! writes the output to the device, and reports the state changes,
+//! draining once the user produces less than a period. Backends only
+//! move the frames through a [`DeviceIo`].
+
+use crate::StreamCallbacks;
+use cubeb_core::{Error, Result, State};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Moves frames between a [`CallbackDriver`] and a device.
+///
+/// Buffers hold a period of frames in the format of the stream
+/// parameters. Errors stop the driver and are reported as
+/// [`State::Error`].
+pub trait DeviceIo: Send + 'static {
+    /// Called on the driver thread before the first period, for the
+    /// backend to raise the priority of the thread, such as with the
+    /// `audio_thread_priority` crate.
+    fn promote(&mut self, _period_frames: u32, _rate: u32) {}
+
+    /// Capture a period from the device, for streams with input.
+    fn read(&mut self, _input: &mut [u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Play a period to the device, for streams with output. When the
+    /// stream drains, the frames after those the user produced are
+    /// silent.
+    fn write(&mut self, _output: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+// What the thread hands back when it ends.
+type Parts<D> = (StreamCallbacks, D);
+
+enum Driver<D> {
+    Stopped(Parts<D>),
+    Running(JoinHandle<Parts<D>>),
+    // Only while switching between the other states.
+    Switching,
+}
+
+// State shared with the driver thread.
+struct Shared {
+    stop: AtomicBool,
+    // Frames played, or captured for input only streams.
+    position: AtomicU64,
+}
+
+/// Calls a stream back every period from a thread, through a
+/// [`DeviceIo`] of type `D`.
+pub struct CallbackDriver<D: DeviceIo> {
+    driver: Driver<D>,
+    shared: Arc<Shared>,
+    period_frames: u32,
+    rate: u32,
+}
+
+impl<D: DeviceIo> CallbackDriver<D> {
+    /// Drive the stream of `callbacks` by periods of `period_frames`
+    /// frames at `rate`.
+    pub fn new(callbacks: StreamCallbacks, device: D, period_frames: u32, rate: u32) -> Self {
+        CallbackDriver {
+            driver: Driver::Stopped((callbacks, device)),
+            shared: Arc::new(Shared {
+                stop: AtomicBool::new(false),
+                position: AtomicU64::new(0),
+            }),
+            period_frames,
+            rate,
+        }
+    }
+
+    /// Frames played so far, or captured for input only streams.
+    pub fn position(&self) -> u64 {
+        self.shared.position.load(Ordering::Acquire)
+    }
+
+    /// Whether the thread is running. It stops by itself once drained, or
+    /// on error.
+    pub fn is_running(&self) -> bool {
+        matches!(&self.driver, Driver::Running(worker) if !worker.is_finished())
+    }
+
+    /// The device, unless running.
+    pub fn device(&mut self) -> Option<&mut D> {
+        match &mut self.driver {
+            Driver::Stopped((_, device)) => Some(device),
+            _ => None,
+        }
+    }
+
+    /// Report [`State::Started`] and start calling back.
+    pub fn start(&mut self) -> Result<()> {
+        if self.period_frames == 0 || self.rate == 0 {
+            return Err(Error::InvalidParameter);
+        }
+        if self.is_running() {
+            return Ok(());
+        }
+        // Collect the thread of a drained stream.
+        self.join();
+        let Driver::Stopped((callbacks, device)) =
+            mem::replace(&mut self.driver, Driver::Switching)
+        else {
+            return Err(Error::Error);
+        };
+        self.shared.stop.store(false, Ordering::Release);
+        let shared = self.shared.clone();
+        let (period_frames, rate) = (self.period_frames, self.rate);
+        // The parts follow once the thread runs, so that they are kept
+        // when it can't be spawned.
+        let (sender, receiver) = mpsc::channel::<Parts<D>>();
+        let worker = thread::Builder::new()
+            .name("cubeb-driver".into())
+            .spawn(move || {
+                let (callbacks, device) = receiver.recv().expect("driver parts");
+                run(callbacks, device, &shared, period_frames, rate)
+            });
+        let worker = match worker {
+            Ok(worker) => worker,
+            Err(_) => {
+                self.driver = Driver::Stopped((callbacks, device));
+                return Err(Error::Error);
+            }
+        };
+        callbacks.state(State::Started);
+        // The thread waits for the parts, so sending can't fail.
+        let _ = sender.send((callbacks, device));
+        self.driver = Driver::Running(worker);
+        Ok(())
+    }
+
+    /// Stop calling back and report [`State::Stopped`].
+    pub fn stop(&mut self) -> Result<()> {
+        self.join();
+        match &self.driver {
+            Driver::Stopped((callbacks, _)) => {
+                callbacks.state(State::Stopped);
+                Ok(())
+            }
+            _ => Err(Error::Error),
+        }
+    }
+
+    // Stop the thread, without reporting it.
+    fn join(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        match mem::replace(&mut self.driver, Driver::Switching) {
+            Driver::Running(worker) => {
+                // After a panic of the device or the user, nothing can be
+                // reused.
+                if let Ok(parts) = worker.join() {
+                    self.driver = Driver::Stopped(parts);
+                }
+            }
+            driver => self.driver = driver,
+        }
+    }
+}
+
+impl<D: DeviceIo> Drop for CallbackDriver<D> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+fn run<D: DeviceIo>(
+    mut callbacks: StreamCallbacks,
+    mut device: D,
+    shared: &Shared,
+    period_frames: u32,
+    rate: u32,
+) -> Parts<D> {
+    device.promote(period_frames, rate);
+    let frames = period_frames as usize;
+    let mut input = vec![0u8; frames * callbacks.input_frame_bytes()];
+    let mut output = vec![0u8; frames * callbacks.output_frame_bytes()];
+    let period = Duration::from_secs_f64(f64::from(period_frames) / f64::from(rate));
+    let mut wake = Instant::now();
+
+    while !shared.stop.load(Ordering::Acquire) {
+        if !input.is_empty() && device.read(&mut input).is_err() {
+            callbacks.state(State::Error);
+            break;
+        }
+        let Some(produced) = callbacks.try_data(&input, &mut output) else {
+            // The user failed, which was reported.
+            break;
+        };
+        let drained = produced < frames;
+        if !output.is_empty() {
+            output[produced * callbacks.output_frame_bytes()..].fill(0);
+            if device.write(&output).is_err() {
+                callbacks.state(State::Error);
+                break;
+            }
+        }
+        let played = if output.is_empty() { frames } else { produced };
+        shared.position.fetch_add(played as u64, Ordering::AcqRel);
+        if drained {
+            callbacks.state(State::Drained);
+            break;
+        }
+
+        // Sleep to the next period, catching up after late wake ups.
+        wake += period;
+        let now = Instant::now();
+        if wake > now {
+            thread::sleep(wake - now);
+        } else {
+            wake = now;
+        }
+    }
+    (callbacks, device)
+}
@@ -0,0 +1,615 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Backend decorator injecting scripted failures, for testing recovery
+//! paths deterministically.
+
+use crate::util::{collection_changed_cb, frame_bytes, CollectionListener};
+use crate::{ContextOps, OptionalOps, StreamOps};
+use cubeb_core::{
+    ffi, DeviceId, DeviceInfo, DeviceRef, DeviceType, Error, InputProcessingParams, Result, Stream,
+    StreamParams, StreamParamsRef,
+};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::{c_long, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Calls of [`ContextOps`] and [`StreamOps`] which [`Faults`] can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Call {
+    Init,
+    MaxChannelCount,
+    MinLatency,
+    PreferredSampleRate,
+    SupportedInputProcessingParams,
+    EnumerateDevices,
+    StreamInit,
+    RegisterDeviceCollectionChanged,
+    Start,
+    Stop,
+    Position,
+    Latency,
+    InputLatency,
+    SetVolume,
+    SetName,
+    CurrentDevice,
+    SetInputMute,
+    SetInputProcessingParams,
+    RegisterDeviceChangedCallback,
+}
+
+impl Call {
+    // Every call.
+    pub(crate) const ALL: [Call; 19] = [
+        Call::Init,
+        Call::MaxChannelCount,
+        Call::MinLatency,
+        Call::PreferredSampleRate,
+        Call::SupportedInputProcessingParams,
+        Call::EnumerateDevices,
+        Call::StreamInit,
+        Call::RegisterDeviceCollectionChanged,
+        Call::Start,
+        Call::Stop,
+        Call::Position,
+        Call::Latency,
+        Call::InputLatency,
+        Call::SetVolume,
+        Call::SetName,
+        Call::CurrentDevice,
+        Call::SetInputMute,
+        Call::SetInputProcessingParams,
+        Call::RegisterDeviceChangedCallback,
+    ];
+}
+
+struct Rule {
+    call: Call,
+    // Only fail this call, counted from 0, or every call if `None`.
+    nth: Option<usize>,
+    error: Error,
+}
+
+#[derive(Default)]
+struct FaultState {
+    calls: HashMap<Call, usize>,
+    rules: Vec<Rule>,
+    drop_every: Option<usize>,
+    duplicate_every: Option<usize>,
+    short_frames: Option<usize>,
+    error_after: Option<Duration>,
+    streams: Vec<Weak<Interceptor>>,
+    listeners: Vec<Weak<CollectionListener>>,
+}
+
+/// Script of the faults injected by a [`FaultInjectingContext`] and its
+/// streams.
+///
+/// Clones share the same script, so faults can be changed while the
+/// context is in use.
+#[derive(Clone, Default)]
+pub struct Faults(Arc<Mutex<FaultState>>);
+
+// Script used by the contexts created by `FaultInjectingContext::init`.
+static INSTALLED: Mutex<Option<Faults>> = Mutex::new(None);
+
+impl Faults {
+    pub fn new() -> Faults {
+        Default::default()
+    }
+
+    /// Use this script for the contexts created from now on through
+    /// [`ContextOps::init`], such as by the C API.
+    pub fn install(&self) {
+        *INSTALLED.lock().unwrap() = Some(self.clone());
+    }
+
+    /// Fail every `call` with `error`.
+    pub fn fail(&self, call: Call, error: Error) -> &Faults {
+        let rule = Rule {
+            call,
+            nth: None,
+            error,
+        };
+        self.0.lock().unwrap().rules.push(rule);
+        self
+    }
+
+    /// Fail the `n`th `call` with `error`, counting from 0 since the
+    /// script was created.
+    pub fn fail_nth(&self, call: Call, n: usize, error: Error) -> &Faults {
+        let rule = Rule {
+            call,
+            nth: Some(n),
+            error,
+        };
+        self.0.lock().unwrap().rules.push(rule);
+        self
+    }
+
+    /// Skip every `n`th data callback of each stream, counting from 1,
+    /// and play silence instead.
+    pub fn drop_callbacks(&self, n: usize) -> &Faults {
+        self.0.lock().unwrap().drop_every = Some(n.max(1));
+        self
+    }
+
+    /// Call the data callback twice with the same buffers every `n`th
+    /// time, counting from 1.
+    pub fn duplicate_callbacks(&self, n: usize) -> &Faults {
+        self.0.lock().unwrap().duplicate_every = Some(n.max(1));
+        self
+    }
+
+    /// Ask the data callbacks for at most `frames` frames, padding the
+    /// output with silence.
+    pub fn short_buffers(&self, frames: usize) -> &Faults {
+        self.0.lock().unwrap().short_frames = Some(frames);
+        self
+    }
+
+    /// Report [`State::Error`](cubeb_core::State::Error) once a stream has
+    /// been started for `after`, then play silence without calling the
+    /// data callback.
+    pub fn error_after(&self, after: Duration) -> &Faults {
+        self.0.lock().unwrap().error_after = Some(after);
+        self
+    }
+
+    /// Remove all the faults.
+    pub fn clear(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.rules.clear();
+        state.drop_every = None;
+        state.duplicate_every = None;
+        state.short_frames = None;
+        state.error_after = None;
+    }
+
+    /// Number of times `call` was made.
+    pub fn calls(&self, call: Call) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .calls
+            .get(&call)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Call the device changed callbacks of the live streams, on the
+    /// calling thread.
+    pub fn fire_device_changed(&self) {
+        let streams: Vec<_> = {
+            let mut state = self.0.lock().unwrap();
+            state.streams.retain(|stream| stream.strong_count() > 0);
+            state.streams.iter().filter_map(Weak::upgrade).collect()
+        };
+        for stream in streams {
+            let callback = *stream.device_changed.lock().unwrap();
+            if let Some(callback) = callback {
+                unsafe { callback(stream.user_ptr as *mut c_void) };
+            }
+        }
+    }
+
+    /// Call the device collection changed callbacks registered for
+    /// `devtype`, on the calling thread.
+    pub fn fire_collection_changed(&self, devtype: DeviceType) {
+        let listeners: Vec<_> = {
+            let mut state = self.0.lock().unwrap();
+            state
+                .listeners
+                .retain(|listener| listener.strong_count() > 0);
+            state.listeners.iter().filter_map(Weak::upgrade).collect()
+        };
+        for listener in listeners {
+            if listener.devtype.intersects(devtype) {
+                unsafe { listener.call() };
+            }
+        }
+    }
+
+    // Count `call`, failing it if scripted.
+    fn check(&self, call: Call) -> Result<()> {
+        let mut state = self.0.lock().unwrap();
+        let count = state.calls.entry(call).or_insert(0);
+        let n = *count;
+        *count += 1;
+        match state
+            .rules
+            .iter()
+            .find(|rule| rule.call == call && rule.nth.is_none_or(|nth| nth == n))
+        {
+            Some(rule) => Err(rule.error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// [`ContextOps`] decorator failing the calls scripted by [`Faults`], and
+/// wrapping the streams of `C`, of type `S`, in [`FaultInjectingStream`].
+///
+/// ```ignore
+/// pub const OPS: Ops = capi_new!(
+///     FaultInjectingContext<MyContext, MyStream>,
+///     FaultInjectingStream<MyStream>
+/// );
+/// ```
+pub struct FaultInjectingContext<C, S> {
+    inner: Box<C>,
+    faults: Faults,
+    // Registered collection changed callbacks, by device type bits.
+    listeners: HashMap<ffi::cubeb_device_type, Arc<CollectionListener>>,
+    _stream: PhantomData<S>,
+}
+
+impl<C: ContextOps, S: StreamOps> FaultInjectingContext<C, S> {
+    /// Wrap an existing context.
+    pub fn wrap(inner: Box<C>, faults: Faults) -> Box<Self> {
+        Box::new(FaultInjectingContext {
+            inner,
+            faults,
+            listeners: HashMap::new(),
+            _stream: PhantomData,
+        })
+    }
+
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    pub fn inner(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+impl<C: ContextOps, S: StreamOps> ContextOps for FaultInjectingContext<C, S> {
+    const UNSUPPORTED: OptionalOps = C::UNSUPPORTED;
+
+    fn init(context_name: Option<&CStr>) -> Result<Box<Self>> {
+        let faults = INSTALLED.lock().unwrap().clone().unwrap_or_default();
+        faults.check(Call::Init)?;
+        Ok(Self::wrap(C::init(context_name)?, faults))
+    }
+
+    fn backend_id(&mut self) -> &CStr {
+        self.inner.backend_id()
+    }
+
+    fn max_channel_count(&mut self) -> Result<u32> {
+        self.faults.check(Call::MaxChannelCount)?;
+        self.inner.max_channel_count()
+    }
+
+    fn min_latency(&mut self, params: StreamParams) -> Result<u32> {
+        self.faults.check(Call::MinLatency)?;
+        self.inner.min_latency(params)
+    }
+
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        self.faults.check(Call::PreferredSampleRate)?;
+        self.inner.preferred_sample_rate()
+    }
+
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        self.faults.check(Call::SupportedInputProcessingParams)?;
+        self.inner.supported_input_processing_params()
+    }
+
+    fn enumerate_devices(&mut self, devtype: DeviceType) -> Result<Box<[DeviceInfo]>> {
+        self.faults.check(Call::EnumerateDevices)?;
+        self.inner.enumerate_devices(devtype)
+    }
+
+    fn device_collection_destroy(&mut self, collection: Box<[DeviceInfo]>) -> Result<()> {
+        self.inner.device_collection_destroy(collection)
+    }
+
+    fn stream_init(
+        &mut self,
+        stream_name: Option<&CStr>,
+        input_device: DeviceId,
+        input_stream_params: Option<&StreamParamsRef>,
+        output_device: DeviceId,
+        output_stream_params: Option<&StreamParamsRef>,
+        latency_frames: u32,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        self.faults.check(Call::StreamInit)?;
+        let interceptor = Arc::new(Interceptor {
+            faults: self.faults.clone(),
+            stream: AtomicUsize::new(0),
+            data_callback,
+            state_callback,
+            user_ptr: user_ptr as usize,
+            device_changed: Mutex::new(None),
+            output_frame_bytes: output_stream_params.map_or(0, frame_bytes),
+            callbacks: AtomicUsize::new(0),
+            started: Mutex::new(None),
+            errored: AtomicBool::new(false),
+        });
+        let inner = self.inner.stream_init(
+            stream_name,
+            input_device,
+            input_stream_params,
+            output_device,
+            output_stream_params,
+            latency_frames,
+            Some(data_cb),
+            Some(state_cb),
+            Arc::as_ptr(&interceptor) as *mut c_void,
+        )?;
+        // The inner stream is an `S`, destroyed like `capi_stream_destroy`
+        // does.
+        let inner_ptr = inner.as_ptr() as *mut S;
+        mem::forget(inner);
+        let inner = unsafe { Box::from_raw(inner_ptr) };
+        let stream = Box::into_raw(Box::new(FaultInjectingStream {
+            inner,
+            interceptor: interceptor.clone(),
+        }));
+        interceptor.stream.store(stream as usize, Ordering::Release);
+        self.faults
+            .0
+            .lock()
+            .unwrap()
+            .streams
+            .push(Arc::downgrade(&interceptor));
+        Ok(unsafe { Stream::from_ptr(stream as *mut _) })
+    }
+
+    fn register_device_collection_changed(
+        &mut self,
+        devtype: DeviceType,
+        cb: ffi::cubeb_device_collection_changed_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<()> {
+        self.faults.check(Call::RegisterDeviceCollectionChanged)?;
+        let Some(callback) = cb else {
+            self.inner
+                .register_device_collection_changed(devtype, None, ptr::null_mut())?;
+            self.listeners.remove(&devtype.bits());
+            return Ok(());
+        };
+        let listener = Arc::new(CollectionListener {
+            devtype,
+            context: self as *mut Self as usize,
+            callback,
+            user_ptr: user_ptr as usize,
+        });
+        self.inner.register_device_collection_changed(
+            devtype,
+            Some(collection_changed_cb),
+            Arc::as_ptr(&listener) as *mut c_void,
+        )?;
+        self.faults
+            .0
+            .lock()
+            .unwrap()
+            .listeners
+            .push(Arc::downgrade(&listener));
+        self.listeners.insert(devtype.bits(), listener);
+        Ok(())
+    }
+}
+
+// Stands between the inner stream and the user callbacks.
+struct Interceptor {
+    faults: Faults,
+    // The `FaultInjectingStream`, as seen by the user.
+    stream: AtomicUsize,
+    data_callback: ffi::cubeb_data_callback,
+    state_callback: ffi::cubeb_state_callback,
+    user_ptr: usize,
+    device_changed: Mutex<ffi::cubeb_device_changed_callback>,
+    output_frame_bytes: usize,
+    callbacks: AtomicUsize,
+    started: Mutex<Option<Instant>>,
+    errored: AtomicBool,
+}
+
+impl Interceptor {
+    fn stream(&self) -> *mut ffi::cubeb_stream {
+        self.stream.load(Ordering::Acquire) as *mut _
+    }
+
+    unsafe fn report_state(&self, state: ffi::cubeb_state) {
+        if let Some(callback) = self.state_callback {
+            callback(self.stream(), self.user_ptr as *mut c_void, state);
+        }
+    }
+
+    unsafe fn silence(&self, output: *mut c_void, from: usize, frames: usize) {
+        if !output.is_null() && frames > from {
+            let bytes = self.output_frame_bytes;
+            slice::from_raw_parts_mut(
+                (output as *mut u8).add(from * bytes),
+                (frames - from) * bytes,
+            )
+            .fill(0);
+        }
+    }
+}
+
+unsafe extern "C" fn data_cb(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input: *const c_void,
+    output: *mut c_void,
+    nframes: c_long,
+) -> c_long {
+    let interceptor = &*(user_ptr as *const Interceptor);
+    let Some(callback) = interceptor.data_callback else {
+        return nframes;
+    };
+    let frames = nframes.max(0) as usize;
+    let n = interceptor.callbacks.fetch_add(1, Ordering::AcqRel) + 1;
+    let (drop, duplicate, short, error_after) = {
+        let state = interceptor.faults.0.lock().unwrap();
+        (
+            state
+                .drop_every
+                .is_some_and(|every| n.is_multiple_of(every)),
+            state
+                .duplicate_every
+                .is_some_and(|every| n.is_multiple_of(every)),
+            state.short_frames,
+            state.error_after,
+        )
+    };
+
+    let started = *interceptor.started.lock().unwrap();
+    let expired = error_after
+        .zip(started)
+        .is_some_and(|(after, started)| started.elapsed() >= after);
+    if expired && !interceptor.errored.swap(true, Ordering::AcqRel) {
+        interceptor.report_state(ffi::CUBEB_STATE_ERROR);
+    }
+    if drop || interceptor.errored.load(Ordering::Acquire) {
+        interceptor.silence(output, 0, frames);
+        return nframes;
+    }
+
+    let requested = short.map_or(frames, |short| short.min(frames));
+    let call = || {
+        callback(
+            interceptor.stream(),
+            interceptor.user_ptr as *mut c_void,
+            input,
+            output,
+            requested as c_long,
+        )
+    };
+    if duplicate {
+        call();
+    }
+    let returned = call();
+    if returned < requested as c_long {
+        // Draining or failing, pass it on.
+        return returned;
+    }
+    interceptor.silence(output, requested, frames);
+    nframes
+}
+
+unsafe extern "C" fn state_cb(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    state: ffi::cubeb_state,
+) {
+    let interceptor = &*(user_ptr as *const Interceptor);
+    // Once errored, a stream reports nothing else.
+    if !interceptor.errored.load(Ordering::Acquire) {
+        interceptor.report_state(state);
+    }
+}
+
+unsafe extern "C" fn device_changed_cb(user_ptr: *mut c_void) {
+    let interceptor = &*(user_ptr as *const Interceptor);
+    let callback = *interceptor.device_changed.lock().unwrap();
+    if let Some(callback) = callback {
+        callback(interceptor.user_ptr as *mut c_void);
+    }
+}
+
+/// Stream of a [`FaultInjectingContext`], wrapping a stream of type `S`.
+pub struct FaultInjectingStream<S> {
+    // Destroyed first, so the inner stream stops calling back before the
+    // interceptor goes away.
+    inner: Box<S>,
+    interceptor: Arc<Interceptor>,
+}
+
+impl<S: StreamOps> FaultInjectingStream<S> {
+    pub fn faults(&self) -> &Faults {
+        &self.interceptor.faults
+    }
+
+    pub fn inner(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S: StreamOps> StreamOps for FaultInjectingStream<S> {
+    const UNSUPPORTED: OptionalOps = S::UNSUPPORTED;
+
+    fn start(&mut self) -> Result<()> {
+        self.faults().check(Call::Start)?;
+        self.inner.start()?;
+        let mut started = self.interceptor.started.lock().unwrap();
+        started.get_or_insert_with(Instant::now);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.faults().check(Call::Stop)?;
+        self.inner.stop()
+    }
+
+    fn position(&mut self) -> Result<u64> {
+        self.faults().check(Call::Position)?;
+        self.inner.position()
+    }
+
+    fn latency(&mut self) -> Result<u32> {
+        self.faults().check(Call::Latency)?;
+        self.inner.latency()
+    }
+
+    fn input_latency(&mut self) -> Result<u32> {
+        self.faults().check(Call::InputLatency)?;
+        self.inner.input_latency()
+    }
+
+    fn set_volume(&mut self, volume: f32) -> Result<()> {
+        self.faults().check(Call::SetVolume)?;
+        self.inner.set_volume(volume)
+    }
+
+    fn set_name(&mut self, name: &CStr) -> Result<()> {
+        self.faults().check(Call::SetName)?;
+        self.inner.set_name(name)
+    }
+
+    fn current_device(&mut self) -> Result<&DeviceRef> {
+        self.faults().check(Call::CurrentDevice)?;
+        self.inner.current_device()
+    }
+
+    fn set_input_mute(&mut self, mute: bool) -> Result<()> {
+        self.faults().check(Call::SetInputMute)?;
+        self.inner.set_input_mute(mute)
+    }
+
+    fn set_input_processing_params(&mut self, params: InputProcessingParams) -> Result<()> {
+        self.faults().check(Call::SetInputProcessingParams)?;
+        self.inner.set_input_processing_params(params)
+    }
+
+    fn device_destroy(&mut self, device: &DeviceRef) -> Result<()> {
+        self.inner.device_destroy(device)
+    }
+
+    fn register_device_changed_callback(
+        &mut self,
+        device_changed_callback: ffi::cubeb_device_changed_callback,
+    ) -> Result<()> {
+        self.faults().check(Call::RegisterDeviceChangedCallback)?;
+        let forward = device_changed_callback.map(|_| device_changed_cb as _);
+        self.inner.register_device_changed_callback(forward)?;
+        *self.interceptor.device_changed.lock().unwrap() = device_changed_callback;
+        Ok(())
+    }
+}
@@ -3,15 +3,33 @@
 // This program is made available under an ISC-style license.  See the
 // accompanying file LICENSE for details.
 
+#[macro_use]
+extern crate bitflags;
 extern crate cubeb_core;
 
+mod backend;
 pub mod capi;
+pub mod conformance;
+mod driver;
+mod fault;
 #[macro_use]
 pub mod log;
 mod ops;
+mod replay;
 mod traits;
+mod util;
 
 // Re-export cubeb_core types
+pub use crate::backend::{
+    BackendContext, BackendStream, CollectionChanged, SafeContext, SafeStream, StreamCallbacks,
+    StreamInitParams, StreamNotifier,
+};
+pub use crate::driver::{CallbackDriver, DeviceIo};
+pub use crate::fault::{Call, FaultInjectingContext, FaultInjectingStream, Faults};
 pub use crate::ops::Ops;
-pub use crate::traits::{ContextOps, StreamOps};
+pub use crate::replay::{
+    Event, Pacing, RecordingContext, RecordingStream, ReplayContext, ReplayStream, Trace,
+    TraceParams, TraceRecorder,
+};
+pub use crate::traits::{ContextOps, OptionalOps, StreamOps};
 pub use cubeb_core::*;
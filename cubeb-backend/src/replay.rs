@@ -0,0 +1,1523 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Recording of backend sessions to traces, and their offline replay.
+//!
+//! A [`RecordingContext`] wraps a backend and records every call made
+//! through it, the data callbacks with their timing, buffer sizes and
+//! input, and the state changes, to a [`Trace`]. A [`ReplayContext`]
+//! plays a trace back to an application, without any audio device.
+//!
+//! Traces are stored as the magic `CUBEBTRC`, a version byte, then the
+//! events. Each event is a tag byte followed by its fields as LEB128
+//! varints, signed values zigzag encoded, times as the difference with
+//! the previous event in microseconds.
+
+use crate::util::{collection_changed_cb, frame_bytes, CollectionListener};
+use crate::{Call, ContextOps, OptionalOps, StreamOps};
+use cubeb_core::{
+    ffi, ChannelLayout, DeviceId, DeviceInfo, DeviceRef, DeviceType, Error, InputProcessingParams,
+    Result, SampleFormat, State, Stream, StreamParams, StreamParamsRef, StreamPrefs,
+};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
+use std::os::raw::{c_long, c_void};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"CUBEBTRC";
+const VERSION: u8 = 1;
+// Longest sleep of a realtime replay between checks for `stop`.
+const MAX_SLEEP: Duration = Duration::from_millis(10);
+
+/// Parameters of a recorded stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceParams {
+    /// Sample format of the stream.
+    pub format: SampleFormat,
+    /// Sample rate, in Hz.
+    pub rate: u32,
+    /// Number of channels.
+    pub channels: u32,
+    /// Channel layout.
+    pub layout: ChannelLayout,
+    /// Stream preferences.
+    pub prefs: StreamPrefs,
+}
+
+impl TraceParams {
+    fn new(params: &StreamParamsRef) -> TraceParams {
+        TraceParams {
+            format: params.format(),
+            rate: params.rate(),
+            channels: params.channels(),
+            layout: params.layout(),
+            prefs: params.prefs(),
+        }
+    }
+}
+
+/// An event of a [`Trace`]. Times are in microseconds since the context
+/// was created, and streams are numbered in creation order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A call of a context, when `stream` is `None`, or of a stream. The
+    /// result holds the value returned by the call, 0 for calls returning
+    /// nothing and the number of devices for
+    /// [`Call::EnumerateDevices`].
+    Call {
+        time: u64,
+        stream: Option<u32>,
+        call: Call,
+        result: Result<u64>,
+    },
+    /// The opening of a stream, with its parameters and the result.
+    StreamInit {
+        time: u64,
+        stream: u32,
+        input: Option<TraceParams>,
+        output: Option<TraceParams>,
+        latency_frames: u32,
+        result: Result<()>,
+    },
+    /// A data callback, with the input passed to it.
+    Data {
+        time: u64,
+        stream: u32,
+        nframes: i64,
+        returned: i64,
+        input: Vec<u8>,
+    },
+    /// A state callback.
+    State {
+        time: u64,
+        stream: u32,
+        state: State,
+    },
+    /// A device changed callback.
+    DeviceChanged { time: u64, stream: u32 },
+    /// The destruction of a stream.
+    StreamDestroy { time: u64, stream: u32 },
+}
+
+impl Event {
+    /// Time of the event, in microseconds since the context was created.
+    pub fn time(&self) -> u64 {
+        match *self {
+            Event::Call { time, .. }
+            | Event::StreamInit { time, .. }
+            | Event::Data { time, .. }
+            | Event::State { time, .. }
+            | Event::DeviceChanged { time, .. }
+            | Event::StreamDestroy { time, .. } => time,
+        }
+    }
+
+    fn stream(&self) -> Option<u32> {
+        match *self {
+            Event::Call { stream, .. } => stream,
+            Event::StreamInit { stream, .. }
+            | Event::Data { stream, .. }
+            | Event::State { stream, .. }
+            | Event::DeviceChanged { stream, .. }
+            | Event::StreamDestroy { stream, .. } => Some(stream),
+        }
+    }
+
+    fn is_call(&self, stream: u32, call: Call) -> bool {
+        matches!(*self, Event::Call { stream: Some(s), call: c, .. } if s == stream && c == call)
+    }
+}
+
+/// A recorded sequence of [`Event`]s.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    /// Events in the order they happened.
+    pub events: Vec<Event>,
+}
+
+impl Trace {
+    /// Encode this trace to `writer`.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = Encoder { writer, time: 0 };
+        encoder.writer.write_all(MAGIC)?;
+        encoder.writer.write_all(&[VERSION])?;
+        for event in &self.events {
+            encoder.event(event)?;
+        }
+        encoder.writer.flush()
+    }
+
+    /// Decode a trace from `reader`.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Trace> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let header = MAGIC.len() + 1;
+        if bytes.len() < header || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a cubeb trace"));
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(invalid("unsupported trace version"));
+        }
+        let mut decoder = Decoder {
+            bytes: &bytes[header..],
+            time: 0,
+        };
+        let mut events = Vec::new();
+        while !decoder.bytes.is_empty() {
+            events.push(decoder.event()?);
+        }
+        Ok(Trace { events })
+    }
+
+    /// Write this trace to the file at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Read a trace from the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Trace> {
+        Trace::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Replay this trace in the contexts created from now on through
+    /// [`ContextOps::init`] of [`ReplayContext`], such as by the C API.
+    pub fn install(&self, pacing: Pacing) {
+        *INSTALLED_TRACE.lock().unwrap() = Some((self.clone(), pacing));
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Event tags.
+const CALL: u8 = 0;
+const STREAM_INIT: u8 = 1;
+const DATA: u8 = 2;
+const STATE: u8 = 3;
+const DEVICE_CHANGED: u8 = 4;
+const STREAM_DESTROY: u8 = 5;
+
+// Code of a call in traces. Codes are part of the format, so they must
+// not change.
+fn call_code(call: Call) -> u64 {
+    match call {
+        Call::Init => 0,
+        Call::MaxChannelCount => 1,
+        Call::MinLatency => 2,
+        Call::PreferredSampleRate => 3,
+        Call::SupportedInputProcessingParams => 4,
+        Call::EnumerateDevices => 5,
+        Call::StreamInit => 6,
+        Call::RegisterDeviceCollectionChanged => 7,
+        Call::Start => 8,
+        Call::Stop => 9,
+        Call::Position => 10,
+        Call::Latency => 11,
+        Call::InputLatency => 12,
+        Call::SetVolume => 13,
+        Call::SetName => 14,
+        Call::CurrentDevice => 15,
+        Call::SetInputMute => 16,
+        Call::SetInputProcessingParams => 17,
+        Call::RegisterDeviceChangedCallback => 18,
+    }
+}
+
+struct Encoder<W> {
+    writer: W,
+    // Time of the previous event.
+    time: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    fn varint(&mut self, mut x: u64) -> io::Result<()> {
+        loop {
+            let byte = (x & 0x7f) as u8;
+            x >>= 7;
+            if x == 0 {
+                return self.writer.write_all(&[byte]);
+            }
+            self.writer.write_all(&[byte | 0x80])?;
+        }
+    }
+
+    fn signed(&mut self, x: i64) -> io::Result<()> {
+        self.varint(((x << 1) ^ (x >> 63)) as u64)
+    }
+
+    fn header(&mut self, tag: u8, time: u64, stream: u32) -> io::Result<()> {
+        self.writer.write_all(&[tag])?;
+        self.varint(time.saturating_sub(self.time))?;
+        self.time = time.max(self.time);
+        self.varint(u64::from(stream))
+    }
+
+    // Errors are stored as their negated code, so 0 is success.
+    fn error(&mut self, result: Result<()>) -> io::Result<()> {
+        self.varint(result.map_or_else(|e| -(e as i64) as u64, |()| 0))
+    }
+
+    fn params(&mut self, params: &Option<TraceParams>) -> io::Result<()> {
+        let Some(params) = params else {
+            return self.varint(0);
+        };
+        self.varint(1)?;
+        self.varint(ffi::cubeb_sample_format::from(params.format) as u64)?;
+        self.varint(u64::from(params.rate))?;
+        self.varint(u64::from(params.channels))?;
+        self.varint(params.layout.bits() as u64)?;
+        self.varint(params.prefs.bits() as u64)
+    }
+
+    fn event(&mut self, event: &Event) -> io::Result<()> {
+        match event {
+            Event::Call {
+                time,
+                stream,
+                call,
+                result,
+            } => {
+                // Context calls are stream 0, streams are numbered from 1.
+                self.header(CALL, *time, stream.map_or(0, |s| s + 1))?;
+                self.varint(call_code(*call))?;
+                self.error(result.map(|_| ()))?;
+                self.varint(*result.as_ref().unwrap_or(&0))
+            }
+            Event::StreamInit {
+                time,
+                stream,
+                input,
+                output,
+                latency_frames,
+                result,
+            } => {
+                self.header(STREAM_INIT, *time, *stream)?;
+                self.params(input)?;
+                self.params(output)?;
+                self.varint(u64::from(*latency_frames))?;
+                self.error(*result)
+            }
+            Event::Data {
+                time,
+                stream,
+                nframes,
+                returned,
+                input,
+            } => {
+                self.header(DATA, *time, *stream)?;
+                self.signed(*nframes)?;
+                self.signed(*returned)?;
+                self.varint(input.len() as u64)?;
+                self.writer.write_all(input)
+            }
+            Event::State {
+                time,
+                stream,
+                state,
+            } => {
+                self.header(STATE, *time, *stream)?;
+                self.varint(ffi::cubeb_state::from(*state) as u64)
+            }
+            Event::DeviceChanged { time, stream } => self.header(DEVICE_CHANGED, *time, *stream),
+            Event::StreamDestroy { time, stream } => self.header(STREAM_DESTROY, *time, *stream),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    time: u64,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let (&byte, rest) = self
+            .bytes
+            .split_first()
+            .ok_or_else(|| invalid("truncated trace"))?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut x = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            x |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        u32::try_from(self.varint()?).map_err(|_| invalid("value out of range"))
+    }
+
+    fn signed(&mut self) -> io::Result<i64> {
+        let x = self.varint()?;
+        Ok((x >> 1) as i64 ^ -((x & 1) as i64))
+    }
+
+    fn error(&mut self) -> io::Result<Result<()>> {
+        let code = i32::try_from(self.varint()?).map_err(|_| invalid("invalid error code"))?;
+        Ok(Err(match -code {
+            ffi::CUBEB_OK => return Ok(Ok(())),
+            ffi::CUBEB_ERROR => Error::Error,
+            ffi::CUBEB_ERROR_INVALID_FORMAT => Error::InvalidFormat,
+            ffi::CUBEB_ERROR_INVALID_PARAMETER => Error::InvalidParameter,
+            ffi::CUBEB_ERROR_NOT_SUPPORTED => Error::NotSupported,
+            ffi::CUBEB_ERROR_DEVICE_UNAVAILABLE => Error::DeviceUnavailable,
+            _ => return Err(invalid("invalid error code")),
+        }))
+    }
+
+    fn format(&mut self) -> io::Result<SampleFormat> {
+        let format = self.varint()?;
+        [
+            SampleFormat::S16LE,
+            SampleFormat::S16BE,
+            SampleFormat::Float32LE,
+            SampleFormat::Float32BE,
+        ]
+        .into_iter()
+        .find(|&f| ffi::cubeb_sample_format::from(f) as u64 == format)
+        .ok_or_else(|| invalid("unknown sample format"))
+    }
+
+    fn state(&mut self) -> io::Result<State> {
+        let state = self.varint()?;
+        [State::Started, State::Stopped, State::Drained, State::Error]
+            .into_iter()
+            .find(|&s| ffi::cubeb_state::from(s) as u64 == state)
+            .ok_or_else(|| invalid("unknown state"))
+    }
+
+    fn params(&mut self) -> io::Result<Option<TraceParams>> {
+        if self.varint()? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(TraceParams {
+            format: self.format()?,
+            rate: self.u32()?,
+            channels: self.u32()?,
+            layout: ChannelLayout::from_bits_truncate(self.varint()? as _),
+            prefs: StreamPrefs::from_bits_truncate(self.varint()? as _),
+        }))
+    }
+
+    fn event(&mut self) -> io::Result<Event> {
+        let tag = self.byte()?;
+        self.time = self
+            .time
+            .checked_add(self.varint()?)
+            .ok_or_else(|| invalid("time out of range"))?;
+        let time = self.time;
+        let stream = self.u32()?;
+        Ok(match tag {
+            CALL => {
+                let code = self.varint()?;
+                let call = Call::ALL
+                    .into_iter()
+                    .find(|&call| call_code(call) == code)
+                    .ok_or_else(|| invalid("unknown call"))?;
+                let error = self.error()?;
+                let value = self.varint()?;
+                Event::Call {
+                    time,
+                    stream: stream.checked_sub(1),
+                    call,
+                    result: error.map(|()| value),
+                }
+            }
+            STREAM_INIT => Event::StreamInit {
+                time,
+                stream,
+                input: self.params()?,
+                output: self.params()?,
+                latency_frames: self.u32()?,
+                result: self.error()?,
+            },
+            DATA => {
+                let nframes = self.signed()?;
+                if nframes < 0 {
+                    return Err(invalid("negative frame count"));
+                }
+                let returned = self.signed()?;
+                let len = self.varint()? as usize;
+                if len > self.bytes.len() {
+                    return Err(invalid("truncated trace"));
+                }
+                let (input, rest) = self.bytes.split_at(len);
+                self.bytes = rest;
+                Event::Data {
+                    time,
+                    stream,
+                    nframes,
+                    returned,
+                    input: input.to_vec(),
+                }
+            }
+            STATE => Event::State {
+                time,
+                stream,
+                state: self.state()?,
+            },
+            DEVICE_CHANGED => Event::DeviceChanged { time, stream },
+            STREAM_DESTROY => Event::StreamDestroy { time, stream },
+            _ => return Err(invalid("unknown event")),
+        })
+    }
+}
+
+struct RecorderState {
+    start: Instant,
+    events: Vec<Event>,
+    streams: u32,
+}
+
+/// Collects the [`Trace`] of [`RecordingContext`]s.
+///
+/// Clones share the same trace.
+#[derive(Clone)]
+pub struct TraceRecorder(Arc<Mutex<RecorderState>>);
+
+// Recorder used by the contexts created by `RecordingContext::init`.
+static INSTALLED_RECORDER: Mutex<Option<TraceRecorder>> = Mutex::new(None);
+
+impl Default for TraceRecorder {
+    fn default() -> TraceRecorder {
+        TraceRecorder(Arc::new(Mutex::new(RecorderState {
+            start: Instant::now(),
+            events: Vec::new(),
+            streams: 0,
+        })))
+    }
+}
+
+impl TraceRecorder {
+    pub fn new() -> TraceRecorder {
+        Default::default()
+    }
+
+    /// Record the contexts created from now on through
+    /// [`ContextOps::init`] of [`RecordingContext`], such as by the C API.
+    pub fn install(&self) {
+        *INSTALLED_RECORDER.lock().unwrap() = Some(self.clone());
+    }
+
+    /// The events recorded so far.
+    pub fn trace(&self) -> Trace {
+        Trace {
+            events: self.0.lock().unwrap().events.clone(),
+        }
+    }
+
+    // Append the event made by `event` from the current time, returning
+    // its index.
+    fn push(&self, event: impl FnOnce(u64) -> Event) -> usize {
+        let mut state = self.0.lock().unwrap();
+        let time = state.start.elapsed().as_micros() as u64;
+        state.events.push(event(time));
+        state.events.len() - 1
+    }
+
+    // Record `call`, before running it so the events it causes follow.
+    fn record<T>(
+        &self,
+        stream: Option<u32>,
+        call: Call,
+        f: impl FnOnce() -> Result<T>,
+        value: impl FnOnce(&T) -> u64,
+    ) -> Result<T> {
+        let index = self.push(|time| Event::Call {
+            time,
+            stream,
+            call,
+            result: Ok(0),
+        });
+        let result = f();
+        if let Event::Call { result: r, .. } = &mut self.0.lock().unwrap().events[index] {
+            *r = result.as_ref().map(value).map_err(|e| *e);
+        }
+        result
+    }
+}
+
+/// [`ContextOps`] decorator recording the calls to it and to its streams,
+/// of type `S`, with a [`TraceRecorder`].
+///
+/// Input data is copied and the trace locked on the audio thread, so
+/// recording adds some load to the data callbacks.
+pub struct RecordingContext<C, S> {
+    inner: Box<C>,
+    recorder: TraceRecorder,
+    // Registered collection changed callbacks, by device type bits.
+    listeners: HashMap<ffi::cubeb_device_type, Arc<CollectionListener>>,
+    _stream: PhantomData<S>,
+}
+
+impl<C: ContextOps, S: StreamOps> RecordingContext<C, S> {
+    /// Wrap an existing context.
+    pub fn wrap(inner: Box<C>, recorder: TraceRecorder) -> Box<Self> {
+        Box::new(RecordingContext {
+            inner,
+            recorder,
+            listeners: HashMap::new(),
+            _stream: PhantomData,
+        })
+    }
+
+    /// The recorder of the events of this context.
+    pub fn recorder(&self) -> &TraceRecorder {
+        &self.recorder
+    }
+
+    /// The wrapped context.
+    pub fn inner(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+impl<C: ContextOps, S: StreamOps> ContextOps for RecordingContext<C, S> {
+    const UNSUPPORTED: OptionalOps = C::UNSUPPORTED;
+
+    fn init(context_name: Option<&CStr>) -> Result<Box<Self>> {
+        let recorder = INSTALLED_RECORDER
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_default();
+        let inner = recorder.record(None, Call::Init, || C::init(context_name), |_| 0)?;
+        Ok(Self::wrap(inner, recorder))
+    }
+
+    fn backend_id(&mut self) -> &CStr {
+        self.inner.backend_id()
+    }
+
+    fn max_channel_count(&mut self) -> Result<u32> {
+        let inner = &mut self.inner;
+        self.recorder.record(
+            None,
+            Call::MaxChannelCount,
+            || inner.max_channel_count(),
+            |&v| u64::from(v),
+        )
+    }
+
+    fn min_latency(&mut self, params: StreamParams) -> Result<u32> {
+        let inner = &mut self.inner;
+        self.recorder.record(
+            None,
+            Call::MinLatency,
+            || inner.min_latency(params),
+            |&v| u64::from(v),
+        )
+    }
+
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        let inner = &mut self.inner;
+        self.recorder.record(
+            None,
+            Call::PreferredSampleRate,
+            || inner.preferred_sample_rate(),
+            |&v| u64::from(v),
+        )
+    }
+
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        let inner = &mut self.inner;
+        self.recorder.record(
+            None,
+            Call::SupportedInputProcessingParams,
+            || inner.supported_input_processing_params(),
+            |v| v.bits() as u64,
+        )
+    }
+
+    fn enumerate_devices(&mut self, devtype: DeviceType) -> Result<Box<[DeviceInfo]>> {
+        let inner = &mut self.inner;
+        self.recorder.record(
+            None,
+            Call::EnumerateDevices,
+            || inner.enumerate_devices(devtype),
+            |devices| devices.len() as u64,
+        )
+    }
+
+    fn device_collection_destroy(&mut self, collection: Box<[DeviceInfo]>) -> Result<()> {
+        self.inner.device_collection_destroy(collection)
+    }
+
+    fn stream_init(
+        &mut self,
+        stream_name: Option<&CStr>,
+        input_device: DeviceId,
+        input_stream_params: Option<&StreamParamsRef>,
+        output_device: DeviceId,
+        output_stream_params: Option<&StreamParamsRef>,
+        latency_frames: u32,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        let id = {
+            let mut state = self.recorder.0.lock().unwrap();
+            state.streams += 1;
+            state.streams - 1
+        };
+        let interceptor = Arc::new(Interceptor {
+            recorder: self.recorder.clone(),
+            id,
+            stream: AtomicUsize::new(0),
+            data_callback,
+            state_callback,
+            user_ptr: user_ptr as usize,
+            device_changed: Mutex::new(None),
+            input_frame_bytes: input_stream_params.map_or(0, frame_bytes),
+        });
+        let index = self.recorder.push(|time| Event::StreamInit {
+            time,
+            stream: id,
+            input: input_stream_params.map(TraceParams::new),
+            output: output_stream_params.map(TraceParams::new),
+            latency_frames,
+            result: Ok(()),
+        });
+        let inner = self.inner.stream_init(
+            stream_name,
+            input_device,
+            input_stream_params,
+            output_device,
+            output_stream_params,
+            latency_frames,
+            Some(data_cb),
+            Some(state_cb),
+            Arc::as_ptr(&interceptor) as *mut c_void,
+        );
+        if let Event::StreamInit { result, .. } = &mut self.recorder.0.lock().unwrap().events[index]
+        {
+            *result = inner.as_ref().map(|_| ()).map_err(|e| *e);
+        }
+        let inner = inner?;
+        // The inner stream is an `S`, destroyed like `capi_stream_destroy`
+        // does.
+        let inner_ptr = inner.as_ptr() as *mut S;
+        mem::forget(inner);
+        let inner = unsafe { Box::from_raw(inner_ptr) };
+        let stream = Box::into_raw(Box::new(RecordingStream {
+            inner,
+            interceptor: interceptor.clone(),
+        }));
+        interceptor.stream.store(stream as usize, Ordering::Release);
+        Ok(unsafe { Stream::from_ptr(stream as *mut _) })
+    }
+
+    fn register_device_collection_changed(
+        &mut self,
+        devtype: DeviceType,
+        cb: ffi::cubeb_device_collection_changed_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<()> {
+        let context = self as *mut Self as usize;
+        let (inner, listeners) = (&mut self.inner, &mut self.listeners);
+        let register = || {
+            let Some(callback) = cb else {
+                inner.register_device_collection_changed(devtype, None, ptr::null_mut())?;
+                listeners.remove(&devtype.bits());
+                return Ok(());
+            };
+            let listener = Arc::new(CollectionListener {
+                devtype,
+                context,
+                callback,
+                user_ptr: user_ptr as usize,
+            });
+            inner.register_device_collection_changed(
+                devtype,
+                Some(collection_changed_cb),
+                Arc::as_ptr(&listener) as *mut c_void,
+            )?;
+            listeners.insert(devtype.bits(), listener);
+            Ok(())
+        };
+        self.recorder
+            .record(None, Call::RegisterDeviceCollectionChanged, register, |_| 0)
+    }
+}
+
+// Stands between the inner stream and the user callbacks.
+struct Interceptor {
+    recorder: TraceRecorder,
+    id: u32,
+    // The `RecordingStream`, as seen by the user.
+    stream: AtomicUsize,
+    data_callback: ffi::cubeb_data_callback,
+    state_callback: ffi::cubeb_state_callback,
+    user_ptr: usize,
+    device_changed: Mutex<ffi::cubeb_device_changed_callback>,
+    input_frame_bytes: usize,
+}
+
+unsafe extern "C" fn data_cb(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input: *const c_void,
+    output: *mut c_void,
+    nframes: c_long,
+) -> c_long {
+    let interceptor = &*(user_ptr as *const Interceptor);
+    let returned = match interceptor.data_callback {
+        Some(callback) => callback(
+            interceptor.stream.load(Ordering::Acquire) as *mut _,
+            interceptor.user_ptr as *mut c_void,
+            input,
+            output,
+            nframes,
+        ),
+        None => nframes,
+    };
+    let input = if input.is_null() || nframes <= 0 {
+        Vec::new()
+    } else {
+        let len = nframes as usize * interceptor.input_frame_bytes;
+        slice::from_raw_parts(input as *const u8, len).to_vec()
+    };
+    // `c_long` is 32 bits on Windows.
+    #[allow(clippy::useless_conversion)]
+    interceptor.recorder.push(|time| Event::Data {
+        time,
+        stream: interceptor.id,
+        nframes: i64::from(nframes),
+        returned: i64::from(returned),
+        input,
+    });
+    returned
+}
+
+unsafe extern "C" fn state_cb(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    state: ffi::cubeb_state,
+) {
+    let interceptor = &*(user_ptr as *const Interceptor);
+    interceptor.recorder.push(|time| Event::State {
+        time,
+        stream: interceptor.id,
+        state: state.into(),
+    });
+    if let Some(callback) = interceptor.state_callback {
+        callback(
+            interceptor.stream.load(Ordering::Acquire) as *mut _,
+            interceptor.user_ptr as *mut c_void,
+            state,
+        );
+    }
+}
+
+unsafe extern "C" fn device_changed_cb(user_ptr: *mut c_void) {
+    let interceptor = &*(user_ptr as *const Interceptor);
+    interceptor.recorder.push(|time| Event::DeviceChanged {
+        time,
+        stream: interceptor.id,
+    });
+    let callback = *interceptor.device_changed.lock().unwrap();
+    if let Some(callback) = callback {
+        callback(interceptor.user_ptr as *mut c_void);
+    }
+}
+
+/// Stream of a [`RecordingContext`], wrapping a stream of type `S`.
+pub struct RecordingStream<S> {
+    // Destroyed first, so the inner stream stops calling back before the
+    // interceptor goes away.
+    inner: Box<S>,
+    interceptor: Arc<Interceptor>,
+}
+
+impl<S> RecordingStream<S> {
+    /// The wrapped stream.
+    pub fn inner(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn record<T>(
+        &mut self,
+        call: Call,
+        f: impl FnOnce(&mut S) -> Result<T>,
+        value: impl FnOnce(&T) -> u64,
+    ) -> Result<T> {
+        let inner = &mut self.inner;
+        let interceptor = &self.interceptor;
+        interceptor
+            .recorder
+            .record(Some(interceptor.id), call, || f(inner), value)
+    }
+}
+
+impl<S> Drop for RecordingStream<S> {
+    fn drop(&mut self) {
+        let stream = self.interceptor.id;
+        self.interceptor
+            .recorder
+            .push(|time| Event::StreamDestroy { time, stream });
+    }
+}
+
+impl<S: StreamOps> StreamOps for RecordingStream<S> {
+    const UNSUPPORTED: OptionalOps = S::UNSUPPORTED;
+
+    fn start(&mut self) -> Result<()> {
+        self.record(Call::Start, |s| s.start(), |_| 0)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.record(Call::Stop, |s| s.stop(), |_| 0)
+    }
+
+    fn position(&mut self) -> Result<u64> {
+        self.record(Call::Position, |s| s.position(), |&v| v)
+    }
+
+    fn latency(&mut self) -> Result<u32> {
+        self.record(Call::Latency, |s| s.latency(), |&v| u64::from(v))
+    }
+
+    fn input_latency(&mut self) -> Result<u32> {
+        self.record(Call::InputLatency, |s| s.input_latency(), |&v| u64::from(v))
+    }
+
+    fn set_volume(&mut self, volume: f32) -> Result<()> {
+        self.record(Call::SetVolume, |s| s.set_volume(volume), |_| 0)
+    }
+
+    fn set_name(&mut self, name: &CStr) -> Result<()> {
+        self.record(Call::SetName, |s| s.set_name(name), |_| 0)
+    }
+
+    fn current_device(&mut self) -> Result<&DeviceRef> {
+        let interceptor = &self.interceptor;
+        let inner = &mut self.inner;
+        interceptor.recorder.record(
+            Some(interceptor.id),
+            Call::CurrentDevice,
+            || inner.current_device(),
+            |_| 0,
+        )
+    }
+
+    fn set_input_mute(&mut self, mute: bool) -> Result<()> {
+        self.record(Call::SetInputMute, |s| s.set_input_mute(mute), |_| 0)
+    }
+
+    fn set_input_processing_params(&mut self, params: InputProcessingParams) -> Result<()> {
+        self.record(
+            Call::SetInputProcessingParams,
+            |s| s.set_input_processing_params(params),
+            |_| 0,
+        )
+    }
+
+    fn device_destroy(&mut self, device: &DeviceRef) -> Result<()> {
+        self.inner.device_destroy(device)
+    }
+
+    fn register_device_changed_callback(
+        &mut self,
+        device_changed_callback: ffi::cubeb_device_changed_callback,
+    ) -> Result<()> {
+        let forward = device_changed_callback.map(|_| device_changed_cb as _);
+        self.record(
+            Call::RegisterDeviceChangedCallback,
+            |s| s.register_device_changed_callback(forward),
+            |_| 0,
+        )?;
+        *self.interceptor.device_changed.lock().unwrap() = device_changed_callback;
+        Ok(())
+    }
+}
+
+/// Timing of a replay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// Deliver the callbacks at their recorded times.
+    Realtime,
+    /// Deliver the callbacks as fast as possible.
+    Immediate,
+}
+
+// Trace used by the contexts created by `ReplayContext::init`.
+static INSTALLED_TRACE: Mutex<Option<(Trace, Pacing)>> = Mutex::new(None);
+
+// Recorded results not yet returned, by stream and call.
+type Results = HashMap<(Option<u32>, Call), VecDeque<Result<u64>>>;
+
+/// Backend replaying a [`Trace`].
+///
+/// Calls return their recorded results in order, failing with
+/// [`Error::Error`] once the recorded ones are exhausted. Streams must be
+/// created in the recorded order, with the recorded parameters. Once
+/// started, a stream delivers the recorded callbacks until stopped, with
+/// the recorded input. Streams whose recorded callbacks don't match their
+/// parameters fail to open. Devices aren't recorded, so enumeration
+/// returns no devices.
+pub struct ReplayContext {
+    trace: Arc<Trace>,
+    pacing: Pacing,
+    results: Arc<Mutex<Results>>,
+    // Indices of the stream init events not yet replayed.
+    inits: VecDeque<usize>,
+}
+
+impl ReplayContext {
+    /// Replay `trace`, skipping the recorded context init.
+    pub fn new(trace: Trace, pacing: Pacing) -> Box<ReplayContext> {
+        let mut results = Results::new();
+        let mut inits = VecDeque::new();
+        for (i, event) in trace.events.iter().enumerate() {
+            match event {
+                Event::Call {
+                    stream,
+                    call,
+                    result,
+                    ..
+                } if *call != Call::Init => {
+                    results
+                        .entry((*stream, *call))
+                        .or_default()
+                        .push_back(*result);
+                }
+                Event::StreamInit { .. } => inits.push_back(i),
+                _ => {}
+            }
+        }
+        Box::new(ReplayContext {
+            trace: Arc::new(trace),
+            pacing,
+            results: Arc::new(Mutex::new(results)),
+            inits,
+        })
+    }
+
+    fn next(&self, call: Call) -> Result<u64> {
+        next_result(&self.results, None, call)
+    }
+}
+
+fn next_result(results: &Mutex<Results>, stream: Option<u32>, call: Call) -> Result<u64> {
+    let mut results = results.lock().unwrap();
+    match results
+        .get_mut(&(stream, call))
+        .and_then(VecDeque::pop_front)
+    {
+        Some(result) => result,
+        None => Err(Error::Error),
+    }
+}
+
+impl ContextOps for ReplayContext {
+    fn init(_context_name: Option<&CStr>) -> Result<Box<Self>> {
+        let (trace, pacing) = INSTALLED_TRACE
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(Error::Error)?;
+        let init = trace.events.iter().find_map(|event| match event {
+            Event::Call {
+                call: Call::Init,
+                result,
+                ..
+            } => Some(*result),
+            _ => None,
+        });
+        init.unwrap_or(Ok(0))?;
+        Ok(ReplayContext::new(trace, pacing))
+    }
+
+    fn backend_id(&mut self) -> &CStr {
+        c"replay"
+    }
+
+    fn max_channel_count(&mut self) -> Result<u32> {
+        self.next(Call::MaxChannelCount).map(|v| v as u32)
+    }
+
+    fn min_latency(&mut self, _params: StreamParams) -> Result<u32> {
+        self.next(Call::MinLatency).map(|v| v as u32)
+    }
+
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        self.next(Call::PreferredSampleRate).map(|v| v as u32)
+    }
+
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        self.next(Call::SupportedInputProcessingParams)
+            .map(|v| InputProcessingParams::from_bits_truncate(v as _))
+    }
+
+    fn enumerate_devices(&mut self, _devtype: DeviceType) -> Result<Box<[DeviceInfo]>> {
+        self.next(Call::EnumerateDevices)?;
+        Ok(Vec::new().into_boxed_slice())
+    }
+
+    fn device_collection_destroy(&mut self, _collection: Box<[DeviceInfo]>) -> Result<()> {
+        Ok(())
+    }
+
+    fn stream_init(
+        &mut self,
+        _stream_name: Option<&CStr>,
+        _input_device: DeviceId,
+        input_stream_params: Option<&StreamParamsRef>,
+        _output_device: DeviceId,
+        output_stream_params: Option<&StreamParamsRef>,
+        _latency_frames: u32,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        let index = self.inits.pop_front().ok_or(Error::Error)?;
+        let Event::StreamInit {
+            stream: id,
+            input,
+            output,
+            result,
+            ..
+        } = &self.trace.events[index]
+        else {
+            unreachable!();
+        };
+        result.as_ref().map_err(|e| *e)?;
+        if *input != input_stream_params.map(TraceParams::new)
+            || *output != output_stream_params.map(TraceParams::new)
+        {
+            return Err(Error::InvalidParameter);
+        }
+
+        let input_frame_bytes = input_stream_params.map_or(0, frame_bytes);
+        let output_frame_bytes = output_stream_params.map_or(0, frame_bytes);
+        let events: Vec<Event> = self
+            .trace
+            .events
+            .iter()
+            .skip(index + 1)
+            .filter(|event| event.stream() == Some(*id))
+            .filter(|event| {
+                !matches!(event, Event::Call { call, .. } if *call != Call::Start && *call != Call::Stop)
+            })
+            .cloned()
+            .collect();
+        let valid = events.iter().all(|event| match *event {
+            Event::Data {
+                nframes, ref input, ..
+            } => usize::try_from(nframes).is_ok_and(|nframes| {
+                nframes.checked_mul(input_frame_bytes) == Some(input.len())
+                    && nframes.checked_mul(output_frame_bytes).is_some()
+            }),
+            _ => true,
+        });
+        if !valid {
+            return Err(Error::Error);
+        }
+        let shared = Arc::new(ReplayShared {
+            stream: AtomicUsize::new(0),
+            data_callback,
+            state_callback,
+            user_ptr: user_ptr as usize,
+            device_changed: Mutex::new(None),
+            input_frame_bytes,
+            output_frame_bytes,
+            pacing: self.pacing,
+            stop: AtomicBool::new(false),
+        });
+        let stream = Box::into_raw(Box::new(ReplayStream {
+            id: *id,
+            shared: shared.clone(),
+            results: self.results.clone(),
+            events: Arc::new(events),
+            cursor: 0,
+            worker: None,
+            device: Default::default(),
+        }));
+        shared.stream.store(stream as usize, Ordering::Release);
+        Ok(unsafe { Stream::from_ptr(stream as *mut _) })
+    }
+
+    fn register_device_collection_changed(
+        &mut self,
+        _devtype: DeviceType,
+        _cb: ffi::cubeb_device_collection_changed_callback,
+        _user_ptr: *mut c_void,
+    ) -> Result<()> {
+        self.next(Call::RegisterDeviceCollectionChanged).map(|_| ())
+    }
+}
+
+// State shared with the thread delivering the callbacks.
+struct ReplayShared {
+    // The `ReplayStream`, as seen by the user.
+    stream: AtomicUsize,
+    data_callback: ffi::cubeb_data_callback,
+    state_callback: ffi::cubeb_state_callback,
+    user_ptr: usize,
+    device_changed: Mutex<ffi::cubeb_device_changed_callback>,
+    input_frame_bytes: usize,
+    output_frame_bytes: usize,
+    pacing: Pacing,
+    stop: AtomicBool,
+}
+
+impl ReplayShared {
+    // Data events must have been checked against the frame sizes by
+    // `ReplayContext::stream_init`.
+    unsafe fn deliver(&self, event: &Event, output: &mut Vec<u8>) {
+        let stream = self.stream.load(Ordering::Acquire) as *mut ffi::cubeb_stream;
+        let user_ptr = self.user_ptr as *mut c_void;
+        match event {
+            Event::Data { nframes, input, .. } => {
+                let Some(callback) = self.data_callback else {
+                    return;
+                };
+                output.clear();
+                output.resize(*nframes as usize * self.output_frame_bytes, 0);
+                let input = if self.input_frame_bytes == 0 {
+                    ptr::null()
+                } else {
+                    input.as_ptr() as *const c_void
+                };
+                let output = if self.output_frame_bytes == 0 {
+                    ptr::null_mut()
+                } else {
+                    output.as_mut_ptr() as *mut c_void
+                };
+                callback(stream, user_ptr, input, output, *nframes as c_long);
+            }
+            Event::State { state, .. } => {
+                if let Some(callback) = self.state_callback {
+                    callback(stream, user_ptr, (*state).into());
+                }
+            }
+            Event::DeviceChanged { .. } => {
+                let callback = *self.device_changed.lock().unwrap();
+                if let Some(callback) = callback {
+                    callback(user_ptr);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Deliver `events[range]`, timed from `base`.
+    fn play(&self, events: &[Event], range: Range<usize>, base: u64) {
+        let start = Instant::now();
+        let mut output = Vec::new();
+        for event in &events[range] {
+            if self.pacing == Pacing::Realtime {
+                let due = start + Duration::from_micros(event.time().saturating_sub(base));
+                loop {
+                    let now = Instant::now();
+                    if now >= due || self.stop.load(Ordering::Acquire) {
+                        break;
+                    }
+                    thread::sleep((due - now).min(MAX_SLEEP));
+                }
+            }
+            if self.stop.load(Ordering::Acquire) {
+                return;
+            }
+            unsafe { self.deliver(event, &mut output) };
+        }
+    }
+}
+
+/// Stream of a [`ReplayContext`].
+pub struct ReplayStream {
+    id: u32,
+    shared: Arc<ReplayShared>,
+    results: Arc<Mutex<Results>>,
+    // The callbacks and the start and stop calls of this stream.
+    events: Arc<Vec<Event>>,
+    // Index of the next event to replay.
+    cursor: usize,
+    worker: Option<JoinHandle<()>>,
+    device: ffi::cubeb_device,
+}
+
+impl ReplayStream {
+    fn next(&self, call: Call) -> Result<u64> {
+        next_result(&self.results, Some(self.id), call)
+    }
+
+    fn join(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for ReplayStream {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+impl StreamOps for ReplayStream {
+    fn start(&mut self) -> Result<()> {
+        self.next(Call::Start)?;
+        self.join();
+        let events = &self.events;
+        let Some(start) = (self.cursor..events.len()).find(|&i| {
+            matches!(
+                events[i],
+                Event::Call {
+                    call: Call::Start,
+                    result: Ok(_),
+                    ..
+                }
+            )
+        }) else {
+            return Ok(());
+        };
+        let end = (start + 1..events.len())
+            .find(|&i| events[i].is_call(self.id, Call::Stop))
+            .unwrap_or(events.len());
+        self.cursor = end;
+
+        self.shared.stop.store(false, Ordering::Release);
+        let shared = self.shared.clone();
+        let events = self.events.clone();
+        let base = events[start].time();
+        self.worker = Some(thread::spawn(move || {
+            shared.play(&events, start + 1..end, base)
+        }));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        let result = self.next(Call::Stop);
+        self.join();
+        // Deliver the state changes caused by stopping.
+        if self.cursor < self.events.len() && self.events[self.cursor].is_call(self.id, Call::Stop)
+        {
+            self.cursor += 1;
+            let mut output = Vec::new();
+            while let Some(event @ Event::State { .. }) = self.events.get(self.cursor) {
+                unsafe { self.shared.deliver(event, &mut output) };
+                self.cursor += 1;
+            }
+        }
+        result.map(|_| ())
+    }
+
+    fn position(&mut self) -> Result<u64> {
+        self.next(Call::Position)
+    }
+
+    fn latency(&mut self) -> Result<u32> {
+        self.next(Call::Latency).map(|v| v as u32)
+    }
+
+    fn input_latency(&mut self) -> Result<u32> {
+        self.next(Call::InputLatency).map(|v| v as u32)
+    }
+
+    fn set_volume(&mut self, _volume: f32) -> Result<()> {
+        self.next(Call::SetVolume).map(|_| ())
+    }
+
+    fn set_name(&mut self, _name: &CStr) -> Result<()> {
+        self.next(Call::SetName).map(|_| ())
+    }
+
+    fn current_device(&mut self) -> Result<&DeviceRef> {
+        self.next(Call::CurrentDevice)?;
+        Ok(unsafe { DeviceRef::from_ptr(&mut self.device as *mut _) })
+    }
+
+    fn set_input_mute(&mut self, _mute: bool) -> Result<()> {
+        self.next(Call::SetInputMute).map(|_| ())
+    }
+
+    fn set_input_processing_params(&mut self, _params: InputProcessingParams) -> Result<()> {
+        self.next(Call::SetInputProcessingParams).map(|_| ())
+    }
+
+    fn device_destroy(&mut self, _device: &DeviceRef) -> Result<()> {
+        Ok(())
+    }
+
+    fn register_device_changed_callback(
+        &mut self,
+        device_changed_callback: ffi::cubeb_device_changed_callback,
+    ) -> Result<()> {
+        self.next(Call::RegisterDeviceChangedCallback)?;
+        *self.shared.device_changed.lock().unwrap() = device_changed_callback;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_round_trip() {
+        let params = TraceParams {
+            format: SampleFormat::Float32LE,
+            rate: 48_000,
+            channels: 2,
+            layout: ChannelLayout::STEREO,
+            prefs: StreamPrefs::VOICE,
+        };
+        let trace = Trace {
+            events: vec![
+                Event::Call {
+                    time: 5,
+                    stream: None,
+                    call: Call::PreferredSampleRate,
+                    result: Ok(48_000),
+                },
+                Event::StreamInit {
+                    time: 10,
+                    stream: 0,
+                    input: Some(params),
+                    output: None,
+                    latency_frames: 256,
+                    result: Ok(()),
+                },
+                Event::Call {
+                    time: 11,
+                    stream: Some(0),
+                    call: Call::Latency,
+                    result: Err(Error::NotSupported),
+                },
+                Event::Data {
+                    time: 1_000_000,
+                    stream: 0,
+                    nframes: 2,
+                    returned: -1,
+                    input: vec![1, 2, 3, 255],
+                },
+                Event::State {
+                    time: 1_000_001,
+                    stream: 0,
+                    state: State::Error,
+                },
+                Event::DeviceChanged {
+                    time: 1_000_001,
+                    stream: 0,
+                },
+                Event::StreamDestroy {
+                    time: 2_000_000,
+                    stream: 0,
+                },
+            ],
+        };
+        let mut bytes = Vec::new();
+        trace.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..8], MAGIC);
+        assert_eq!(Trace::read_from(&bytes[..]).unwrap(), trace);
+
+        let truncated = Trace::read_from(&bytes[..bytes.len() - 1]);
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(Trace::read_from(&b"CUBEBTRC\x02"[..]).is_err());
+    }
+
+    #[test]
+    fn call_codes() {
+        let mut codes: Vec<_> = Call::ALL.into_iter().map(call_code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), Call::ALL.len());
+        assert_eq!(call_code(Call::Start), 8);
+    }
+
+    #[test]
+    fn corrupt_trace() {
+        let trace = Trace {
+            events: vec![
+                Event::StreamInit {
+                    time: 10,
+                    stream: 0,
+                    input: None,
+                    output: Some(TraceParams {
+                        format: SampleFormat::S16LE,
+                        rate: 44_100,
+                        channels: 1,
+                        layout: ChannelLayout::MONO,
+                        prefs: StreamPrefs::NONE,
+                    }),
+                    latency_frames: 128,
+                    result: Err(Error::DeviceUnavailable),
+                },
+                Event::State {
+                    time: 20,
+                    stream: 0,
+                    state: State::Drained,
+                },
+            ],
+        };
+        let mut bytes = Vec::new();
+        trace.write_to(&mut bytes).unwrap();
+        let events = MAGIC.len() + 1;
+
+        // Every corrupted byte and every truncation decodes or fails, but
+        // never panics.
+        for i in MAGIC.len()..bytes.len() {
+            for value in [0x00, 0x01, 0x05, 0x7f, 0x80, 0xff] {
+                let mut corrupt = bytes.clone();
+                corrupt[i] = value;
+                let _ = Trace::read_from(&corrupt[..]);
+            }
+            let _ = Trace::read_from(&bytes[..i]);
+        }
+
+        // A long run of continuation bytes.
+        let mut corrupt = bytes[..events].to_vec();
+        corrupt.push(STATE);
+        corrupt.extend([0xff; 11]);
+        assert!(Trace::read_from(&corrupt[..]).is_err());
+
+        // Unknown values are rejected rather than mapped to defaults.
+        let event = |tag, rest: &[u8]| {
+            let mut corrupt = bytes[..events].to_vec();
+            corrupt.extend([tag, 0, 0]);
+            corrupt.extend(rest);
+            Trace::read_from(&corrupt[..]).unwrap_err().kind()
+        };
+        assert_eq!(event(STATE, &[9]), io::ErrorKind::InvalidData);
+        assert_eq!(event(CALL, &[0, 7, 0]), io::ErrorKind::InvalidData);
+        // A frame count of -1.
+        assert_eq!(event(DATA, &[1, 0, 0]), io::ErrorKind::InvalidData);
+        assert_eq!(
+            event(STREAM_INIT, &[1, 9, 1, 1, 0, 0, 0, 0, 0]),
+            io::ErrorKind::InvalidData
+        );
+
+        // Time deltas overflowing the clock.
+        let mut corrupt = bytes[..events].to_vec();
+        for _ in 0..2 {
+            corrupt.extend([DEVICE_CHANGED]);
+            corrupt.extend([0xff; 9]);
+            corrupt.extend([0x01, 0]);
+        }
+        assert!(Trace::read_from(&corrupt[..]).is_err());
+    }
+}
@@ -4,22 +4,72 @@
 // accompanying file LICENSE for details.
 
 use crate::ffi;
+use crate::util::unsupported;
 use cubeb_core::{
-    DeviceId, DeviceInfo, DeviceRef, DeviceType, InputProcessingParams, Result, Stream,
+    DeviceId, DeviceInfo, DeviceRef, DeviceType, Error, InputProcessingParams, Result, Stream,
     StreamParams, StreamParamsRef,
 };
 use std::ffi::CStr;
 use std::os::raw::c_void;
 
+bitflags! {
+    /// Operations a backend may leave unimplemented.
+    ///
+    /// `capi_new!` leaves the [`Ops`](crate::Ops) entries of the
+    /// operations listed in [`ContextOps::UNSUPPORTED`] and
+    /// [`StreamOps::UNSUPPORTED`] empty, so libcubeb's fallbacks apply.
+    pub struct OptionalOps: u32 {
+        const MAX_CHANNEL_COUNT = 1 << 0;
+        const MIN_LATENCY = 1 << 1;
+        const PREFERRED_SAMPLE_RATE = 1 << 2;
+        const SUPPORTED_INPUT_PROCESSING_PARAMS = 1 << 3;
+        /// Device enumeration, and destruction of the device collections.
+        const ENUMERATE_DEVICES = 1 << 4;
+        const REGISTER_DEVICE_COLLECTION_CHANGED = 1 << 5;
+        const LATENCY = 1 << 6;
+        const INPUT_LATENCY = 1 << 7;
+        const SET_VOLUME = 1 << 8;
+        const SET_NAME = 1 << 9;
+        /// Current device queries, and destruction of the devices.
+        const CURRENT_DEVICE = 1 << 10;
+        const SET_INPUT_MUTE = 1 << 11;
+        const SET_INPUT_PROCESSING_PARAMS = 1 << 12;
+        const REGISTER_DEVICE_CHANGED_CALLBACK = 1 << 13;
+    }
+}
+
+/// Operations with a default implementation are optional. Those a
+/// backend doesn't implement must be listed in [`ContextOps::UNSUPPORTED`],
+/// which leaves them out of the [`Ops`](crate::Ops) table so libcubeb's
+/// fallbacks apply; keeping a default without listing it fails to build.
 pub trait ContextOps {
+    /// Optional operations left out of the [`Ops`](crate::Ops) table,
+    /// such as [`OptionalOps::all()`] for a backend implementing none.
+    const UNSUPPORTED: OptionalOps = OptionalOps::empty();
+
     fn init(context_name: Option<&CStr>) -> Result<Box<Self>>;
     fn backend_id(&mut self) -> &CStr;
-    fn max_channel_count(&mut self) -> Result<u32>;
-    fn min_latency(&mut self, params: StreamParams) -> Result<u32>;
-    fn preferred_sample_rate(&mut self) -> Result<u32>;
-    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams>;
-    fn enumerate_devices(&mut self, devtype: DeviceType) -> Result<Box<[DeviceInfo]>>;
-    fn device_collection_destroy(&mut self, collection: Box<[DeviceInfo]>) -> Result<()>;
+    fn max_channel_count(&mut self) -> Result<u32> {
+        unsupported!(MAX_CHANNEL_COUNT)
+    }
+    fn min_latency(&mut self, _params: StreamParams) -> Result<u32> {
+        unsupported!(MIN_LATENCY)
+    }
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        unsupported!(PREFERRED_SAMPLE_RATE)
+    }
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        unsupported!(SUPPORTED_INPUT_PROCESSING_PARAMS)
+    }
+    fn enumerate_devices(&mut self, _devtype: DeviceType) -> Result<Box<[DeviceInfo]>> {
+        unsupported!(ENUMERATE_DEVICES)
+    }
+    /// Release a collection returned by `enumerate_devices`, dropping it by
+    /// default.
+    fn device_collection_destroy(&mut self, collection: Box<[DeviceInfo]>) -> Result<()> {
+        drop(collection);
+        Ok(())
+    }
     #[allow(clippy::too_many_arguments)]
     fn stream_init(
         &mut self,
@@ -35,26 +85,56 @@ pub trait ContextOps {
     ) -> Result<Stream>;
     fn register_device_collection_changed(
         &mut self,
-        devtype: DeviceType,
-        cb: ffi::cubeb_device_collection_changed_callback,
-        user_ptr: *mut c_void,
-    ) -> Result<()>;
+        _devtype: DeviceType,
+        _cb: ffi::cubeb_device_collection_changed_callback,
+        _user_ptr: *mut c_void,
+    ) -> Result<()> {
+        unsupported!(REGISTER_DEVICE_COLLECTION_CHANGED)
+    }
 }
 
+/// Operations with a default implementation are optional. Those a
+/// backend doesn't implement must be listed in [`StreamOps::UNSUPPORTED`],
+/// which leaves them out of the [`Ops`](crate::Ops) table so libcubeb's
+/// fallbacks apply; keeping a default without listing it fails to build.
 pub trait StreamOps {
+    /// Optional operations left out of the [`Ops`](crate::Ops) table,
+    /// such as [`OptionalOps::all()`] for a backend implementing none.
+    const UNSUPPORTED: OptionalOps = OptionalOps::empty();
+
     fn start(&mut self) -> Result<()>;
     fn stop(&mut self) -> Result<()>;
     fn position(&mut self) -> Result<u64>;
-    fn latency(&mut self) -> Result<u32>;
-    fn input_latency(&mut self) -> Result<u32>;
-    fn set_volume(&mut self, volume: f32) -> Result<()>;
-    fn set_name(&mut self, name: &CStr) -> Result<()>;
-    fn current_device(&mut self) -> Result<&DeviceRef>;
-    fn set_input_mute(&mut self, mute: bool) -> Result<()>;
-    fn set_input_processing_params(&mut self, params: InputProcessingParams) -> Result<()>;
-    fn device_destroy(&mut self, device: &DeviceRef) -> Result<()>;
+    fn latency(&mut self) -> Result<u32> {
+        unsupported!(LATENCY)
+    }
+    fn input_latency(&mut self) -> Result<u32> {
+        unsupported!(INPUT_LATENCY)
+    }
+    fn set_volume(&mut self, _volume: f32) -> Result<()> {
+        unsupported!(SET_VOLUME)
+    }
+    fn set_name(&mut self, _name: &CStr) -> Result<()> {
+        unsupported!(SET_NAME)
+    }
+    fn current_device(&mut self) -> Result<&DeviceRef> {
+        unsupported!(CURRENT_DEVICE)
+    }
+    fn set_input_mute(&mut self, _mute: bool) -> Result<()> {
+        unsupported!(SET_INPUT_MUTE)
+    }
+    fn set_input_processing_params(&mut self, _params: InputProcessingParams) -> Result<()> {
+        unsupported!(SET_INPUT_PROCESSING_PARAMS)
+    }
+    /// Release a device returned by `current_device`, doing nothing by
+    /// default.
+    fn device_destroy(&mut self, _device: &DeviceRef) -> Result<()> {
+        Ok(())
+    }
     fn register_device_changed_callback(
         &mut self,
-        device_changed_callback: ffi::cubeb_device_changed_callback,
-    ) -> Result<()>;
+        _device_changed_callback: ffi::cubeb_device_changed_callback,
+    ) -> Result<()> {
+        unsupported!(REGISTER_DEVICE_CHANGED_CALLBACK)
+    }
 }
@@ -0,0 +1,63 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details.
+
+//! Helpers shared by the backend wrappers.
+
+use cubeb_core::{ffi, DeviceType, SampleFormat, StreamParamsRef};
+use std::os::raw::c_void;
+
+// Bytes per frame of a stream with `params`.
+pub(crate) fn frame_bytes(params: &StreamParamsRef) -> usize {
+    let sample_bytes = match params.format() {
+        SampleFormat::S16LE | SampleFormat::S16BE | SampleFormat::S16NE => 2,
+        SampleFormat::Float32LE | SampleFormat::Float32BE | SampleFormat::Float32NE => 4,
+    };
+    sample_bytes * params.channels() as usize
+}
+
+// A device collection changed callback registered by the user.
+pub(crate) struct CollectionListener {
+    pub(crate) devtype: DeviceType,
+    // The wrapping context, as seen by the user.
+    pub(crate) context: usize,
+    pub(crate) callback: unsafe extern "C" fn(*mut ffi::cubeb, *mut c_void),
+    pub(crate) user_ptr: usize,
+}
+
+impl CollectionListener {
+    pub(crate) unsafe fn call(&self) {
+        (self.callback)(
+            self.context as *mut ffi::cubeb,
+            self.user_ptr as *mut c_void,
+        );
+    }
+}
+
+// Device collection changed callback of the inner context, whose user
+// pointer is a `CollectionListener`.
+pub(crate) unsafe extern "C" fn collection_changed_cb(_: *mut ffi::cubeb, user_ptr: *mut c_void) {
+    let listener = &*(user_ptr as *const CollectionListener);
+    listener.call();
+}
+
+// Default implementation of the optional operation `$op`, failing to
+// build unless `Self::UNSUPPORTED` lists it, so that a backend can't keep
+// the default while still exposing the operation.
+macro_rules! unsupported {
+    ($op:ident) => {{
+        const {
+            assert!(
+                Self::UNSUPPORTED.contains(OptionalOps::$op),
+                concat!(
+                    "implement the operation or list OptionalOps::",
+                    stringify!($op),
+                    " in UNSUPPORTED"
+                )
+            )
+        };
+        Err(Error::NotSupported)
+    }};
+}
+pub(crate) use unsupported;
@@ -0,0 +1,275 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details
+
+// Each test crate only uses part of the fixture.
+#![allow(dead_code)]
+
+use cubeb_backend::{
+    ffi, ContextOps, DeviceId, DeviceType, OptionalOps, Result, SampleFormat, Stream, StreamOps,
+    StreamParamsBuilder, StreamParamsRef,
+};
+use std::ffi::CStr;
+use std::os::raw::{c_long, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+pub const RATE: u32 = 48_000;
+pub const LATENCY: u32 = 128;
+
+// A mono S16 backend whose callbacks are driven by the test.
+pub struct ManualContext {
+    pub collection_changed: Option<(ffi::cubeb_device_collection_changed_callback, usize)>,
+}
+
+impl ContextOps for ManualContext {
+    const UNSUPPORTED: OptionalOps = OptionalOps::MIN_LATENCY
+        .union(OptionalOps::SUPPORTED_INPUT_PROCESSING_PARAMS)
+        .union(OptionalOps::ENUMERATE_DEVICES);
+
+    fn init(_context_name: Option<&CStr>) -> Result<Box<Self>> {
+        Ok(Box::new(ManualContext {
+            collection_changed: None,
+        }))
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"manual"
+    }
+    fn max_channel_count(&mut self) -> Result<u32> {
+        Ok(1)
+    }
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        Ok(RATE)
+    }
+    fn stream_init(
+        &mut self,
+        _stream_name: Option<&CStr>,
+        _input_device: DeviceId,
+        _input_stream_params: Option<&StreamParamsRef>,
+        _output_device: DeviceId,
+        _output_stream_params: Option<&StreamParamsRef>,
+        _latency_frames: u32,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        let stm = Box::new(ManualStream {
+            data_callback,
+            state_callback,
+            user_ptr,
+            device_changed: None,
+            position: 0,
+        });
+        Ok(unsafe { Stream::from_ptr(Box::into_raw(stm) as *mut _) })
+    }
+    fn register_device_collection_changed(
+        &mut self,
+        _devtype: DeviceType,
+        cb: ffi::cubeb_device_collection_changed_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<()> {
+        self.collection_changed = cb.map(|_| (cb, user_ptr as usize));
+        Ok(())
+    }
+}
+
+pub struct ManualStream {
+    pub data_callback: ffi::cubeb_data_callback,
+    pub state_callback: ffi::cubeb_state_callback,
+    pub user_ptr: *mut c_void,
+    pub device_changed: ffi::cubeb_device_changed_callback,
+    // Frames asked for so far.
+    position: u64,
+}
+
+impl ManualStream {
+    // Ask an output stream for `frames` frames, returning the frame count
+    // and the output.
+    pub fn tick(&mut self, frames: usize) -> (c_long, Vec<i16>) {
+        self.call(ptr::null(), frames)
+    }
+
+    // Ask a duplex stream for as many frames as `input` has.
+    pub fn tick_duplex(&mut self, input: &[i16]) -> (c_long, Vec<i16>) {
+        self.call(input.as_ptr(), input.len())
+    }
+
+    fn call(&mut self, input: *const i16, frames: usize) -> (c_long, Vec<i16>) {
+        self.position += frames as u64;
+        let mut output = vec![-1; frames];
+        let returned = unsafe {
+            self.data_callback.unwrap()(
+                ptr::null_mut(),
+                self.user_ptr,
+                input as *const c_void,
+                output.as_mut_ptr() as *mut c_void,
+                frames as c_long,
+            )
+        };
+        (returned, output)
+    }
+
+    fn report(&mut self, state: ffi::cubeb_state) {
+        unsafe { self.state_callback.unwrap()(ptr::null_mut(), self.user_ptr, state) };
+    }
+}
+
+impl StreamOps for ManualStream {
+    const UNSUPPORTED: OptionalOps = OptionalOps::INPUT_LATENCY
+        .union(OptionalOps::SET_VOLUME)
+        .union(OptionalOps::SET_NAME)
+        .union(OptionalOps::CURRENT_DEVICE)
+        .union(OptionalOps::SET_INPUT_MUTE)
+        .union(OptionalOps::SET_INPUT_PROCESSING_PARAMS);
+
+    fn start(&mut self) -> Result<()> {
+        self.report(ffi::CUBEB_STATE_STARTED);
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<()> {
+        self.report(ffi::CUBEB_STATE_STOPPED);
+        Ok(())
+    }
+    fn position(&mut self) -> Result<u64> {
+        Ok(self.position)
+    }
+    fn latency(&mut self) -> Result<u32> {
+        Ok(LATENCY)
+    }
+    fn register_device_changed_callback(
+        &mut self,
+        device_changed_callback: ffi::cubeb_device_changed_callback,
+    ) -> Result<()> {
+        self.device_changed = device_changed_callback;
+        Ok(())
+    }
+}
+
+// What the user callbacks of a stream saw.
+pub struct User {
+    // The stream passed to the data callback, checked unless zero.
+    pub stream: AtomicUsize,
+    // Frames asked for by each data callback.
+    pub requested: Mutex<Vec<c_long>>,
+    pub input: Mutex<Vec<i16>>,
+    pub states: Mutex<Vec<ffi::cubeb_state>>,
+    pub device_changes: AtomicUsize,
+    pub collection_changes: AtomicUsize,
+    // Frames to produce before draining.
+    pub frames_left: AtomicUsize,
+    // Frames produced so far.
+    pub produced: AtomicUsize,
+}
+
+impl Default for User {
+    fn default() -> User {
+        User::draining_after(usize::MAX)
+    }
+}
+
+impl User {
+    // A user draining the stream after producing `frames` frames.
+    pub fn draining_after(frames: usize) -> User {
+        User {
+            stream: AtomicUsize::new(0),
+            requested: Mutex::new(Vec::new()),
+            input: Mutex::new(Vec::new()),
+            states: Mutex::new(Vec::new()),
+            device_changes: AtomicUsize::new(0),
+            collection_changes: AtomicUsize::new(0),
+            frames_left: AtomicUsize::new(frames),
+            produced: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn user_ptr(&self) -> *mut c_void {
+        self as *const User as *mut c_void
+    }
+}
+
+// The samples `user_data_cb` produces, numbering the frames from 1 so
+// that they can't be mistaken for silence.
+pub fn produced(frames: std::ops::Range<usize>) -> Vec<i16> {
+    frames.map(|i| (i + 1) as i16).collect()
+}
+
+// Record the input, and output the next frames of `produced` until
+// `User::frames_left` runs out.
+pub unsafe extern "C" fn user_data_cb(
+    stream: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    input: *const c_void,
+    output: *mut c_void,
+    nframes: c_long,
+) -> c_long {
+    let user = &*(user_ptr as *const User);
+    let expected = user.stream.load(Ordering::Acquire);
+    if expected != 0 {
+        assert_eq!(stream as usize, expected);
+    }
+    user.requested.lock().unwrap().push(nframes);
+    let nframes = nframes as usize;
+    if !input.is_null() {
+        let input = std::slice::from_raw_parts(input as *const i16, nframes);
+        user.input.lock().unwrap().extend_from_slice(input);
+    }
+    let left = user.frames_left.load(Ordering::Acquire);
+    let count = nframes.min(left);
+    user.frames_left.store(left - count, Ordering::Release);
+    let start = user.produced.fetch_add(count, Ordering::AcqRel);
+    if !output.is_null() {
+        let output = std::slice::from_raw_parts_mut(output as *mut i16, nframes);
+        output[..count].copy_from_slice(&produced(start..start + count));
+    }
+    count as c_long
+}
+
+pub unsafe extern "C" fn user_state_cb(
+    _: *mut ffi::cubeb_stream,
+    user_ptr: *mut c_void,
+    state: ffi::cubeb_state,
+) {
+    let user = &*(user_ptr as *const User);
+    user.states.lock().unwrap().push(state);
+}
+
+pub unsafe extern "C" fn user_device_changed_cb(user_ptr: *mut c_void) {
+    let user = &*(user_ptr as *const User);
+    user.device_changes.fetch_add(1, Ordering::Relaxed);
+}
+
+pub unsafe extern "C" fn user_collection_changed_cb(_: *mut ffi::cubeb, user_ptr: *mut c_void) {
+    let user = &*(user_ptr as *const User);
+    user.collection_changes.fetch_add(1, Ordering::Relaxed);
+}
+
+// The mono S16 parameters of the test streams.
+pub fn params() -> cubeb_backend::StreamParams {
+    StreamParamsBuilder::new()
+        .format(SampleFormat::S16NE)
+        .rate(RATE)
+        .channels(1)
+        .take()
+}
+
+// Open a mono S16 output stream, with input if `duplex`, calling back
+// `user`, and return it as the `S` that `ctx` creates.
+pub fn stream_init<C: ContextOps, S>(ctx: &mut C, duplex: bool, user: &User) -> Result<Box<S>> {
+    let params = params();
+    let stream = ctx.stream_init(
+        None,
+        ptr::null(),
+        if duplex { Some(&params) } else { None },
+        ptr::null(),
+        Some(&params),
+        LATENCY,
+        Some(user_data_cb),
+        Some(user_state_cb),
+        user.user_ptr(),
+    )?;
+    let stm = stream.as_ptr() as *mut S;
+    std::mem::forget(stream);
+    Ok(unsafe { Box::from_raw(stm) })
+}
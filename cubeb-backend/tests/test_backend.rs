@@ -0,0 +1,363 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details
+
+extern crate cubeb_backend;
+
+mod common;
+
+use common::{
+    params, produced, user_collection_changed_cb, user_data_cb, user_device_changed_cb,
+    user_state_cb, User, RATE,
+};
+use cubeb_backend::{
+    capi_new_safe, ffi, BackendContext, BackendStream, CollectionChanged, ContextOps, DeviceInfo,
+    DeviceType, Error, InputProcessingParams, Ops, OptionalOps, Result, SafeContext, State,
+    StreamCallbacks, StreamInitParams, StreamNotifier, StreamParams,
+};
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+
+pub const OPS: Ops = capi_new_safe!(ThreadContext);
+
+const PERIOD: usize = 64;
+
+// A duplex backend calling back from a thread for a few periods, looping
+// its output back to its input.
+#[derive(Default)]
+struct ThreadContext {
+    notifiers: Vec<StreamNotifier>,
+    collection_changed: Option<CollectionChanged>,
+}
+
+impl BackendContext for ThreadContext {
+    type Stream = ThreadStream;
+
+    fn init(_context_name: Option<&CStr>) -> Result<Self> {
+        Ok(ThreadContext::default())
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"thread"
+    }
+    fn max_channel_count(&mut self) -> Result<u32> {
+        Ok(2)
+    }
+    fn min_latency(&mut self, _params: StreamParams) -> Result<u32> {
+        Ok(PERIOD as u32)
+    }
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        Ok(RATE)
+    }
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        Ok(InputProcessingParams::NONE)
+    }
+    fn enumerate_devices(&mut self, _devtype: DeviceType) -> Result<Vec<DeviceInfo>> {
+        Ok(vec![DeviceInfo::default()])
+    }
+    fn stream_init(
+        &mut self,
+        params: &StreamInitParams,
+        callbacks: StreamCallbacks,
+    ) -> Result<ThreadStream> {
+        if params.input_stream_params.is_none() || params.output_stream_params.is_none() {
+            return Err(Error::NotSupported);
+        }
+        self.notifiers.push(callbacks.notifier());
+        Ok(ThreadStream {
+            callbacks: Some(callbacks),
+            worker: None,
+        })
+    }
+    fn register_device_collection_changed(
+        &mut self,
+        _devtype: DeviceType,
+        callback: Option<CollectionChanged>,
+    ) -> Result<()> {
+        self.collection_changed = callback;
+        Ok(())
+    }
+}
+
+struct ThreadStream {
+    callbacks: Option<StreamCallbacks>,
+    worker: Option<JoinHandle<StreamCallbacks>>,
+}
+
+impl BackendStream for ThreadStream {
+    const UNSUPPORTED: OptionalOps = OptionalOps::SET_NAME
+        .union(OptionalOps::CURRENT_DEVICE)
+        .union(OptionalOps::SET_INPUT_MUTE)
+        .union(OptionalOps::SET_INPUT_PROCESSING_PARAMS);
+
+    fn start(&mut self) -> Result<()> {
+        let mut callbacks = self.callbacks.take().ok_or(Error::Error)?;
+        callbacks.state(State::Started);
+        self.worker = Some(thread::spawn(move || {
+            let mut input = vec![0u8; PERIOD * callbacks.input_frame_bytes()];
+            let mut output = vec![0u8; PERIOD * callbacks.output_frame_bytes()];
+            for _ in 0..3 {
+                if callbacks.data(&input, &mut output) < PERIOD {
+                    callbacks.state(State::Drained);
+                    break;
+                }
+                input.copy_from_slice(&output);
+            }
+            callbacks
+        }));
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<()> {
+        let callbacks = self.worker.take().ok_or(Error::Error)?.join().unwrap();
+        callbacks.state(State::Stopped);
+        self.callbacks = Some(callbacks);
+        Ok(())
+    }
+    fn position(&mut self) -> Result<u64> {
+        Ok(0)
+    }
+    fn latency(&mut self) -> Result<u32> {
+        Ok(PERIOD as u32)
+    }
+    fn input_latency(&mut self) -> Result<u32> {
+        Ok(PERIOD as u32)
+    }
+    fn set_volume(&mut self, _volume: f32) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Run a duplex mono S16 stream through the C API until it stops or
+// drains.
+fn run(user: &User) {
+    let mut c: *mut ffi::cubeb = ptr::null_mut();
+    assert_eq!(
+        unsafe { OPS.init.unwrap()(&mut c, ptr::null()) },
+        ffi::CUBEB_OK
+    );
+    let id = unsafe { CStr::from_ptr(OPS.get_backend_id.unwrap()(c)) };
+    assert_eq!(id, c"thread");
+
+    let params = params();
+    let user_ptr = user.user_ptr();
+    let mut s: *mut ffi::cubeb_stream = ptr::null_mut();
+    assert_eq!(
+        unsafe {
+            OPS.stream_init.unwrap()(
+                c,
+                &mut s,
+                ptr::null(),
+                ptr::null(),
+                params.as_ptr(),
+                ptr::null(),
+                params.as_ptr(),
+                PERIOD as u32,
+                Some(user_data_cb),
+                Some(user_state_cb),
+                user_ptr,
+            )
+        },
+        ffi::CUBEB_OK
+    );
+    user.stream.store(s as usize, Ordering::Release);
+    assert_eq!(
+        unsafe {
+            OPS.stream_register_device_changed_callback.unwrap()(s, Some(user_device_changed_cb))
+        },
+        ffi::CUBEB_OK
+    );
+    assert_eq!(
+        unsafe {
+            OPS.register_device_collection_changed.unwrap()(
+                c,
+                ffi::CUBEB_DEVICE_TYPE_OUTPUT,
+                Some(user_collection_changed_cb),
+                user_ptr,
+            )
+        },
+        ffi::CUBEB_OK
+    );
+
+    unsafe {
+        assert_eq!(OPS.stream_start.unwrap()(s), ffi::CUBEB_OK);
+        assert_eq!(OPS.stream_stop.unwrap()(s), ffi::CUBEB_OK);
+    }
+
+    let ctx = unsafe { &mut *(c as *mut SafeContext<ThreadContext>) };
+    ctx.inner().notifiers[0].device_changed();
+    ctx.inner().collection_changed.as_ref().unwrap().notify();
+
+    unsafe {
+        OPS.stream_destroy.unwrap()(s);
+        OPS.destroy.unwrap()(c);
+    }
+}
+
+#[test]
+fn test_backend_callbacks() {
+    let user = User::default();
+    run(&user);
+
+    // The output of each period is looped back as the input of the next.
+    let input = user.input.lock().unwrap();
+    assert_eq!(input.len(), 3 * PERIOD);
+    assert!(input[..PERIOD].iter().all(|&s| s == 0));
+    assert_eq!(input[PERIOD..], produced(0..2 * PERIOD));
+    assert_eq!(
+        *user.states.lock().unwrap(),
+        [ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_STOPPED]
+    );
+    assert_eq!(user.device_changes.load(Ordering::Relaxed), 1);
+    assert_eq!(user.collection_changes.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_backend_drain() {
+    let user = User::draining_after(PERIOD + 1);
+    run(&user);
+
+    assert_eq!(user.input.lock().unwrap().len(), 2 * PERIOD);
+    assert_eq!(
+        *user.states.lock().unwrap(),
+        [
+            ffi::CUBEB_STATE_STARTED,
+            ffi::CUBEB_STATE_DRAINED,
+            ffi::CUBEB_STATE_STOPPED
+        ]
+    );
+}
+
+pub const MINIMAL_OPS: Ops = capi_new_safe!(MinimalContext);
+
+// A backend implementing only the required operations, relying on the
+// defaults for the others.
+struct MinimalContext;
+
+impl BackendContext for MinimalContext {
+    type Stream = MinimalStream;
+    const UNSUPPORTED: OptionalOps = OptionalOps::all();
+
+    fn init(_context_name: Option<&CStr>) -> Result<Self> {
+        Ok(MinimalContext)
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"minimal"
+    }
+    fn stream_init(
+        &mut self,
+        _params: &StreamInitParams,
+        _callbacks: StreamCallbacks,
+    ) -> Result<MinimalStream> {
+        Ok(MinimalStream)
+    }
+}
+
+struct MinimalStream;
+
+impl BackendStream for MinimalStream {
+    const UNSUPPORTED: OptionalOps = OptionalOps::all();
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn position(&mut self) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+#[test]
+fn test_backend_unsupported() {
+    let ops = MINIMAL_OPS;
+    assert!(ops.get_max_channel_count.is_none());
+    assert!(ops.get_preferred_sample_rate.is_none());
+    assert!(ops.enumerate_devices.is_none());
+    assert!(ops.device_collection_destroy.is_none());
+    assert!(ops.register_device_collection_changed.is_none());
+    assert!(ops.stream_get_latency.is_none());
+    assert!(ops.stream_set_volume.is_none());
+    assert!(ops.stream_get_current_device.is_none());
+    assert!(ops.stream_device_destroy.is_none());
+    // Device changes are reported through the callbacks handle.
+    assert!(ops.stream_register_device_changed_callback.is_some());
+    assert!(ops.stream_start.is_some());
+
+    assert!(OPS.get_max_channel_count.is_some());
+    assert!(OPS.stream_set_volume.is_some());
+}
+
+thread_local! {
+    // Input and output bytes `LengthContext` passes to the data callback.
+    static BUFFER_BYTES: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+// A backend calling the data callback once when a stream is opened, with
+// buffers of `BUFFER_BYTES`.
+struct LengthContext;
+
+impl BackendContext for LengthContext {
+    type Stream = MinimalStream;
+    const UNSUPPORTED: OptionalOps = OptionalOps::all();
+
+    fn init(_context_name: Option<&CStr>) -> Result<Self> {
+        Ok(LengthContext)
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"length"
+    }
+    fn stream_init(
+        &mut self,
+        _params: &StreamInitParams,
+        mut callbacks: StreamCallbacks,
+    ) -> Result<MinimalStream> {
+        let (input, output) = BUFFER_BYTES.get();
+        assert_eq!(
+            callbacks.data(&vec![0; input], &mut vec![0; output]) * 2,
+            output
+        );
+        Err(Error::Error)
+    }
+}
+
+// Open a duplex mono S16 stream, passing buffers of `input` and `output`
+// bytes to its data callback.
+fn data_lengths(input: usize, output: usize) {
+    BUFFER_BYTES.set((input, output));
+    let params = params();
+    let mut ctx = SafeContext::<LengthContext>::init(None).unwrap();
+    let stream = ctx.stream_init(
+        None,
+        ptr::null(),
+        Some(&params),
+        ptr::null(),
+        Some(&params),
+        PERIOD as u32,
+        None,
+        None,
+        ptr::null_mut(),
+    );
+    assert!(stream.is_err());
+}
+
+#[test]
+fn test_backend_data_lengths() {
+    data_lengths(2 * PERIOD, 2 * PERIOD);
+    data_lengths(0, 0);
+}
+
+#[test]
+#[should_panic(expected = "isn't a whole number")]
+fn test_backend_data_partial_frame() {
+    data_lengths(2 * PERIOD + 1, 2 * PERIOD + 1);
+}
+
+#[test]
+#[should_panic(expected = "frame counts differ")]
+fn test_backend_data_short_input() {
+    data_lengths(PERIOD, 2 * PERIOD);
+}
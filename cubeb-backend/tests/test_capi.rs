@@ -5,12 +5,12 @@
 
 #![allow(clippy::float_cmp)]
 
-#[macro_use]
 extern crate cubeb_backend;
 
 use cubeb_backend::{
-    ffi, ContextOps, DeviceId, DeviceInfo, DeviceRef, DeviceType, InputProcessingParams, Ops,
-    Result, Stream, StreamOps, StreamParams, StreamParamsRef,
+    capi_new, ffi, ContextOps, DeviceId, DeviceInfo, DeviceRef, DeviceType, Error,
+    InputProcessingParams, Ops, OptionalOps, Result, Stream, StreamOps, StreamParams,
+    StreamParamsRef,
 };
 use std::ffi::CStr;
 use std::mem::ManuallyDrop;
@@ -292,6 +292,92 @@ fn test_ops_stream_set_input_processing_params() {
     );
 }
 
+pub const MINIMAL_OPS: Ops = capi_new!(MinimalContext, MinimalStream);
+
+// A backend implementing only the required operations, relying on the
+// defaults for the others.
+struct MinimalContext;
+
+impl ContextOps for MinimalContext {
+    const UNSUPPORTED: OptionalOps = OptionalOps::all();
+
+    fn init(_context_name: Option<&CStr>) -> Result<Box<Self>> {
+        Ok(Box::new(MinimalContext))
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"minimal"
+    }
+    fn stream_init(
+        &mut self,
+        _stream_name: Option<&CStr>,
+        _input_device: DeviceId,
+        _input_stream_params: Option<&StreamParamsRef>,
+        _output_device: DeviceId,
+        _output_stream_params: Option<&StreamParamsRef>,
+        _latency_frame: u32,
+        _data_callback: ffi::cubeb_data_callback,
+        _state_callback: ffi::cubeb_state_callback,
+        _user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        Ok(unsafe { Stream::from_ptr(Box::into_raw(Box::new(MinimalStream)) as *mut _) })
+    }
+}
+
+struct MinimalStream;
+
+impl StreamOps for MinimalStream {
+    const UNSUPPORTED: OptionalOps = OptionalOps::all();
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn position(&mut self) -> Result<u64> {
+        Ok(0u64)
+    }
+}
+
+#[test]
+fn test_ops_unsupported() {
+    // Every optional operation is left to libcubeb's fallbacks.
+    let ops = MINIMAL_OPS;
+    assert!(ops.get_max_channel_count.is_none());
+    assert!(ops.get_min_latency.is_none());
+    assert!(ops.get_preferred_sample_rate.is_none());
+    assert!(ops.get_supported_input_processing_params.is_none());
+    assert!(ops.enumerate_devices.is_none());
+    assert!(ops.device_collection_destroy.is_none());
+    assert!(ops.register_device_collection_changed.is_none());
+    assert!(ops.stream_get_latency.is_none());
+    assert!(ops.stream_get_input_latency.is_none());
+    assert!(ops.stream_set_volume.is_none());
+    assert!(ops.stream_set_name.is_none());
+    assert!(ops.stream_get_current_device.is_none());
+    assert!(ops.stream_device_destroy.is_none());
+    assert!(ops.stream_set_input_mute.is_none());
+    assert!(ops.stream_set_input_processing_params.is_none());
+    assert!(ops.stream_register_device_changed_callback.is_none());
+    assert!(ops.init.is_some());
+    assert!(ops.stream_start.is_some());
+    assert!(ops.stream_get_position.is_some());
+
+    // The defaults still fail when called from Rust.
+    let mut ctx = MinimalContext::init(None).unwrap();
+    assert_eq!(ctx.preferred_sample_rate(), Err(Error::NotSupported));
+    assert_eq!(MinimalStream.set_volume(0.5), Err(Error::NotSupported));
+}
+
+#[test]
+fn test_ops_all_supported() {
+    // Backends implementing every operation keep every entry.
+    assert!(OPS.get_max_channel_count.is_some());
+    assert!(OPS.enumerate_devices.is_some());
+    assert!(OPS.stream_set_volume.is_some());
+    assert!(OPS.stream_register_device_changed_callback.is_some());
+}
+
 fn get_ctx() -> *mut ffi::cubeb {
     CONTEXT.get_or_init(TestContextPtr::new).ptr
 }
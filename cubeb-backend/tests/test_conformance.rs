@@ -0,0 +1,315 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details
+
+extern crate cubeb_backend;
+
+use cubeb_backend::conformance::{self, Config};
+use cubeb_backend::{
+    ffi, ContextOps, DeviceId, DeviceInfo, DeviceRef, DeviceType, InputProcessingParams,
+    OptionalOps, Result, Stream, StreamOps, StreamParams, StreamParamsRef,
+};
+use std::ffi::CStr;
+use std::os::raw::{c_long, c_void};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const PERIOD: usize = 480;
+
+// A backend playing to a fake device from a thread. The broken variant
+// reports inconsistent devices and positions, and keeps calling back for
+// a while after being stopped.
+struct FakeContext<const BROKEN: bool>;
+
+impl<const BROKEN: bool> ContextOps for FakeContext<BROKEN> {
+    fn init(_context_name: Option<&CStr>) -> Result<Box<Self>> {
+        Ok(Box::new(FakeContext))
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"fake"
+    }
+    fn max_channel_count(&mut self) -> Result<u32> {
+        Ok(2)
+    }
+    fn min_latency(&mut self, _params: StreamParams) -> Result<u32> {
+        Ok(PERIOD as u32)
+    }
+    fn preferred_sample_rate(&mut self) -> Result<u32> {
+        Ok(48_000)
+    }
+    fn supported_input_processing_params(&mut self) -> Result<InputProcessingParams> {
+        Ok(InputProcessingParams::NONE)
+    }
+    fn enumerate_devices(&mut self, devtype: DeviceType) -> Result<Box<[DeviceInfo]>> {
+        let info = DeviceInfo::default();
+        let raw = unsafe { &mut *info.as_ptr() };
+        raw.device_type = devtype.bits();
+        raw.min_rate = if BROKEN { 96_000 } else { 8_000 };
+        raw.default_rate = 48_000;
+        raw.max_rate = 96_000;
+        raw.latency_lo = PERIOD as u32;
+        raw.latency_hi = 4 * PERIOD as u32;
+        Ok(vec![info].into_boxed_slice())
+    }
+    fn device_collection_destroy(&mut self, _collection: Box<[DeviceInfo]>) -> Result<()> {
+        Ok(())
+    }
+    fn stream_init(
+        &mut self,
+        _stream_name: Option<&CStr>,
+        _input_device: DeviceId,
+        input_stream_params: Option<&StreamParamsRef>,
+        _output_device: DeviceId,
+        output_stream_params: Option<&StreamParamsRef>,
+        _latency_frames: u32,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        let params = output_stream_params.or(input_stream_params).unwrap();
+        let stm = Box::new(FakeStream::<BROKEN> {
+            shared: Arc::new(Shared {
+                data_callback: data_callback.unwrap(),
+                user_ptr: user_ptr as usize,
+                channels: params.channels() as usize,
+                output: output_stream_params.is_some(),
+                running: AtomicBool::new(false),
+                late_callbacks: AtomicUsize::new(0),
+                position: AtomicU64::new(0),
+            }),
+            state_callback: state_callback.unwrap(),
+            user_ptr,
+            worker: None,
+            device: Default::default(),
+        });
+        Ok(unsafe { Stream::from_ptr(Box::into_raw(stm) as *mut _) })
+    }
+    fn register_device_collection_changed(
+        &mut self,
+        _dev_type: DeviceType,
+        _collection_changed_callback: ffi::cubeb_device_collection_changed_callback,
+        _user_ptr: *mut c_void,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+// A backend implementing none of the optional context operations, opening
+// the streams of `FakeContext`.
+struct MinimalContext;
+
+impl ContextOps for MinimalContext {
+    const UNSUPPORTED: OptionalOps = OptionalOps::all();
+
+    fn init(_context_name: Option<&CStr>) -> Result<Box<Self>> {
+        Ok(Box::new(MinimalContext))
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"minimal"
+    }
+    fn stream_init(
+        &mut self,
+        stream_name: Option<&CStr>,
+        input_device: DeviceId,
+        input_stream_params: Option<&StreamParamsRef>,
+        output_device: DeviceId,
+        output_stream_params: Option<&StreamParamsRef>,
+        latency_frames: u32,
+        data_callback: ffi::cubeb_data_callback,
+        state_callback: ffi::cubeb_state_callback,
+        user_ptr: *mut c_void,
+    ) -> Result<Stream> {
+        FakeContext::<false>.stream_init(
+            stream_name,
+            input_device,
+            input_stream_params,
+            output_device,
+            output_stream_params,
+            latency_frames,
+            data_callback,
+            state_callback,
+            user_ptr,
+        )
+    }
+}
+
+// State shared with the thread running the callbacks.
+struct Shared {
+    data_callback: unsafe extern "C" fn(
+        *mut ffi::cubeb_stream,
+        *mut c_void,
+        *const c_void,
+        *mut c_void,
+        c_long,
+    ) -> c_long,
+    user_ptr: usize,
+    channels: usize,
+    output: bool,
+    running: AtomicBool,
+    // Callbacks still made after stopping.
+    late_callbacks: AtomicUsize,
+    position: AtomicU64,
+}
+
+struct FakeStream<const BROKEN: bool> {
+    shared: Arc<Shared>,
+    state_callback: unsafe extern "C" fn(*mut ffi::cubeb_stream, *mut c_void, ffi::cubeb_state),
+    user_ptr: *mut c_void,
+    worker: Option<JoinHandle<()>>,
+    device: ffi::cubeb_device,
+}
+
+impl<const BROKEN: bool> FakeStream<BROKEN> {
+    fn join(&mut self) {
+        self.shared.running.store(false, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            worker.join().unwrap();
+        }
+    }
+}
+
+impl<const BROKEN: bool> Drop for FakeStream<BROKEN> {
+    fn drop(&mut self) {
+        self.shared.late_callbacks.store(0, Ordering::Release);
+        self.join();
+    }
+}
+
+impl<const BROKEN: bool> StreamOps for FakeStream<BROKEN> {
+    fn start(&mut self) -> Result<()> {
+        let shared = self.shared.clone();
+        shared.running.store(true, Ordering::Release);
+        self.worker = Some(thread::spawn(move || {
+            let mut buffer = vec![0f32; PERIOD * shared.channels];
+            while shared.running.load(Ordering::Acquire)
+                || shared
+                    .late_callbacks
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                    .is_ok()
+            {
+                let (input, output) = if shared.output {
+                    (std::ptr::null(), buffer.as_mut_ptr() as *mut c_void)
+                } else {
+                    (buffer.as_ptr() as *const c_void, std::ptr::null_mut())
+                };
+                let frames = unsafe {
+                    (shared.data_callback)(
+                        std::ptr::null_mut(),
+                        shared.user_ptr as *mut c_void,
+                        input,
+                        output,
+                        PERIOD as c_long,
+                    )
+                };
+                let played = if BROKEN { 2 * frames } else { frames };
+                shared.position.fetch_add(played as u64, Ordering::AcqRel);
+                thread::sleep(Duration::from_millis(5));
+            }
+        }));
+        unsafe {
+            (self.state_callback)(
+                std::ptr::null_mut(),
+                self.user_ptr,
+                ffi::CUBEB_STATE_STARTED,
+            )
+        };
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<()> {
+        if BROKEN {
+            self.shared.late_callbacks.store(3, Ordering::Release);
+            self.shared.running.store(false, Ordering::Release);
+            return Ok(());
+        }
+        self.join();
+        unsafe {
+            (self.state_callback)(
+                std::ptr::null_mut(),
+                self.user_ptr,
+                ffi::CUBEB_STATE_STOPPED,
+            )
+        };
+        Ok(())
+    }
+    fn position(&mut self) -> Result<u64> {
+        Ok(self.shared.position.load(Ordering::Acquire))
+    }
+    fn latency(&mut self) -> Result<u32> {
+        Ok(PERIOD as u32)
+    }
+    fn input_latency(&mut self) -> Result<u32> {
+        Ok(PERIOD as u32)
+    }
+    fn set_volume(&mut self, _volume: f32) -> Result<()> {
+        Ok(())
+    }
+    fn set_name(&mut self, _name: &CStr) -> Result<()> {
+        Ok(())
+    }
+    fn current_device(&mut self) -> Result<&DeviceRef> {
+        Ok(unsafe { DeviceRef::from_ptr(&mut self.device as *mut _) })
+    }
+    fn set_input_mute(&mut self, _mute: bool) -> Result<()> {
+        Ok(())
+    }
+    fn set_input_processing_params(&mut self, _params: InputProcessingParams) -> Result<()> {
+        Ok(())
+    }
+    fn device_destroy(&mut self, _device: &DeviceRef) -> Result<()> {
+        Ok(())
+    }
+    fn register_device_changed_callback(
+        &mut self,
+        _: ffi::cubeb_device_changed_callback,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn config() -> Config {
+    Config {
+        run_time: Duration::from_millis(100),
+        settle_time: Duration::from_millis(50),
+        input: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_conformance_fake_backend() {
+    let report = unsafe { conformance::run::<FakeContext<false>, FakeStream<false>>(&config()) };
+    assert!(report.passed(), "{}", report);
+    assert!(report.checks.iter().any(|c| c.name == "input states"));
+}
+
+#[test]
+fn test_conformance_broken_backend() {
+    let report = unsafe { conformance::run::<FakeContext<true>, FakeStream<true>>(&config()) };
+    let failures: Vec<_> = report.failures().map(|c| c.name).collect();
+    assert_eq!(
+        failures,
+        [
+            "input devices",
+            "output devices",
+            "output position",
+            "output stop",
+            "output states",
+            "input stop",
+            "input states",
+        ],
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn test_conformance_minimal_backend() {
+    let report = unsafe { conformance::run::<MinimalContext, FakeStream<false>>(&config()) };
+    assert!(report.passed(), "{}", report);
+    // Devices aren't checked, and the streams run at the default rate.
+    assert!(!report.checks.iter().any(|c| c.name.ends_with("devices")));
+    assert!(report.checks.iter().any(|c| c.name == "input states"));
+}
@@ -0,0 +1,176 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details
+
+extern crate cubeb_backend;
+
+mod common;
+
+use common::{produced, User, RATE};
+use cubeb_backend::{
+    ffi, BackendContext, BackendStream, CallbackDriver, ContextOps, DeviceIo, Error, Result,
+    SafeContext, SafeStream, StreamCallbacks, StreamInitParams, StreamOps,
+};
+use std::ffi::CStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PERIOD: usize = 32;
+
+// A device capturing a ramp, and keeping what it plays.
+#[derive(Clone, Default)]
+struct TestDevice {
+    captured: Arc<Mutex<Vec<i16>>>,
+    played: Arc<Mutex<Vec<i16>>>,
+    // Periods to play before failing.
+    fail_after: Option<usize>,
+}
+
+impl DeviceIo for TestDevice {
+    fn read(&mut self, input: &mut [u8]) -> Result<()> {
+        let mut captured = self.captured.lock().unwrap();
+        for frame in input.chunks_exact_mut(2) {
+            let sample = captured.len() as i16;
+            frame.copy_from_slice(&sample.to_ne_bytes());
+            captured.push(sample);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, output: &[u8]) -> Result<()> {
+        let mut played = self.played.lock().unwrap();
+        if self.fail_after == Some(played.len() / PERIOD) {
+            return Err(Error::DeviceUnavailable);
+        }
+        let samples = output.chunks_exact(2);
+        played.extend(samples.map(|s| i16::from_ne_bytes([s[0], s[1]])));
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct DriverContext {
+    device: TestDevice,
+}
+
+impl BackendContext for DriverContext {
+    type Stream = DriverStream;
+
+    fn init(_context_name: Option<&CStr>) -> Result<Self> {
+        Ok(DriverContext::default())
+    }
+    fn backend_id(&mut self) -> &'static CStr {
+        c"driver"
+    }
+    fn stream_init(
+        &mut self,
+        _params: &StreamInitParams,
+        callbacks: StreamCallbacks,
+    ) -> Result<DriverStream> {
+        Ok(DriverStream {
+            driver: CallbackDriver::new(callbacks, self.device.clone(), PERIOD as u32, RATE),
+        })
+    }
+}
+
+struct DriverStream {
+    driver: CallbackDriver<TestDevice>,
+}
+
+impl BackendStream for DriverStream {
+    fn start(&mut self) -> Result<()> {
+        self.driver.start()
+    }
+    fn stop(&mut self) -> Result<()> {
+        self.driver.stop()
+    }
+    fn position(&mut self) -> Result<u64> {
+        Ok(self.driver.position())
+    }
+}
+
+// Open a mono S16 stream, with input if `duplex`, on `device`.
+fn stream_init(device: TestDevice, duplex: bool, user: &User) -> Box<SafeStream<DriverStream>> {
+    let mut ctx = SafeContext::<DriverContext>::init(None).unwrap();
+    ctx.inner().device = device;
+    common::stream_init(&mut *ctx, duplex, user).unwrap()
+}
+
+fn wait_for(condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_driver_drain() {
+    let user = User::draining_after(2 * PERIOD + PERIOD / 2);
+    let device = TestDevice::default();
+    let mut stm = stream_init(device.clone(), false, &user);
+    stm.start().unwrap();
+    wait_for(|| user.states.lock().unwrap().len() == 2);
+    assert_eq!(
+        *user.states.lock().unwrap(),
+        [ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_DRAINED]
+    );
+    assert_eq!(stm.position(), Ok((2 * PERIOD + PERIOD / 2) as u64));
+
+    // The rest of the last period is silent.
+    let played = device.played.lock().unwrap().clone();
+    assert_eq!(played.len(), 3 * PERIOD);
+    assert_eq!(
+        played[..2 * PERIOD + PERIOD / 2],
+        produced(0..2 * PERIOD + PERIOD / 2)
+    );
+    assert!(played[2 * PERIOD + PERIOD / 2..].iter().all(|&s| s == 0));
+
+    stm.stop().unwrap();
+    assert_eq!(
+        user.states.lock().unwrap().last(),
+        Some(&ffi::CUBEB_STATE_STOPPED)
+    );
+}
+
+#[test]
+fn test_driver_duplex() {
+    let user = User::default();
+    let device = TestDevice::default();
+    let mut stm = stream_init(device.clone(), true, &user);
+    stm.start().unwrap();
+    wait_for(|| device.played.lock().unwrap().len() >= 4 * PERIOD);
+    stm.stop().unwrap();
+
+    // The input is passed on as it was captured.
+    let played = device.played.lock().unwrap().clone();
+    let captured = device.captured.lock().unwrap().clone();
+    let input = user.input.lock().unwrap().clone();
+    assert_eq!(input, captured[..input.len()]);
+    assert_eq!(played, produced(0..played.len()));
+    assert_eq!(stm.position(), Ok(played.len() as u64));
+    assert_eq!(
+        *user.states.lock().unwrap(),
+        [ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_STOPPED]
+    );
+}
+
+#[test]
+fn test_driver_device_error() {
+    let user = User::default();
+    let device = TestDevice {
+        fail_after: Some(2),
+        ..Default::default()
+    };
+    let mut stm = stream_init(device.clone(), false, &user);
+    stm.start().unwrap();
+    wait_for(|| user.states.lock().unwrap().len() == 2);
+    assert_eq!(
+        *user.states.lock().unwrap(),
+        [ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_ERROR]
+    );
+    assert_eq!(device.played.lock().unwrap().len(), 2 * PERIOD);
+    assert_eq!(stm.position(), Ok(2 * PERIOD as u64));
+}
@@ -0,0 +1,150 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details
+
+#[macro_use]
+extern crate cubeb_backend;
+
+mod common;
+
+use common::{
+    produced, user_collection_changed_cb, user_device_changed_cb, ManualContext, ManualStream,
+    User, RATE,
+};
+use cubeb_backend::{
+    ffi, Call, ContextOps, DeviceType, Error, FaultInjectingContext, FaultInjectingStream, Faults,
+    Ops, Result, StreamOps,
+};
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+type Context = FaultInjectingContext<ManualContext, ManualStream>;
+type FaultStream = FaultInjectingStream<ManualStream>;
+
+pub const OPS: Ops = capi_new!(Context, FaultStream);
+
+// Open a mono output stream, returning it as the fault injecting stream.
+fn stream_init(ctx: &mut Context, user: &User) -> Result<Box<FaultStream>> {
+    common::stream_init(ctx, false, user)
+}
+
+#[test]
+fn test_fault_failing_calls() {
+    let faults = Faults::new();
+    faults
+        .fail(Call::PreferredSampleRate, Error::DeviceUnavailable)
+        .fail_nth(Call::StreamInit, 0, Error::InvalidFormat);
+    let mut ctx = Context::wrap(ManualContext::init(None).unwrap(), faults.clone());
+    assert_eq!(ctx.preferred_sample_rate(), Err(Error::DeviceUnavailable));
+    assert_eq!(ctx.max_channel_count(), Ok(1));
+
+    let user = User::default();
+    assert_eq!(
+        stream_init(&mut ctx, &user).err(),
+        Some(Error::InvalidFormat)
+    );
+    let mut stream = stream_init(&mut ctx, &user).unwrap();
+    faults.fail_nth(Call::Start, 1, Error::Error);
+    assert_eq!(stream.start(), Ok(()));
+    assert_eq!(stream.start(), Err(Error::Error));
+    assert_eq!(stream.start(), Ok(()));
+    assert_eq!(faults.calls(Call::Start), 3);
+    assert_eq!(faults.calls(Call::StreamInit), 2);
+
+    faults.clear();
+    assert_eq!(ctx.preferred_sample_rate(), Ok(RATE));
+}
+
+#[test]
+fn test_fault_callbacks() {
+    let faults = Faults::new();
+    let mut ctx = Context::wrap(ManualContext::init(None).unwrap(), faults.clone());
+    let user = User::default();
+    let mut stream = stream_init(&mut ctx, &user).unwrap();
+    stream.start().unwrap();
+
+    faults.drop_callbacks(2);
+    let (returned, output) = stream.inner().tick(4);
+    assert_eq!((returned, output), (4, produced(0..4)));
+    // Dropped callbacks play silence.
+    let (returned, output) = stream.inner().tick(4);
+    assert_eq!((returned, output), (4, vec![0; 4]));
+    assert_eq!(*user.requested.lock().unwrap(), [4]);
+
+    faults.clear();
+    faults.duplicate_callbacks(1).short_buffers(3);
+    let (returned, output) = stream.inner().tick(4);
+    // The first call is overwritten by its duplicate.
+    assert_eq!((returned, output), (4, vec![8, 9, 10, 0]));
+    assert_eq!(*user.requested.lock().unwrap(), [4, 3, 3]);
+
+    faults.clear();
+    faults.error_after(Duration::ZERO);
+    let (returned, output) = stream.inner().tick(4);
+    assert_eq!((returned, output), (4, vec![0; 4]));
+    stream.stop().unwrap();
+    // Nothing is reported after the error.
+    assert_eq!(
+        *user.states.lock().unwrap(),
+        [ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_ERROR]
+    );
+    assert_eq!(user.requested.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_fault_notifications() {
+    let faults = Faults::new();
+    let mut ctx = Context::wrap(ManualContext::init(None).unwrap(), faults.clone());
+    let user = User::default();
+    let user_ptr = user.user_ptr();
+    let mut stream = stream_init(&mut ctx, &user).unwrap();
+    stream
+        .register_device_changed_callback(Some(user_device_changed_cb))
+        .unwrap();
+    ctx.register_device_collection_changed(
+        DeviceType::OUTPUT,
+        Some(user_collection_changed_cb),
+        user_ptr,
+    )
+    .unwrap();
+
+    faults.fire_device_changed();
+    faults.fire_collection_changed(DeviceType::INPUT);
+    faults.fire_collection_changed(DeviceType::OUTPUT);
+    assert_eq!(user.device_changes.load(Ordering::Relaxed), 1);
+    assert_eq!(user.collection_changes.load(Ordering::Relaxed), 1);
+
+    // Notifications from the wrapped backend are forwarded too.
+    let inner = stream.inner();
+    unsafe { inner.device_changed.unwrap()(inner.user_ptr) };
+    let (cb, listener) = ctx.inner().collection_changed.unwrap();
+    unsafe { cb.unwrap()(ptr::null_mut(), listener as *mut c_void) };
+    assert_eq!(user.device_changes.load(Ordering::Relaxed), 2);
+    assert_eq!(user.collection_changes.load(Ordering::Relaxed), 2);
+
+    drop(stream);
+    faults.fire_device_changed();
+    assert_eq!(user.device_changes.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_fault_capi_init() {
+    let faults = Faults::new();
+    faults.fail_nth(Call::Init, 0, Error::Error);
+    faults.install();
+
+    let mut c: *mut ffi::cubeb = ptr::null_mut();
+    let init = OPS.init.unwrap();
+    assert_eq!(unsafe { init(&mut c, ptr::null()) }, ffi::CUBEB_ERROR);
+    assert_eq!(unsafe { init(&mut c, ptr::null()) }, ffi::CUBEB_OK);
+    let mut rate = 0;
+    assert_eq!(
+        unsafe { OPS.get_preferred_sample_rate.unwrap()(c, &mut rate) },
+        ffi::CUBEB_OK
+    );
+    assert_eq!(rate, RATE);
+    unsafe { OPS.destroy.unwrap()(c) };
+}
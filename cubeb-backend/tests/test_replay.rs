@@ -0,0 +1,149 @@
+// Copyright © 2026 Mozilla Foundation
+//
+// This program is made available under an ISC-style license.  See the
+// accompanying file LICENSE for details
+
+extern crate cubeb_backend;
+
+mod common;
+
+use common::{stream_init, user_data_cb, user_state_cb, ManualContext, ManualStream, User};
+use cubeb_backend::{
+    ffi, Call, ContextOps, Error, Event, Pacing, RecordingContext, RecordingStream, ReplayContext,
+    ReplayStream, SampleFormat, StreamOps, StreamParamsBuilder, Trace, TraceRecorder,
+};
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Context = RecordingContext<ManualContext, ManualStream>;
+
+// Frames the recorded user produces, draining in the second callback.
+const FRAMES: usize = 65;
+
+fn record(user: &User) -> Trace {
+    let recorder = TraceRecorder::new();
+    let mut ctx = Context::wrap(ManualContext::init(None).unwrap(), recorder.clone());
+    assert_eq!(ctx.preferred_sample_rate(), Ok(common::RATE));
+    let mut stm: Box<RecordingStream<ManualStream>> = stream_init(&mut *ctx, true, user).unwrap();
+    stm.start().unwrap();
+    // Drive the inner stream as its audio thread would.
+    assert_eq!(stm.inner().tick_duplex(&[7; 64]).0, 64);
+    assert_eq!(stm.inner().tick_duplex(&[-3, 3]).0, 1);
+    assert_eq!(stm.position(), Ok(66));
+    assert_eq!(stm.input_latency(), Err(Error::NotSupported));
+    stm.stop().unwrap();
+    drop(stm);
+    recorder.trace()
+}
+
+#[test]
+fn test_replay_round_trip() {
+    let recorded = User::draining_after(FRAMES);
+    let trace = record(&recorded);
+
+    let data: Vec<_> = trace
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Data {
+                nframes, returned, ..
+            } => Some((*nframes, *returned)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(data, [(64, 64), (2, 1)]);
+    assert!(trace.events.iter().any(|event| matches!(
+        event,
+        Event::Call {
+            stream: Some(0),
+            call: Call::InputLatency,
+            result: Err(Error::NotSupported),
+            ..
+        }
+    )));
+    assert!(matches!(
+        trace.events.last(),
+        Some(Event::StreamDestroy { stream: 0, .. })
+    ));
+
+    let mut bytes = Vec::new();
+    trace.write_to(&mut bytes).unwrap();
+    let trace = Trace::read_from(&bytes[..]).unwrap();
+
+    let replayed = User::draining_after(FRAMES);
+    let mut ctx = ReplayContext::new(trace, Pacing::Immediate);
+    assert_eq!(ctx.backend_id(), c"replay");
+    assert_eq!(ctx.preferred_sample_rate(), Ok(common::RATE));
+    // Only one preferred rate query was recorded.
+    assert_eq!(ctx.preferred_sample_rate(), Err(Error::Error));
+    let mut stm: Box<ReplayStream> = stream_init(&mut *ctx, true, &replayed).unwrap();
+    stm.start().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while replayed.requested.lock().unwrap().len() < 2 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(stm.position(), Ok(66));
+    assert_eq!(stm.input_latency(), Err(Error::NotSupported));
+    stm.stop().unwrap();
+    drop(stm);
+
+    assert_eq!(
+        *replayed.requested.lock().unwrap(),
+        *recorded.requested.lock().unwrap()
+    );
+    assert_eq!(
+        *replayed.input.lock().unwrap(),
+        *recorded.input.lock().unwrap()
+    );
+    assert_eq!(
+        *replayed.states.lock().unwrap(),
+        [ffi::CUBEB_STATE_STARTED, ffi::CUBEB_STATE_STOPPED]
+    );
+    assert_eq!(
+        *replayed.states.lock().unwrap(),
+        *recorded.states.lock().unwrap()
+    );
+}
+
+#[test]
+fn test_replay_params_mismatch() {
+    let trace = record(&User::draining_after(FRAMES));
+    let mut ctx = ReplayContext::new(trace, Pacing::Immediate);
+    let params = StreamParamsBuilder::new()
+        .format(SampleFormat::Float32NE)
+        .rate(common::RATE)
+        .channels(1)
+        .take();
+    let result = ctx.stream_init(
+        None,
+        ptr::null(),
+        None,
+        ptr::null(),
+        Some(&params),
+        common::LATENCY,
+        Some(user_data_cb),
+        Some(user_state_cb),
+        ptr::null_mut(),
+    );
+    assert_eq!(result.err(), Some(Error::InvalidParameter));
+}
+
+#[test]
+fn test_replay_data_mismatch() {
+    let mut trace = record(&User::draining_after(FRAMES));
+    let data = trace
+        .events
+        .iter_mut()
+        .find_map(|event| match event {
+            Event::Data { input, .. } => Some(input),
+            _ => None,
+        })
+        .unwrap();
+    // A partial frame of input.
+    data.pop();
+    let mut ctx = ReplayContext::new(trace, Pacing::Immediate);
+    let user = User::draining_after(FRAMES);
+    let result = stream_init::<_, ReplayStream>(&mut *ctx, true, &user);
+    assert_eq!(result.err(), Some(Error::Error));
+}